@@ -10,6 +10,8 @@ pub enum Namespace {
     Global,
     Specific(usize),
     Any,
+    /// The members of the module of the given index
+    Module(usize),
 }
 
 #[derive(Clone, Debug)]
@@ -17,6 +19,9 @@ pub struct Scopes<K: Hash + Eq, V> {
     flags:   u8,
     scopes:  Vec<Scope<K, V>>,
     current: usize,
+    /// The scopes holding the members of the modules, which are entered through scopes that
+    /// refer to them
+    modules: Vec<Scope<K, V>>,
 }
 
 #[derive(Clone, Debug)]
@@ -25,6 +30,16 @@ pub struct Scope<K: Hash + Eq, V> {
     /// This scope is on a namespace boundary.
     /// Any previous scopes need to be accessed through `super::`.
     namespace: bool,
+    /// The modules imported in this scope, by their namespace
+    imports:   HashMap<K, usize>,
+    /// The module whose members are used in place of the variables of this scope
+    module:    Option<usize>,
+}
+
+impl<K: Hash + Eq, V> Scope<K, V> {
+    fn new(namespace: bool) -> Self {
+        Self { vars: HashMap::with_capacity(64), namespace, imports: HashMap::new(), module: None }
+    }
 }
 
 impl<K: Hash + Eq, V> Deref for Scope<K, V> {
@@ -41,25 +56,78 @@ impl<K: Hash + Eq, V: Clone> Scopes<K, V> {
     pub fn with_capacity(cap: usize) -> Self {
         Self {
             flags:   0,
-            scopes:  vec![Scope { vars: HashMap::with_capacity(cap), namespace: false }],
+            scopes:  vec![Scope { vars: HashMap::with_capacity(cap), ..Scope::new(false) }],
             current: 0,
+            modules: Vec::new(),
         }
     }
 
     pub fn new_scope(&mut self, namespace: bool) {
         self.current += 1;
         if self.current >= self.scopes.len() {
-            self.scopes.push(Scope { vars: HashMap::with_capacity(64), namespace });
+            self.scopes.push(Scope::new(namespace));
         } else {
             self.scopes[self.current].namespace = namespace;
         }
     }
 
     pub fn pop_scope(&mut self) {
-        self.scopes[self.current].clear();
+        let scope = &mut self.scopes[self.current];
+        scope.clear();
+        scope.imports.clear();
+        scope.module = None;
         self.current -= 1;
     }
 
+    /// Create a new module, and enter a namespace scope holding its members. Returns the index
+    /// of the module.
+    pub fn new_module(&mut self) -> usize {
+        self.modules.push(Scope::new(true));
+        let module = self.modules.len() - 1;
+        self.enter_module(module);
+        module
+    }
+
+    /// Enter a namespace scope holding the members of a module. The changes made to the
+    /// variables of the scope are kept by the module when it is exited.
+    pub fn enter_module(&mut self, module: usize) {
+        self.new_scope(true);
+        self.scopes[self.current].module = Some(module);
+    }
+
+    /// The module whose members are held by the current scope
+    pub fn current_module(&self) -> Option<usize> { self.scopes[self.current].module }
+
+    /// Bind a module to a namespace in the current scope
+    pub fn import<T: Into<K>>(&mut self, namespace: T, module: usize) {
+        self.scope_mut(self.current).imports.insert(namespace.into(), module);
+    }
+
+    /// The module bound to a namespace by the imports of the module `within`, or else by those
+    /// of the innermost scope
+    pub fn module<Q: ?Sized>(&self, namespace: &Q, within: Option<usize>) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        match within {
+            Some(module) => self.modules[module].imports.get(namespace).copied(),
+            None => self.scopes().filter_map(|scope| scope.imports.get(namespace)).next().copied(),
+        }
+    }
+
+    /// The scope itself, or the members of the module that it refers to
+    fn resolve<'a>(&'a self, scope: &'a Scope<K, V>) -> &'a Scope<K, V> {
+        scope.module.map_or(scope, |module| &self.modules[module])
+    }
+
+    fn scope_mut(&mut self, index: usize) -> &mut Scope<K, V> {
+        match self.scopes[index].module {
+            Some(module) => &mut self.modules[module],
+            None => &mut self.scopes[index],
+        }
+    }
+
     pub fn pop_scopes<'a>(&'a mut self, index: usize) -> impl Iterator<Item = Scope<K, V>> + 'a {
         self.current = index;
         self.scopes.drain(index + 1..)
@@ -73,30 +141,44 @@ impl<K: Hash + Eq, V: Clone> Scopes<K, V> {
 
     pub fn scopes(&self) -> impl DoubleEndedIterator<Item = &Scope<K, V>> {
         let amount = self.scopes.len() - self.current - 1;
-        self.scopes.iter().rev().skip(amount)
+        self.scopes.iter().rev().skip(amount).map(move |scope| self.resolve(scope))
     }
 
-    pub fn scopes_mut(&mut self) -> impl Iterator<Item = &mut Scope<K, V>> {
+    pub fn index_scope_for_var<Q: ?Sized>(&self, name: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
         let amount = self.scopes.len() - self.current - 1;
-        self.scopes.iter_mut().rev().skip(amount)
+        for (i, scope) in self.scopes.iter().enumerate().rev().skip(amount) {
+            if self.resolve(scope).contains_key(name) {
+                return Some(i);
+            }
+        }
+        None
     }
 
-    pub fn index_scope_for_var<Q: ?Sized>(&self, name: &Q) -> Option<usize>
+    /// The index of the scope holding a variable which can be changed from the current scope
+    fn index_writable_var<Q: ?Sized>(&self, name: &Q) -> Option<usize>
     where
         K: Borrow<Q>,
         Q: Hash + Eq,
     {
         let amount = self.scopes.len() - self.current - 1;
         for (i, scope) in self.scopes.iter().enumerate().rev().skip(amount) {
+            let scope = self.resolve(scope);
             if scope.contains_key(name) {
                 return Some(i);
             }
+            if scope.namespace {
+                break;
+            }
         }
         None
     }
 
     pub fn set<T: Into<K>, S: Into<V>>(&mut self, name: T, value: S) -> Option<V> {
-        self.scopes[self.current].insert(name.into(), value.into())
+        self.scope_mut(self.current).insert(name.into(), value.into())
     }
 
     pub fn get<Q: ?Sized>(&self, name: &Q, namespace: Namespace) -> Option<&V>
@@ -127,6 +209,7 @@ impl<K: Hash + Eq, V: Clone> Scopes<K, V> {
                 None
             }
             Namespace::Any => self.scopes().filter_map(|scope| scope.get(name)).next(),
+            Namespace::Module(module) => self.modules.get(module).and_then(|scope| scope.get(name)),
         }
     }
 
@@ -135,16 +218,8 @@ impl<K: Hash + Eq, V: Clone> Scopes<K, V> {
         K: Borrow<Q>,
         Q: Hash + Eq,
     {
-        for scope in self.scopes_mut() {
-            let exit = scope.namespace;
-            if let val @ Some(_) = scope.get_mut(name) {
-                return val;
-            }
-            if exit {
-                break;
-            }
-        }
-        None
+        let index = self.index_writable_var(name)?;
+        self.scope_mut(index).get_mut(name)
     }
 
     pub fn remove_variable<Q: ?Sized>(&mut self, name: &Q) -> Option<V>
@@ -152,15 +227,7 @@ impl<K: Hash + Eq, V: Clone> Scopes<K, V> {
         K: Borrow<Q>,
         Q: Hash + Eq,
     {
-        for scope in self.scopes_mut() {
-            let exit = scope.namespace;
-            if let val @ Some(_) = scope.remove(name) {
-                return val;
-            }
-            if exit {
-                break;
-            }
-        }
-        None
+        let index = self.index_writable_var(name)?;
+        self.scope_mut(index).remove(name)
     }
 }
@@ -0,0 +1,387 @@
+use super::{types, Value};
+use std::{error::Error, fmt, fmt::Write};
+
+/// The number of arrays and objects that may be nested within each other
+const MAX_DEPTH: usize = 128;
+
+/// An error encountered while reading a JSON document
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum JsonError {
+    /// The document ended in the middle of a value
+    UnexpectedEnd,
+    /// A character that is not valid at this point of the document, with its byte offset
+    UnexpectedChar(char, usize),
+    /// An invalid escape sequence in a string, with its byte offset
+    InvalidEscape(usize),
+    /// An array or object nested too deeply, with its byte offset
+    TooDeep(usize),
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            JsonError::UnexpectedEnd => write!(f, "unexpected end of document"),
+            JsonError::UnexpectedChar(c, pos) => {
+                write!(f, "unexpected character '{}' at offset {}", c, pos)
+            }
+            JsonError::InvalidEscape(pos) => write!(f, "invalid escape sequence at offset {}", pos),
+            JsonError::TooDeep(pos) => write!(f, "value nested too deeply at offset {}", pos),
+        }
+    }
+}
+
+impl Error for JsonError {}
+
+impl<T> Value<T> {
+    /// Read a JSON document.
+    ///
+    /// Objects become `BTreeMap`s and arrays become `Array`s. All other values are kept as
+    /// strings, `null` being the empty string.
+    pub fn from_json(document: &str) -> Result<Self, JsonError> {
+        let mut parser = Parser { data: document, read: 0, depth: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            Some(c) => Err(JsonError::UnexpectedChar(c, parser.read)),
+            None => Ok(value),
+        }
+    }
+
+    /// Write the value as a JSON document.
+    ///
+    /// Strings that are valid JSON numbers or booleans are written as such, and the keys of
+    /// hash maps are sorted so that the output is stable.
+    pub fn to_json(&self) -> String {
+        let mut output = String::new();
+        write_json(self, &mut output);
+        output
+    }
+}
+
+fn write_json<T>(value: &Value<T>, output: &mut String) {
+    match value {
+        Value::Str(string)
+            if is_number(string) || string.as_str() == "true" || string.as_str() == "false" =>
+        {
+            output.push_str(string)
+        }
+        Value::Str(string) => write_string(string, output),
+        Value::Alias(alias) => write_string(alias, output),
+        Value::Array(array) => {
+            output.push('[');
+            for (id, value) in array.iter().enumerate() {
+                if id != 0 {
+                    output.push(',');
+                }
+                write_json(value, output);
+            }
+            output.push(']');
+        }
+        Value::HashMap(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            write_object(entries.into_iter(), output);
+        }
+        Value::BTreeMap(map) => write_object(map.iter(), output),
+        Value::Function(_) | Value::None => output.push_str("null"),
+    }
+}
+
+fn write_object<'a, T: 'a, I>(entries: I, output: &mut String)
+where
+    I: Iterator<Item = (&'a types::Str, &'a Value<T>)>,
+{
+    output.push('{');
+    for (id, (key, value)) in entries.enumerate() {
+        if id != 0 {
+            output.push(',');
+        }
+        write_string(key, output);
+        output.push(':');
+        write_json(value, output);
+    }
+    output.push('}');
+}
+
+/// Quote a string for a JSON document, whatever it contains
+pub fn json_string(string: &str) -> String {
+    let mut output = String::with_capacity(string.len() + 2);
+    write_string(string, &mut output);
+    output
+}
+
+fn write_string(string: &str, output: &mut String) {
+    output.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            '\u{8}' => output.push_str("\\b"),
+            '\u{c}' => output.push_str("\\f"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(output, "\\u{:04x}", c as u32);
+            }
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+/// Whether `string` follows the JSON grammar for numbers
+fn is_number(string: &str) -> bool {
+    let bytes = string.as_bytes();
+    let mut read = if bytes.first() == Some(&b'-') { 1 } else { 0 };
+    let digits = |read: usize| bytes[read..].iter().take_while(|c| c.is_ascii_digit()).count();
+
+    match digits(read) {
+        0 => return false,
+        n if n > 1 && bytes[read] == b'0' => return false,
+        n => read += n,
+    }
+    if bytes.get(read) == Some(&b'.') {
+        match digits(read + 1) {
+            0 => return false,
+            n => read += n + 1,
+        }
+    }
+    if let Some(b'e') | Some(b'E') = bytes.get(read) {
+        read += 1;
+        if let Some(b'+') | Some(b'-') = bytes.get(read) {
+            read += 1;
+        }
+        match digits(read) {
+            0 => return false,
+            n => read += n,
+        }
+    }
+    read == bytes.len()
+}
+
+struct Parser<'a> {
+    data:  &'a str,
+    read:  usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> { self.data[self.read..].chars().next() }
+
+    fn next(&mut self) -> Result<char, JsonError> {
+        let c = self.peek().ok_or(JsonError::UnexpectedEnd)?;
+        self.read += c.len_utf8();
+        Ok(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        match self.next()? {
+            c if c == expected => Ok(()),
+            c => Err(JsonError::UnexpectedChar(c, self.read - c.len_utf8())),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.read += self.data[self.read..]
+            .bytes()
+            .take_while(|&c| c == b' ' || c == b'\t' || c == b'\n' || c == b'\r')
+            .count();
+    }
+
+    fn value<T>(&mut self) -> Result<Value<T>, JsonError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c @ '{') | Some(c @ '[') => {
+                if self.depth == MAX_DEPTH {
+                    return Err(JsonError::TooDeep(self.read));
+                }
+                self.depth += 1;
+                let value = if c == '{' { self.object() } else { self.array() };
+                self.depth -= 1;
+                value
+            }
+            Some('"') => self.string().map(Value::Str),
+            Some(_) => self.literal(),
+            None => Err(JsonError::UnexpectedEnd),
+        }
+    }
+
+    fn object<T>(&mut self) -> Result<Value<T>, JsonError> {
+        let mut map = types::BTreeMap::new();
+        self.expect('{')?;
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.read += 1;
+            return Ok(Value::BTreeMap(map));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            map.insert(key, self.value()?);
+            self.skip_whitespace();
+            match self.next()? {
+                ',' => (),
+                '}' => return Ok(Value::BTreeMap(map)),
+                c => return Err(JsonError::UnexpectedChar(c, self.read - c.len_utf8())),
+            }
+        }
+    }
+
+    fn array<T>(&mut self) -> Result<Value<T>, JsonError> {
+        let mut array = types::Array::new();
+        self.expect('[')?;
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.read += 1;
+            return Ok(Value::Array(array));
+        }
+
+        loop {
+            array.push(self.value()?);
+            self.skip_whitespace();
+            match self.next()? {
+                ',' => (),
+                ']' => return Ok(Value::Array(array)),
+                c => return Err(JsonError::UnexpectedChar(c, self.read - c.len_utf8())),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<types::Str, JsonError> {
+        let mut string = types::Str::new();
+        self.expect('"')?;
+        loop {
+            match self.next()? {
+                '"' => return Ok(string),
+                '\\' => {
+                    let start = self.read - 1;
+                    let c = match self.next()? {
+                        '"' => '"',
+                        '\\' => '\\',
+                        '/' => '/',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => self.unicode(start)?,
+                        _ => return Err(JsonError::InvalidEscape(start)),
+                    };
+                    string.push(c);
+                }
+                c if (c as u32) < 0x20 => {
+                    return Err(JsonError::UnexpectedChar(c, self.read - 1));
+                }
+                c => string.push(c),
+            }
+        }
+    }
+
+    /// Read the code point of a `\u` escape, which may be a pair of UTF-16 surrogates
+    fn unicode(&mut self, start: usize) -> Result<char, JsonError> {
+        let high = self.hex(start)?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !self.data[self.read..].starts_with("\\u") {
+                return Err(JsonError::InvalidEscape(start));
+            }
+            self.read += 2;
+            let low = self.hex(start)?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(JsonError::InvalidEscape(start));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        std::char::from_u32(code).ok_or(JsonError::InvalidEscape(start))
+    }
+
+    /// Read the four hexadecimal digits of a `\u` escape
+    fn hex(&mut self, start: usize) -> Result<u32, JsonError> {
+        let rest = &self.data[self.read..];
+        let len = rest.bytes().take(4).take_while(u8::is_ascii_hexdigit).count();
+        if len < 4 {
+            return Err(if len == rest.len() {
+                JsonError::UnexpectedEnd
+            } else {
+                JsonError::InvalidEscape(start)
+            });
+        }
+        let code =
+            u32::from_str_radix(&rest[..4], 16).map_err(|_| JsonError::InvalidEscape(start))?;
+        self.read += 4;
+        Ok(code)
+    }
+
+    fn literal<T>(&mut self) -> Result<Value<T>, JsonError> {
+        let start = self.read;
+        let len = self.data[start..]
+            .bytes()
+            .take_while(|&c| c.is_ascii_alphanumeric() || c == b'-' || c == b'+' || c == b'.')
+            .count();
+        let word = &self.data[start..start + len];
+        self.read += len;
+        match word {
+            "null" => Ok(Value::Str(types::Str::new())),
+            "true" | "false" => Ok(Value::Str(word.into())),
+            _ if is_number(word) => Ok(Value::Str(word.into())),
+            _ => Err(JsonError::UnexpectedChar(
+                self.data[start..].chars().next().unwrap_or_default(),
+                start,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::array;
+
+    #[test]
+    fn parse() {
+        let value: Value<()> =
+            Value::from_json(r#" {"a": [1, -2.5e3, true, null], "b": {"c": "x\tyé😀"}} "#).unwrap();
+        let mut inner = types::BTreeMap::new();
+        inner.insert("c".into(), Value::Str("x\ty\u{e9}\u{1f600}".into()));
+        let mut map = types::BTreeMap::new();
+        map.insert("a".into(), Value::Array(array!["1", "-2.5e3", "true", ""]));
+        map.insert("b".into(), Value::BTreeMap(inner));
+        assert_eq!(value, Value::BTreeMap(map));
+
+        assert_eq!(Value::<()>::from_json("[]"), Ok(Value::Array(types::Array::new())));
+        assert_eq!(Value::<()>::from_json("\"a\""), Ok(Value::Str("a".into())));
+        assert_eq!(Value::<()>::from_json("[1,"), Err(JsonError::UnexpectedEnd));
+        assert_eq!(Value::<()>::from_json("[1 2]"), Err(JsonError::UnexpectedChar('2', 3)));
+        assert_eq!(Value::<()>::from_json("{} x"), Err(JsonError::UnexpectedChar('x', 3)));
+        assert_eq!(Value::<()>::from_json("01"), Err(JsonError::UnexpectedChar('0', 0)));
+        assert_eq!(Value::<()>::from_json(r#""\q""#), Err(JsonError::InvalidEscape(1)));
+        assert_eq!(Value::<()>::from_json(r#""\u+041""#), Err(JsonError::InvalidEscape(1)));
+        assert_eq!(Value::<()>::from_json(r#""\u00e""#), Err(JsonError::InvalidEscape(1)));
+        assert_eq!(Value::<()>::from_json(r#""\u00"#), Err(JsonError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn depth() {
+        let nested = "[".repeat(MAX_DEPTH) + &"]".repeat(MAX_DEPTH);
+        assert!(Value::<()>::from_json(&nested).is_ok());
+        let nested = "[".repeat(MAX_DEPTH + 1) + &"]".repeat(MAX_DEPTH + 1);
+        assert_eq!(Value::<()>::from_json(&nested), Err(JsonError::TooDeep(MAX_DEPTH)));
+    }
+
+    #[test]
+    fn serialize() {
+        let mut map = types::HashMap::new();
+        map.insert("b".into(), Value::Array(array!["1", "a \"b\"", "false", "1.", ""]));
+        map.insert("a".into(), Value::Str("line\n".into()));
+        let value: Value<()> = Value::HashMap(map);
+        assert_eq!(value.to_json(), r#"{"a":"line\n","b":[1,"a \"b\"",false,"1.",""]}"#);
+
+        let document = r#"{"a":{"b":[0.5,{"c":"\u0001"}]}}"#;
+        assert_eq!(Value::<()>::from_json(document).unwrap().to_json(), document);
+    }
+}
@@ -1,8 +1,10 @@
+mod json;
 mod math;
 mod modification;
 pub mod types;
 
 pub use self::{
+    json::{json_string, JsonError},
     math::{EuclDiv, OpError, Pow},
     modification::Modifications,
 };
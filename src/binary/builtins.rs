@@ -1,4 +1,4 @@
-use ion_shell::{builtin, builtins::Status, types::Str, Shell, Signal};
+use ion_shell::{builtin, builtins::Status, types::Str, Shell, Signal, Trap};
 use nix::{sys::signal, unistd::Pid};
 use std::{error::Error, os::unix::process::CommandExt, process::Command};
 
@@ -50,6 +50,7 @@ DESCRIPTION
     Makes ion exit. The exit status will be that of the last command executed."
 )]
 pub fn exit(args: &[Str], shell: &mut Shell<'_>) -> Status {
+    shell.run_trap(Trap::Exit);
     // Kill all active background tasks before exiting the shell.
     shell.background_send(Signal::SIGTERM).expect("Could not terminate background jobs");
     let exit_code = args
@@ -73,7 +74,7 @@ DESCRIPTION
 OPTIONS
     -c  Execute command with an empty environment."
 )]
-pub fn exec(args: &[Str], _shell: &mut Shell<'_>) -> Status {
+pub fn exec(args: &[Str], shell: &mut Shell<'_>) -> Status {
     let mut clear_env = false;
     let mut idx = 1;
     for arg in args.iter().skip(1) {
@@ -89,9 +90,11 @@ pub fn exec(args: &[Str], _shell: &mut Shell<'_>) -> Status {
             let args = if args.len() > idx + 1 { &args[idx + 1..] } else { &[] };
             let mut command = Command::new(argument.as_str());
             command.args(args.iter().map(Str::as_str));
-            if clear_env {
-                command.env_clear();
+            command.env_clear();
+            if !clear_env {
+                command.envs(shell.variables().env().iter());
             }
+            command.current_dir(shell.variables().env().cwd());
             Status::error(format!("ion: exec: {}", command.exec().description()))
         }
         None => Status::error("ion: exec: no command provided"),
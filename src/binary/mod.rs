@@ -12,7 +12,7 @@ use ion_shell::{
     expansion::Expander,
     parser::Terminator,
     types::{self, array},
-    IonError, PipelineError, Shell, Signal, Value,
+    IonError, PipelineError, Shell, Signal, Trap, Value,
 };
 use itertools::Itertools;
 use liner::{Buffer, Context, KeyBindings};
@@ -77,7 +77,9 @@ pub struct InteractiveShell<'a> {
 impl<'a> InteractiveShell<'a> {
     const CONFIG_FILE_NAME: &'static str = "initrc";
 
-    pub fn new(shell: Shell<'a>) -> Self {
+    pub fn new(mut shell: Shell<'a>) -> Self {
+        // The line editor and the completer read the environment of the process
+        shell.variables_mut().env_mut().set_sync(true);
         let mut context = Context::new();
         context.word_divider_fn = Box::new(word_divide);
         InteractiveShell {
@@ -132,6 +134,7 @@ impl<'a> InteractiveShell<'a> {
         let context_bis = self.context.clone();
         let huponexit = self.huponexit.clone();
         let prep_for_exit = &move |shell: &mut Shell<'_>| {
+            shell.run_trap(Trap::Exit);
             // context will be sent a signal to commit all changes to the history file,
             // and waiting for the history thread in the background to finish.
             if huponexit.get() {
@@ -267,9 +270,11 @@ impl<'a> InteractiveShell<'a> {
 
     fn exec_init_file(project_dir: &BaseDirectories, shell: &mut Shell) {
         let initrc = project_dir.find_config_file(Self::CONFIG_FILE_NAME);
-        match initrc.and_then(|initrc| fs::File::open(&initrc).ok()) {
-            Some(script) => {
-                if let Err(err) = shell.execute_command(std::io::BufReader::new(script)) {
+        match initrc.and_then(|initrc| fs::File::open(&initrc).ok().map(|script| (script, initrc)))
+        {
+            Some((script, initrc)) => {
+                let name = initrc.to_string_lossy();
+                if let Err(err) = shell.execute_script(&name, std::io::BufReader::new(script)) {
                     eprintln!("ion: could not exec initrc: {}", err);
                 }
             }
@@ -287,39 +292,45 @@ impl<'a> InteractiveShell<'a> {
         self.terminated.set(true);
         {
             let mut shell = self.shell.borrow_mut();
-            match shell.on_command(&cmd, true) {
-                Ok(_) => (),
-                Err(IonError::PipelineExecutionError(PipelineError::CommandNotFound(command))) => {
-                    if Self::try_cd(&command, &mut shell).ok().map_or(false, |res| res.is_failure())
-                    {
-                        if let Some(Value::Function(func)) =
-                            shell.variables().get("COMMAND_NOT_FOUND").cloned()
+            if let Err(why) = shell.on_command(&cmd, true) {
+                // The error may have been raised within a function
+                match why.origin() {
+                    IonError::PipelineExecutionError(PipelineError::CommandNotFound(command)) => {
+                        if Self::try_cd(command, &mut shell)
+                            .ok()
+                            .map_or(false, |res| res.is_failure())
                         {
-                            if let Err(why) = shell.execute_function(&func, &["ion", &command]) {
-                                eprintln!("ion: command not found handler: {}", why);
+                            if let Some(Value::Function(func)) =
+                                shell.variables().get("COMMAND_NOT_FOUND").cloned()
+                            {
+                                if let Err(why) =
+                                    shell.execute_function(&func, &["ion", command.as_str()])
+                                {
+                                    eprintln!("ion: command not found handler: {}", why);
+                                }
+                            } else {
+                                eprintln!("ion: command not found: {}", command);
                             }
-                        } else {
-                            eprintln!("ion: command not found: {}", command);
                         }
+                        // Status::COULD_NOT_EXEC
                     }
-                    // Status::COULD_NOT_EXEC
-                }
-                Err(IonError::PipelineExecutionError(PipelineError::CommandExecError(
-                    ref err,
-                    ref command,
-                ))) if err.kind() == io::ErrorKind::PermissionDenied && command.len() == 1 => {
-                    if Self::try_cd(&command[0], &mut shell)
-                        .ok()
-                        .map_or(false, |res| res.is_failure())
-                    {
-                        eprintln!("ion: {}", err);
+                    IonError::PipelineExecutionError(PipelineError::CommandExecError(
+                        err,
+                        command,
+                    )) if err.kind() == io::ErrorKind::PermissionDenied && command.len() == 1 => {
+                        if Self::try_cd(&command[0], &mut shell)
+                            .ok()
+                            .map_or(false, |res| res.is_failure())
+                        {
+                            eprintln!("ion: {}", err);
+                            shell.reset_flow();
+                        }
+                    }
+                    _ => {
+                        eprintln!("ion: {}", why);
                         shell.reset_flow();
                     }
                 }
-                Err(err) => {
-                    eprintln!("ion: {}", err);
-                    shell.reset_flow();
-                }
             }
         }
         self.save_command(&cmd);
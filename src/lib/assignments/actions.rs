@@ -100,7 +100,7 @@ impl<'a> Action<'a> {
         match var.kind {
             Primitive::Indexed(..) | Primitive::Str => Ok(Action(var, operator, value)),
             Primitive::Array(_) | Primitive::HashMap(_) | Primitive::BTreeMap(_) => {
-                if is_array {
+                if is_array || is_structured(value) || is_call(value) {
                     Ok(Action(var, operator, value))
                 } else {
                     Err(AssignmentError::InvalidValue(var.kind, Primitive::Str))
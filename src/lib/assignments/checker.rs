@@ -1,10 +1,16 @@
 use crate::{
-    expansion::{self, Expander},
-    parser::lexers::assignments::{Primitive, TypeError},
+    expansion::{self, Expander, WordIterator, WordToken},
+    parser::lexers::{
+        assignments::{Primitive, TypeError},
+        ArgumentSplitter,
+    },
     shell::variables::Value,
     types,
 };
-use std::{iter::Iterator, rc::Rc};
+use std::{
+    iter::{FromIterator, Iterator},
+    rc::Rc,
+};
 
 /// Determines if the supplied value is either an array or a string.
 ///
@@ -28,6 +34,31 @@ pub fn is_array(value: &str) -> bool {
     }
 }
 
+/// Determines if the supplied value is a call to an array method such as `@from_json(...)`,
+/// whose result keeps the structure of its values when it is assigned.
+pub fn is_structured(value: &str) -> bool {
+    if !value.starts_with('@') || !value.ends_with(')') {
+        return false;
+    }
+    match single_word(value) {
+        Some(WordToken::ArrayMethod(method, _)) => method.is_structured(),
+        _ => false,
+    }
+}
+
+/// The word that the value consists of, if it is a single one
+fn single_word(value: &str) -> Option<WordToken<'_>> {
+    let mut words = WordIterator::new(value, false);
+    match (words.next(), words.next()) {
+        (Some(word), None) => Some(word),
+        _ => None,
+    }
+}
+
+/// Determines if the supplied value is a `@(...)` expression, whose value keeps its structure
+/// when it is returned by the function that it calls.
+pub fn is_call(value: &str) -> bool { value.starts_with("@(") && value.ends_with(')') }
+
 pub fn is_boolean(value: &mut types::Str) -> bool {
     if ["true", "1", "y"].contains(&value.as_str()) {
         value.clear();
@@ -42,26 +73,41 @@ pub fn is_boolean(value: &mut types::Str) -> bool {
     }
 }
 
+/// Splits an array literal into its elements without expanding them, so that the arrays and
+/// maps nested within it keep their structure.
+fn elements(value: &str) -> ArgumentSplitter<'_> {
+    ArgumentSplitter::new(&value[1..value.len() - 1])
+}
+
 fn get_map_of<E: Expander>(
     primitive_type: &Primitive,
     shell: &mut E,
     expression: &str,
 ) -> expansion::Result<Value<Rc<types::Function>>, E::Error> {
-    let array = shell.expand_string(expression)?;
-
     let inner_kind = match primitive_type {
         Primitive::HashMap(ref inner) | Primitive::BTreeMap(ref inner) => inner,
         _ => unreachable!(),
     };
 
+    let nested = inner_kind.is_collection() && is_array(expression);
+    let array: types::Args = if nested {
+        elements(expression).map(Into::into).collect()
+    } else {
+        shell.expand_string(expression)?
+    };
+
     let size = array.len();
 
     let iter = array.into_iter().map(|string| {
         let mut parts = string.splitn(2, '=');
         if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+            let key = if nested { shell.get_string(key)? } else { key.into() };
             value_check(shell, value, inner_kind).and_then(|val| match val {
-                Value::Str(_) | Value::Array(_) | Value::HashMap(_) | Value::BTreeMap(_) => {
-                    Ok(((*key).into(), val))
+                Value::Str(_) => Ok((key, val)),
+                Value::Array(_) | Value::HashMap(_) | Value::BTreeMap(_)
+                    if inner_kind.is_collection() =>
+                {
+                    Ok((key, val))
                 }
                 _ => Err(TypeError::BadValue((**inner_kind).clone()).into()),
             })
@@ -91,19 +137,104 @@ fn get_map_of<E: Expander>(
     }
 }
 
+/// Checks the values read from a JSON document, or returned by a function, against the expected
+/// type. An untyped assignment, which expects a `str`, keeps the whole value.
+fn structured_check(
+    value: Value<Rc<types::Function>>,
+    expected: &Primitive,
+) -> Result<Value<Rc<types::Function>>, TypeError> {
+    match expected {
+        Primitive::Str => Ok(value),
+        Primitive::Indexed(_, kind) if **kind == Primitive::Str => Ok(value),
+        _ => structure_check(value, expected),
+    }
+}
+
+/// Checks a structured value against the expected type, in which a `str` is only a string.
+///
+/// Since JSON objects are read as `BTreeMap`s, they are converted to the kind of map that is
+/// expected.
+pub fn structure_check(
+    value: Value<Rc<types::Function>>,
+    expected: &Primitive,
+) -> Result<Value<Rc<types::Function>>, TypeError> {
+    match (value, expected) {
+        (value @ Value::Str(_), Primitive::Str) => Ok(value),
+        (Value::Str(mut string), Primitive::Boolean) => {
+            if is_boolean(&mut string) {
+                Ok(Value::Str(string))
+            } else {
+                Err(TypeError::BadValue(expected.clone()))
+            }
+        }
+        (Value::Str(string), Primitive::Integer) if string.parse::<i64>().is_ok() => {
+            Ok(Value::Str(string))
+        }
+        (Value::Str(string), Primitive::Float) if string.parse::<f64>().is_ok() => {
+            Ok(Value::Str(string))
+        }
+        (Value::Array(array), Primitive::Array(inner)) => {
+            array.into_iter().map(|value| structure_check(value, inner)).collect()
+        }
+        (Value::HashMap(map), Primitive::HashMap(inner)) => {
+            entries_check(map, inner).map(Value::HashMap)
+        }
+        (Value::HashMap(map), Primitive::BTreeMap(inner)) => {
+            entries_check(map, inner).map(Value::BTreeMap)
+        }
+        (Value::BTreeMap(map), Primitive::HashMap(inner)) => {
+            entries_check(map, inner).map(Value::HashMap)
+        }
+        (Value::BTreeMap(map), Primitive::BTreeMap(inner)) => {
+            entries_check(map, inner).map(Value::BTreeMap)
+        }
+        (value, Primitive::Indexed(_, kind)) => structure_check(value, kind),
+        _ => Err(TypeError::BadValue(expected.clone())),
+    }
+}
+
+/// Checks the values of the entries of a map against the expected type
+fn entries_check<I, M>(entries: I, expected: &Primitive) -> Result<M, TypeError>
+where
+    I: IntoIterator<Item = (types::Str, Value<Rc<types::Function>>)>,
+    M: FromIterator<(types::Str, Value<Rc<types::Function>>)>,
+{
+    entries
+        .into_iter()
+        .map(|(key, value)| structure_check(value, expected).map(|value| (key, value)))
+        .collect()
+}
+
 pub fn value_check<E: Expander>(
     shell: &mut E,
     value: &str,
     expected: &Primitive,
 ) -> expansion::Result<Value<Rc<types::Function>>, E::Error> {
+    if is_structured(value) {
+        if let Some(WordToken::ArrayMethod(method, _)) = single_word(value) {
+            return structured_check(method.value(shell)?, expected).map_err(Into::into);
+        }
+    }
+
+    // A value returned by a function keeps its structure when it is called with `@(...)`
+    if is_call(value) {
+        if let Some(WordToken::ArrayProcess(command, false, None)) = single_word(value) {
+            return structured_check(shell.command_value(command)?, expected).map_err(Into::into);
+        }
+    }
+
     if is_array(value) {
-        let extracted = shell.get_array(value)?;
         match expected {
-            Primitive::Str => extracted
+            Primitive::Str => shell
+                .get_array(value)?
                 .iter()
                 .map(|item| value_check(shell, item, &Primitive::Str))
                 .collect::<Result<_, _>>(),
-            Primitive::Array(ref inner) => extracted
+            Primitive::Array(ref inner) if inner.is_collection() => {
+                elements(value).map(|item| value_check(shell, item, inner)).collect()
+            }
+            Primitive::Array(ref inner) => shell
+                .get_array(value)?
                 .iter()
                 .map(|item| value_check(shell, item, inner))
                 .collect::<Result<_, _>>(),
@@ -192,4 +323,73 @@ mod test {
         )
         .is_err());
     }
+
+    #[test]
+    fn is_nested_collection_() {
+        let int_array = || Primitive::Array(Box::new(Primitive::Integer));
+        assert_eq!(
+            value_check(
+                &mut DummyExpander,
+                "[[1 2] [3]]",
+                &Primitive::Array(Box::new(int_array()))
+            )
+            .unwrap(),
+            Value::Array(vec![
+                Value::Array(vec!["1".into(), "2".into()]),
+                Value::Array(vec!["3".into()])
+            ])
+        );
+        assert!(value_check(
+            &mut DummyExpander,
+            "[[1 2] 3]",
+            &Primitive::Array(Box::new(int_array()))
+        )
+        .is_err());
+
+        let mut inner = types::BTreeMap::new();
+        inner.insert("b".into(), Value::Array(vec!["1".into(), "2".into()]));
+        let mut expected = types::HashMap::new();
+        expected.insert("a".into(), Value::BTreeMap(inner));
+        assert_eq!(
+            value_check(
+                &mut DummyExpander,
+                "[a=[b=[1 2]]]",
+                &Primitive::HashMap(Box::new(Primitive::BTreeMap(Box::new(int_array()))))
+            )
+            .unwrap(),
+            Value::HashMap(expected)
+        );
+    }
+
+    #[test]
+    fn is_json_map_() {
+        let document = r#"@from_json('{"a": [1, 2], "b": []}')"#;
+        let mut expected = types::HashMap::new();
+        expected.insert("a".into(), Value::Array(vec!["1".into(), "2".into()]));
+        expected.insert("b".into(), Value::Array(vec![]));
+        assert_eq!(
+            value_check(
+                &mut DummyExpander,
+                document,
+                &Primitive::HashMap(Box::new(Primitive::Array(Box::new(Primitive::Integer))))
+            )
+            .unwrap(),
+            Value::HashMap(expected)
+        );
+        assert!(value_check(
+            &mut DummyExpander,
+            r#"@from_json('{"a": ["x"]}')"#,
+            &Primitive::HashMap(Box::new(Primitive::Array(Box::new(Primitive::Integer))))
+        )
+        .is_err());
+        // Only an untyped value may hold collections in place of strings
+        let document = r#"@from_json('{"a": ["x"]}')"#;
+        assert!(value_check(&mut DummyExpander, document, &Primitive::Str).is_ok());
+        assert!(value_check(
+            &mut DummyExpander,
+            document,
+            &Primitive::HashMap(Box::new(Primitive::Str))
+        )
+        .is_err());
+    }
 }
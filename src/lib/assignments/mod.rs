@@ -2,5 +2,5 @@ mod actions;
 mod checker;
 pub use self::{
     actions::{Action, AssignmentActions},
-    checker::{is_array, value_check},
+    checker::{is_array, structure_check, value_check},
 };
@@ -49,8 +49,8 @@ fn get_command_info<'a>(command: &str, shell: &mut Shell<'_>) -> Result<Cow<'a,
         Some(Value::Function(_)) => Ok("function".into()),
         _ if shell.builtins().contains(command) => Ok("builtin".into()),
         _ => {
-            let paths = env::var_os("PATH").unwrap_or_else(|| "/bin".into());
-            for path in env::split_paths(&paths) {
+            let paths = shell.variables().env().get("PATH").unwrap_or("/bin");
+            for path in env::split_paths(paths) {
                 let executable = path.join(command);
                 if executable.is_file() {
                     return Ok(executable.display().to_string().into());
@@ -94,53 +94,16 @@ pub fn echo(args: &[types::Str], _: &mut Shell<'_>) -> Status {
                 let mut check = false;
                 for &byte in arg {
                     match byte {
-                        b'\\' if check => {
-                            buffer.write_all(&[byte])?;
-                            check = false;
-                        }
-                        b'\\' => check = true,
-                        b'a' if check => {
-                            buffer.write_all(&[7])?; // bell
-                            check = false;
-                        }
-                        b'b' if check => {
-                            buffer.write_all(&[8])?; // backspace
-                            check = false;
-                        }
-                        b'c' if check => {
-                            return Ok(());
-                        }
-                        b'e' if check => {
-                            buffer.write_all(&[27])?; // escape
-                            check = false;
-                        }
-                        b'f' if check => {
-                            buffer.write_all(&[12])?; // form feed
-                            check = false;
-                        }
-                        b'n' if check => {
-                            buffer.write_all(b"\n")?; // newline
-                            check = false;
-                        }
-                        b'r' if check => {
-                            buffer.write_all(b"\r")?;
-                            check = false;
-                        }
-                        b't' if check => {
-                            buffer.write_all(b"\t")?;
-                            check = false;
-                        }
-                        b'v' if check => {
-                            buffer.write_all(&[11])?; // vertical tab
-                            check = false;
-                        }
+                        b'\\' if !check => check = true,
+                        b'c' if check => return Ok(()),
                         _ if check => {
-                            buffer.write_all(&[b'\\', byte])?;
+                            match escaped(byte) {
+                                Some(byte) => buffer.write_all(&[byte])?,
+                                None => buffer.write_all(&[b'\\', byte])?,
+                            }
                             check = false;
                         }
-                        _ => {
-                            buffer.write_all(&[byte])?;
-                        }
+                        _ => buffer.write_all(&[byte])?,
                     }
                 }
             } else {
@@ -155,3 +118,20 @@ pub fn echo(args: &[types::Str], _: &mut Shell<'_>) -> Status {
 
     inner().and_then(|_| buffer.flush()).into()
 }
+
+/// The byte that the escape sequence `\byte` stands for, as interpreted by `echo -e` and
+/// `printf`. `\c`, which stops the output, is left to the caller.
+pub(super) const fn escaped(byte: u8) -> Option<u8> {
+    match byte {
+        b'\\' => Some(b'\\'),
+        b'a' => Some(7),  // bell
+        b'b' => Some(8),  // backspace
+        b'e' => Some(27), // escape
+        b'f' => Some(12), // form feed
+        b'n' => Some(b'\n'),
+        b'r' => Some(b'\r'),
+        b't' => Some(b'\t'),
+        b'v' => Some(11), // vertical tab
+        _ => None,
+    }
+}
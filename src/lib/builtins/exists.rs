@@ -312,9 +312,9 @@ mod tests {
         let name_str = "test_function";
         let name = types::Str::from(name_str);
         let mut args = Vec::new();
-        args.push(KeyBuf { name: "testy".into(), kind: Primitive::Str });
+        args.push(KeyBuf { name: "testy".into(), kind: Primitive::Str }.into());
         let mut statements = Vec::new();
-        statements.push(Statement::End);
+        statements.push(Statement::End.into());
         let description: types::Str = "description".into();
 
         shell.variables_mut().set(
@@ -461,9 +461,9 @@ mod tests {
         let name_str = "test_function";
         let name: types::Str = name_str.into();
         let mut args = Vec::new();
-        args.push(KeyBuf { name: "testy".into(), kind: Primitive::Str });
+        args.push(KeyBuf { name: "testy".into(), kind: Primitive::Str }.into());
         let mut statements = Vec::new();
-        statements.push(Statement::End);
+        statements.push(Statement::End.into());
         let description: types::Str = "description".into();
 
         shell.variables_mut().set(
@@ -27,3 +27,53 @@ pub fn fn_(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
     }
     Status::SUCCESS
 }
+
+#[builtin(
+    desc = "print the location a function was called from",
+    man = "
+SYNOPSIS
+    caller [ -h | --help ] [FRAME]
+
+DESCRIPTION
+    Prints the line and the file of the statement that called the current function.
+
+    If FRAME is given, the name of the calling function is printed as well, and FRAME selects how
+    many calls to go up the call stack, where 0 is the call of the current function. Calls made
+    outside of any function are attributed to 'main'.
+
+    Returns a failure status when the shell is not executing a function, or when FRAME is deeper
+    than the call stack.
+
+EXAMPLES
+    fn sqrt value:float
+        if test $value -lt 0
+            echo cannot compute the square root of $value, called at line $(caller)
+        end
+    end"
+)]
+pub fn caller(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
+    let frame = match args.get(1).map(|arg| arg.parse::<usize>()) {
+        None => 0,
+        Some(Ok(frame)) => frame,
+        Some(Err(_)) => {
+            return Status::bad_argument(format!("ion: caller: '{}' is not a valid frame", args[1]))
+        }
+    };
+
+    let call_stack = shell.call_stack();
+    let index = match call_stack.len().checked_sub(1).and_then(|last| last.checked_sub(frame)) {
+        Some(index) => index,
+        None => return Status::FALSE,
+    };
+    let call_site = call_stack[index].call_site();
+    let file = call_site.file().unwrap_or("ion");
+
+    if args.len() > 1 {
+        let function =
+            index.checked_sub(1).map_or("main", |caller| call_stack[caller].name().as_str());
+        println!("{} {} {}", call_site.line(), function, file);
+    } else {
+        println!("{} {}", call_site.line(), file);
+    }
+    Status::SUCCESS
+}
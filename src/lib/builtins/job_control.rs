@@ -3,10 +3,65 @@
 
 use super::Status;
 use crate::{
-    shell::{BackgroundProcess, Shell},
+    shell::{
+        variables::{Value, Variables},
+        BackgroundProcess, Shell,
+    },
     types,
 };
+use itertools::Itertools;
 use smallvec::SmallVec;
+use std::time::{Duration, UNIX_EPOCH};
+use types_rs::json_string;
+
+/// Resolves a job spec to the number of a job, which may have exited since:
+///
+/// - `%N`: the job numbered N
+/// - `%+`, `%%` or `%`: the current job, which is the last one sent to the background
+/// - `%-`: the previous job, which was sent to the background before the current one
+/// - `%name`: the job whose command starts with name
+/// - `%?text`: the job whose command contains text
+///
+/// Without the `%`, the spec is the number of the job.
+pub fn resolve_job(shell: &Shell<'_>, spec: &str) -> Result<usize, String> {
+    fn find_job<F: Fn(&str) -> bool>(
+        shell: &Shell<'_>,
+        spec: &str,
+        matches: F,
+    ) -> Result<usize, String> {
+        let jobs = shell.background_jobs();
+        let mut found = jobs
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| job.exists() && matches(job.name()))
+            .map(|(njob, _)| njob);
+        match (found.next(), found.next()) {
+            (Some(njob), None) => Ok(njob),
+            (Some(_), Some(_)) => Err(format!("ambiguous job spec: '{}'", spec)),
+            (None, _) => Err(format!("no such job: '{}'", spec)),
+        }
+    }
+
+    match spec {
+        "%" | "%%" | "%+" => shell.previous_job().ok_or_else(|| "no current job".into()),
+        "%-" => shell.older_job().ok_or_else(|| "no previous job".into()),
+        _ if spec.starts_with("%?") => find_job(shell, spec, |name| name.contains(&spec[2..])),
+        _ if spec.starts_with('%') => spec[1..]
+            .parse::<usize>()
+            .or_else(|_| find_job(shell, spec, |name| name.starts_with(&spec[1..]))),
+        _ => spec.parse::<usize>().map_err(|_| format!("invalid job spec: '{}'", spec)),
+    }
+}
+
+/// Resolves a job spec to the number of a job which still exists. See [`resolve_job`].
+pub fn job_spec(shell: &Shell<'_>, spec: &str) -> Result<usize, String> {
+    let njob = resolve_job(shell, spec)?;
+    if shell.background_jobs().get(njob).map_or(false, BackgroundProcess::exists) {
+        Ok(njob)
+    } else {
+        Err(format!("job {} does not exist", spec))
+    }
+}
 
 /// Disowns given process job IDs, and optionally marks jobs to not receive SIGHUP signals.
 /// The `-a` flag selects all jobs, `-r` selects all running jobs, and `-h` specifies to mark
@@ -26,11 +81,7 @@ pub fn disown(shell: &mut Shell<'_>, args: &[types::Str]) -> Result<(), String>
             "-a" => all_jobs = true,
             "-h" => no_sighup = true,
             "-r" => run_jobs = true,
-            _ => {
-                let jobspec =
-                    arg.parse::<usize>().map_err(|_| format!("invalid jobspec: '{}'", arg))?;
-                collected_jobs.push(jobspec);
-            }
+            _ => collected_jobs.push(job_spec(shell, arg)?),
         }
     }
 
@@ -64,27 +115,162 @@ pub fn disown(shell: &mut Shell<'_>, args: &[types::Str]) -> Result<(), String>
     Ok(())
 }
 
-/// Display a list of all jobs running in the background.
-pub fn jobs(shell: &mut Shell<'_>) {
-    for (id, process) in shell.background_jobs().iter().enumerate() {
-        if process.exists() {
-            eprintln!("[{}] {}", id, process);
+/// Resolves a job given to `wait`: a job spec, or the process ID of a job. The job may have
+/// exited, as long as its exit status is still known.
+fn waited_job(shell: &Shell<'_>, arg: &str) -> Result<usize, String> {
+    let njob = if arg.starts_with('%') {
+        resolve_job(shell, arg)?
+    } else {
+        let pid = arg.parse::<i32>().map_err(|_| format!("invalid process id: '{}'", arg))?;
+        shell
+            .background_jobs()
+            .iter()
+            .position(|job| job.pid().as_raw() == pid)
+            .ok_or_else(|| format!("no job has the process id {}", pid))?
+    };
+    match shell.background_jobs().get(njob) {
+        Some(job) if job.exists() || job.exit_status().is_some() => Ok(njob),
+        _ => Err(format!("job {} does not exist", arg)),
+    }
+}
+
+/// The status returned by `wait` when the timeout expires, as with timeout(1)
+const TIMED_OUT: Status = Status::from_exit_code(124);
+
+/// Waits for the given jobs, or for all the running jobs, to exit or stop, returning the exit
+/// status of the last one. With `-n`, only waits for the first of them to exit, and with `-p`,
+/// stores the process ID of that job in a variable. With `-t`, gives up after a number of
+/// seconds.
+pub fn wait(shell: &mut Shell<'_>, args: &[types::Str]) -> Status {
+    let mut any = false;
+    let mut variable = None;
+    let mut timeout = None;
+    let mut args = args.iter();
+    let mut specs = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-n" => any = true,
+            "-p" => match args.next() {
+                Some(name) if Variables::is_valid_name(name) => variable = Some(name),
+                Some(name) => {
+                    return Status::bad_argument(format!(
+                        "ion: wait: '{}' is not a valid variable name",
+                        name
+                    ))
+                }
+                None => return Status::bad_argument("ion: wait: -p requires a variable name"),
+            },
+            "-t" => match args.next().map(|secs| secs.parse::<f64>()) {
+                Some(Ok(secs)) if secs >= 0. && secs.is_finite() => {
+                    timeout = Some(Duration::from_secs_f64(secs))
+                }
+                Some(_) => return Status::bad_argument("ion: wait: invalid timeout"),
+                None => return Status::bad_argument("ion: wait: -t requires a timeout"),
+            },
+            _ => specs.push(arg),
         }
     }
+
+    let njobs: Vec<usize> = if specs.is_empty() {
+        let jobs = shell.background_jobs();
+        jobs.iter().enumerate().filter(|(_, job)| job.is_running()).map(|(njob, _)| njob).collect()
+    } else {
+        match specs.iter().map(|spec| waited_job(shell, spec)).collect::<Result<_, _>>() {
+            Ok(njobs) => njobs,
+            Err(why) => return Status::error(format!("ion: wait: {}", why)),
+        }
+    };
+
+    if any {
+        // A given job which has already exited is reported right away
+        let (exited, running) = {
+            let jobs = shell.background_jobs();
+            (
+                njobs.iter().copied().find(|&njob| !jobs[njob].exists()),
+                njobs.iter().any(|&njob| jobs[njob].is_running()),
+            )
+        };
+        let njob = match exited {
+            Some(njob) => njob,
+            None if !running => {
+                eprintln!("ion: wait: no running jobs");
+                return Status::NO_SUCH_COMMAND;
+            }
+            None => match shell.wait_for_any(&njobs, timeout) {
+                Ok(Some(njob)) => njob,
+                Ok(None) => return TIMED_OUT,
+                Err(err) => return Status::error(err.to_string()),
+            },
+        };
+        if let Some(name) = variable {
+            let pid = shell.background_jobs()[njob].pid();
+            shell.variables_mut().set(name, Value::Str(pid.to_string().into()));
+        }
+        return shell.job_status(njob);
+    }
+
+    match shell.wait_for_jobs(&njobs, timeout) {
+        Ok(true) if specs.is_empty() => Status::SUCCESS,
+        Ok(true) => njobs.last().map_or(Status::SUCCESS, |&njob| shell.job_status(njob)),
+        Ok(false) => TIMED_OUT,
+        Err(err) => Status::error(err.to_string()),
+    }
+}
+
+/// Display a list of all jobs running in the background. With `-l`, the IDs of all the
+/// processes of each job are listed. With `-p`, only the process group IDs of the jobs are
+/// printed, and with `-j`, the jobs are printed as a JSON array, both to stdout.
+pub fn jobs(shell: &Shell<'_>, args: &[types::Str]) -> Status {
+    let processes = shell.background_jobs();
+    let jobs = processes.iter().enumerate().filter(|(_, process)| process.exists());
+    match args.first().map(types::Str::as_str) {
+        None => jobs.for_each(|(id, process)| eprintln!("[{}] {}", id, process)),
+        Some("-l") => jobs.for_each(|(id, process)| {
+            eprintln!(
+                "[{}] {} {}\t{}",
+                id,
+                process.pids().iter().join(" "),
+                process.state(),
+                process.name()
+            )
+        }),
+        Some("-p") => jobs.for_each(|(_, process)| println!("{}", process.pid())),
+        Some("-j") => {
+            println!("[{}]", jobs.map(|(id, process)| job_to_json(id, process)).join(","));
+        }
+        Some(arg) => return Status::bad_argument(format!("ion: jobs: invalid option: '{}'", arg)),
+    }
+    Status::SUCCESS
+}
+
+/// A job as a JSON object, for its machine-readable output. The command, the directory and the
+/// state of the job are always strings, and its IDs and start time are always numbers.
+fn job_to_json(id: usize, process: &BackgroundProcess) -> String {
+    let started = process.started().duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(
+        r#"{{"command":{},"cwd":{},"id":{},"pgid":{},"pids":[{}],"started":{},"state":{}}}"#,
+        json_string(process.name()),
+        json_string(&process.cwd().to_string_lossy()),
+        id,
+        process.pid(),
+        process.pids().iter().join(","),
+        started.as_secs(),
+        json_string(&process.state().to_string()),
+    )
 }
 
 /// Hands control of the foreground process to the specified jobs, recording their exit status.
 /// If the job is stopped, the job will be resumed.
 /// If multiple jobs are given, then only the last job's exit status will be returned.
 pub fn fg(shell: &mut Shell<'_>, args: &[types::Str]) -> Status {
-    fn fg_job(shell: &mut Shell<'_>, njob: usize) -> Status {
-        let (pid, cont) = {
-            if let Some(job) = shell.background_jobs().iter().nth(njob).filter(|p| p.exists()) {
-                (job.pid(), !job.is_running())
-            } else {
-                // Informs the user that the specified job ID no longer exists.
-                return Status::error(format!("ion: fg: job {} does not exist", njob));
+    fn fg_job(shell: &mut Shell<'_>, spec: &str) -> Status {
+        let (pid, cont) = match job_spec(shell, spec) {
+            Ok(njob) => {
+                let jobs = shell.background_jobs();
+                (jobs[njob].pid(), !jobs[njob].is_running())
             }
+            // Informs the user that the specified job no longer exists.
+            Err(why) => return Status::error(format!("ion: fg: {}", why)),
         };
         // Give the bg task the foreground, and wait for it to finish. Also resume it if it
         // isn't running
@@ -92,58 +278,90 @@ pub fn fg(shell: &mut Shell<'_>, args: &[types::Str]) -> Status {
     }
 
     if args.is_empty() {
-        if let Some(previous_job) = shell.previous_job() {
-            fg_job(shell, previous_job)
+        if shell.previous_job().is_some() {
+            fg_job(shell, "%+")
         } else {
             Status::error("ion: fg: no jobs are running in the background")
         }
     } else {
+        let mut status = Status::SUCCESS;
         for arg in args {
-            match arg.parse::<usize>() {
-                Ok(njob) => {
-                    fg_job(shell, njob);
-                }
-                Err(_) => {
-                    return Status::error(format!("ion: fg: {} is not a valid job number", arg))
-                }
-            }
+            status = fg_job(shell, arg);
         }
-        Status::SUCCESS
+        status
     }
 }
 
 /// Resumes a stopped background process, if it was stopped.
 pub fn bg(shell: &mut Shell<'_>, args: &[types::Str]) -> Status {
-    fn bg_job(shell: &mut Shell<'_>, njob: usize) -> Status {
-        if let Some(job) = shell.background_jobs().iter().nth(njob).filter(|p| p.exists()) {
-            if job.is_running() {
-                Status::error(format!("ion: bg: job {} is already running", njob))
-            } else {
-                job.resume();
-                Status::SUCCESS
+    fn bg_job(shell: &mut Shell<'_>, spec: &str) -> Status {
+        match job_spec(shell, spec) {
+            Ok(njob) => {
+                let jobs = shell.background_jobs();
+                let job = &jobs[njob];
+                if job.is_running() {
+                    Status::error(format!("ion: bg: job {} is already running", spec))
+                } else {
+                    job.resume();
+                    Status::SUCCESS
+                }
             }
-        } else {
-            Status::error(format!("ion: bg: job {} does not exist", njob))
+            Err(why) => Status::error(format!("ion: bg: {}", why)),
         }
     }
 
     if args.is_empty() {
-        if let Some(previous_job) = shell.previous_job() {
-            bg_job(shell, previous_job)
+        if shell.previous_job().is_some() {
+            bg_job(shell, "%+")
         } else {
             Status::error("ion: bg: no jobs are running in the background")
         }
     } else {
         for arg in args {
-            if let Ok(njob) = arg.parse::<usize>() {
-                let status = bg_job(shell, njob);
-                if !status.is_success() {
-                    return status;
-                }
-            } else {
-                return Status::error(format!("ion: bg: {} is not a valid job number", arg));
-            };
+            let status = bg_job(shell, arg);
+            if !status.is_success() {
+                return status;
+            }
         }
         Status::SUCCESS
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::ProcessState;
+    use nix::unistd::Pid;
+
+    fn add_job(shell: &mut Shell<'_>, pid: i32, name: &str) {
+        let pid = Pid::from_raw(pid);
+        let job = BackgroundProcess::new(pid, vec![pid], ProcessState::Running, name.into());
+        shell.background_jobs_mut().push(job);
+    }
+
+    #[test]
+    fn resolve_job_specs() {
+        let mut shell = Shell::default();
+        add_job(&mut shell, 1001, "sleep 5");
+        add_job(&mut shell, 1002, "sleep 6");
+        add_job(&mut shell, 1003, "cat file");
+
+        assert_eq!(resolve_job(&shell, "%1"), Ok(1));
+        assert_eq!(resolve_job(&shell, "2"), Ok(2));
+        assert_eq!(resolve_job(&shell, "%cat"), Ok(2));
+        assert_eq!(resolve_job(&shell, "%?6"), Ok(1));
+        assert_eq!(resolve_job(&shell, "%?fi"), Ok(2));
+        assert_eq!(resolve_job(&shell, "%sle"), Err("ambiguous job spec: '%sle'".to_string()));
+        assert_eq!(resolve_job(&shell, "%?sle"), Err("ambiguous job spec: '%?sle'".to_string()));
+        assert_eq!(resolve_job(&shell, "%vim"), Err("no such job: '%vim'".to_string()));
+        assert_eq!(resolve_job(&shell, "vim"), Err("invalid job spec: 'vim'".to_string()));
+        assert_eq!(resolve_job(&shell, "%+"), Err("no current job".to_string()));
+        assert_eq!(resolve_job(&shell, "%-"), Err("no previous job".to_string()));
+        assert_eq!(job_spec(&shell, "%5"), Err("job %5 does not exist".to_string()));
+
+        // Jobs which exited are not looked up by their command
+        shell.background_jobs_mut()[2].forget();
+        assert_eq!(resolve_job(&shell, "%cat"), Err("no such job: '%cat'".to_string()));
+        assert_eq!(job_spec(&shell, "%2"), Err("job %2 does not exist".to_string()));
+    }
+}
@@ -0,0 +1,162 @@
+use super::{job_control::job_spec, Status};
+use crate as ion_shell;
+use crate::{shell::Shell, types};
+use builtins_proc::builtin;
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::Pid,
+};
+use std::convert::TryFrom;
+
+/// Parses a signal given by name, with or without the SIG prefix, or by number. The signal 0,
+/// which only checks that the target exists, is `None`.
+fn parse_signal(name: &str) -> Result<Option<Signal>, String> {
+    let signal = match name.parse::<i32>() {
+        Ok(0) => return Ok(None),
+        Ok(number) => Signal::try_from(number).ok(),
+        Err(_) => {
+            let name = name.to_uppercase();
+            let name = if name.starts_with("SIG") { name } else { ["SIG", &name].concat() };
+            name.parse().ok()
+        }
+    };
+    signal.map(Some).ok_or_else(|| format!("invalid signal: '{}'", name))
+}
+
+/// The name of a signal, without the SIG prefix
+fn signal_name(signal: Signal) -> &'static str { &signal.as_str()[3..] }
+
+/// Prints the names of all the signals, or converts each argument between a name and a number.
+/// An exit status is converted to the signal which terminated the process.
+fn list_signals(args: &[types::Str]) -> Status {
+    if args.is_empty() {
+        for signal in Signal::iterator() {
+            println!("{:>2} {}", signal as i32, signal_name(signal));
+        }
+        return Status::SUCCESS;
+    }
+
+    for arg in args {
+        let result = match arg.parse::<i32>() {
+            Ok(number) => Signal::try_from(if number > 128 { number - 128 } else { number })
+                .map(|signal| signal_name(signal).to_string())
+                .map_err(|_| format!("invalid signal: '{}'", arg)),
+            Err(_) => parse_signal(arg).map(|signal| signal.map_or(0, |s| s as i32).to_string()),
+        };
+        match result {
+            Ok(converted) => println!("{}", converted),
+            Err(why) => return Status::error(format!("ion: kill: {}", why)),
+        }
+    }
+    Status::SUCCESS
+}
+
+/// Sends the signal to a job, or to a process if the target is not a job spec
+fn kill_target(shell: &Shell<'_>, target: &str, signal: Option<Signal>) -> Result<(), String> {
+    if target.starts_with('%') {
+        let njob = job_spec(shell, target)?;
+        let jobs = shell.background_jobs();
+        let job = &jobs[njob];
+        signal::killpg(job.pid(), signal).map_err(|why| why.to_string())?;
+        // A stopped job has to be resumed for the signal to terminate it
+        let stops = [Signal::SIGSTOP, Signal::SIGTSTP, Signal::SIGTTIN, Signal::SIGTTOU];
+        if job.is_stopped() && signal.map_or(false, |signal| !stops.contains(&signal)) {
+            job.resume();
+        }
+        Ok(())
+    } else {
+        let pid = target.parse::<i32>().map_err(|_| format!("invalid process id: '{}'", target))?;
+        signal::kill(Pid::from_raw(pid), signal).map_err(|why| why.to_string())
+    }
+}
+
+#[builtin(
+    desc = "send a signal to jobs and processes",
+    man = "
+SYNOPSIS
+    kill [ -h | --help ] [-s SIGNAL | -SIGNAL] TARGET...
+    kill -l [STATUS | SIGNAL]...
+
+DESCRIPTION
+    Sends SIGNAL, which is SIGTERM by default, to each TARGET. A TARGET is either a job spec, in
+    which case the signal is sent to all the processes of the job, or a process ID. A negative
+    process ID, which must follow '--', selects a process group. Stopped jobs are resumed after
+    being sent a signal which does not stop them, so that they can handle it.
+
+    Signals may be given by name, with or without the SIG prefix, or by number. The signal 0
+    is not sent, but checks that the targets exist.
+
+JOB SPECS
+    %N      the job numbered N, as listed by jobs
+    %+, %%  the current job, which is the last one sent to the background
+    %-      the previous job
+    %name   the job whose command starts with name
+    %?text  the job whose command contains text
+
+OPTIONS
+    -l
+        list the signals, or convert each exit STATUS or SIGNAL to the name or the number of
+        the signal
+    -s SIGNAL
+        the signal to send
+
+EXAMPLES
+    kill %1
+    kill -s HUP %server
+    kill -9 12345
+    kill -l 143"
+)]
+pub fn kill(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
+    let mut signal = Some(Signal::SIGTERM);
+    let mut targets = &args[1..];
+    match targets.first().map(types::Str::as_str) {
+        None => return Status::bad_argument("usage: kill [-s SIGNAL | -SIGNAL] TARGET..."),
+        Some("-l") => return list_signals(&targets[1..]),
+        Some("-s") => {
+            signal = match targets.get(1).map(|name| parse_signal(name)) {
+                Some(Ok(parsed)) => parsed,
+                Some(Err(why)) => return Status::bad_argument(format!("ion: kill: {}", why)),
+                None => return Status::bad_argument("ion: kill: -s requires a signal"),
+            };
+            targets = &targets[2..];
+        }
+        Some("--") => targets = &targets[1..],
+        Some(arg) if arg.starts_with('-') => {
+            signal = match parse_signal(&arg[1..]) {
+                Ok(parsed) => parsed,
+                Err(why) => return Status::bad_argument(format!("ion: kill: {}", why)),
+            };
+            targets = &targets[1..];
+        }
+        Some(_) => (),
+    }
+    if targets.first().map_or(false, |arg| arg.as_str() == "--") {
+        targets = &targets[1..];
+    }
+    if targets.is_empty() {
+        return Status::bad_argument("ion: kill: no target specified");
+    }
+
+    let mut status = Status::SUCCESS;
+    for target in targets {
+        if let Err(why) = kill_target(shell, target, signal) {
+            status = Status::error(format!("ion: kill: {}: {}", target, why));
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_signals() {
+        assert_eq!(parse_signal("TERM"), Ok(Some(Signal::SIGTERM)));
+        assert_eq!(parse_signal("sigkill"), Ok(Some(Signal::SIGKILL)));
+        assert_eq!(parse_signal("1"), Ok(Some(Signal::SIGHUP)));
+        assert_eq!(parse_signal("0"), Ok(None));
+        assert!(parse_signal("NOPE").is_err());
+        assert!(parse_signal("100").is_err());
+    }
+}
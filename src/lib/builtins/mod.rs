@@ -9,12 +9,15 @@ mod functions;
 mod helpers;
 mod is;
 mod job_control;
+mod kill;
 mod math;
+mod printf;
 mod random;
 mod set;
 mod source;
 mod status;
 mod test;
+mod trap;
 mod variables;
 
 pub use self::{
@@ -22,15 +25,18 @@ pub use self::{
     conditionals::{builtin_contains, builtin_ends_with, builtin_starts_with},
     echo::builtin_echo,
     exists::builtin_exists,
-    functions::builtin_fn_,
+    functions::{builtin_caller, builtin_fn_},
     helpers::Status,
     is::builtin_is,
+    kill::builtin_kill,
     man_pages::check_help,
     math::builtin_math,
+    printf::builtin_printf,
     set::builtin_set,
     source::builtin_source,
     status::builtin_status,
     test::builtin_test,
+    trap::builtin_trap,
     variables::{builtin_alias, builtin_drop, builtin_unalias},
 };
 use crate as ion_shell;
@@ -159,9 +165,14 @@ impl<'a> BuiltinMap<'a> {
 
     /// Create and control variables
     ///
-    /// Contains `fn`, `alias`, `unalias`, `drop`, `read`
+    /// Contains `fn`, `caller`, `alias`, `unalias`, `drop`, `read`
     pub fn with_variables(&mut self) -> &mut Self {
         self.add("fn", &builtin_fn_, "Print list of functions")
+            .add(
+                "caller",
+                &builtin_caller,
+                "Print the location the current function was called from",
+            )
             .add("alias", &builtin_alias, "View, set or unset aliases")
             .add("unalias", &builtin_unalias, "Delete an alias")
             .add("drop", &builtin_drop, "Delete a variable")
@@ -170,7 +181,7 @@ impl<'a> BuiltinMap<'a> {
 
     /// Control subrpocesses states
     ///
-    /// Contains `disown`, `bg`, `fg`, `wait`, `isatty`, `jobs`
+    /// Contains `disown`, `bg`, `fg`, `wait`, `isatty`, `jobs`, `kill`, `trap`
     pub fn with_process_control(&mut self) -> &mut Self {
         self.add("disown", &builtin_disown, DISOWN_DESC)
             .add("bg", &builtin_bg, "Resumes a stopped background process")
@@ -182,6 +193,8 @@ impl<'a> BuiltinMap<'a> {
             )
             .add("isatty", &builtin_isatty, "Returns 0 exit status if the supplied FD is a tty")
             .add("jobs", &builtin_jobs, "Displays all jobs that are attached to the background")
+            .add("kill", &builtin_kill, "Sends a signal to jobs and processes")
+            .add("trap", &builtin_trap, "Runs a command when the shell receives a signal")
     }
 
     /// Utilities concerning the filesystem
@@ -229,12 +242,13 @@ impl<'a> BuiltinMap<'a> {
 
     /// Basic utilities for any ion embedded library
     ///
-    /// Contains `help`, `source`, `status`, `echo`, `type`, `which`
+    /// Contains `help`, `source`, `status`, `echo`, `printf`, `type`, `which`
     pub fn with_basic(&mut self) -> &mut Self {
         self.add("help", &builtin_help, HELP_DESC)
             .add("source", &builtin_source, SOURCE_DESC)
             .add("status", &builtin_status, "Evaluates the current runtime status")
             .add("echo", &builtin_echo, "Display a line of text")
+            .add("printf", &builtin_printf, "Format and print data")
             .add("which", &builtin_which, "indicates what would be called for a given command")
             .add("type", &builtin_which, "indicates what would be called for a given command")
     }
@@ -263,7 +277,7 @@ DESCRIPTION
     Execute the script given in argument and apply env vars diff to the current shell
     If the script is a file, the file is executed, else is it treated as a literal script"
 )]
-pub fn source_sh(args: &[types::Str], _shell: &mut Shell<'_>) -> Status {
+pub fn source_sh(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
     let mut arg = match args.get(1) {
         None => return Status::bad_argument("Please pass a shell script as option"),
         Some(arg) => Cow::Borrowed(arg),
@@ -278,6 +292,9 @@ pub fn source_sh(args: &[types::Str], _shell: &mut Shell<'_>) -> Status {
     let script = format!("{}\nenv | sort > {}", arg, temp.as_path().display());
     match Command::new("sh")
         .args(&["-c", &script])
+        .env_clear()
+        .envs(shell.variables().env().iter())
+        .current_dir(shell.variables().env().cwd())
         .stdout(Stdio::null())
         .stderr(Stdio::null())
         .status()
@@ -304,10 +321,10 @@ pub fn source_sh(args: &[types::Str], _shell: &mut Shell<'_>) -> Status {
                         continue;
                     }
                 };
-                let prev_val = std::env::var_os(name);
-                if prev_val.as_ref().and_then(|x| x.to_str()) != Some(val) {
+                let env = shell.variables_mut().env_mut();
+                if env.get(name) != Some(val) {
                     println!("Set {} to {}", name, val);
-                    std::env::set_var(name, val);
+                    env.set(name, val);
                 }
             }
             Status::SUCCESS
@@ -349,31 +366,31 @@ DESCRIPTION
 "
 )]
 pub fn cd(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
+    let cdpath = shell.variables().get("CDPATH").cloned();
+    let (dir_stack, env) = shell.dir_stack_env_mut();
     let err = match args.get(1) {
         Some(dir) => {
             let dir = dir.as_str();
-            if let Some(Value::Array(cdpath)) = shell.variables().get("CDPATH").cloned() {
+            if let Some(Value::Array(cdpath)) = cdpath {
                 if dir == "-" {
-                    shell.dir_stack_mut().switch_to_previous_directory()
+                    dir_stack.switch_to_previous_directory(env)
                 } else {
                     let check_cdpath_first = cdpath
                         .iter()
                         .map(|path| {
                             let path_dir = Path::new(&path.to_string()).join(dir);
-                            shell.dir_stack_mut().change_and_push_dir(&path_dir)
+                            dir_stack.change_and_push_dir(&path_dir, env)
                         })
                         .find(Result::is_ok)
-                        .unwrap_or_else(|| {
-                            shell.dir_stack_mut().change_and_push_dir(Path::new(dir))
-                        });
-                    shell.dir_stack_mut().popd(1);
+                        .unwrap_or_else(|| dir_stack.change_and_push_dir(Path::new(dir), env));
+                    dir_stack.popd(1);
                     check_cdpath_first
                 }
             } else {
-                shell.dir_stack_mut().change_and_push_dir(Path::new(dir))
+                dir_stack.change_and_push_dir(Path::new(dir), env)
             }
         }
-        None => shell.dir_stack_mut().switch_to_home_directory(),
+        None => dir_stack.switch_to_home_directory(env),
     };
 
     match err {
@@ -521,30 +538,31 @@ pub fn pushd(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
         }
     }
 
+    let (dir_stack, env) = shell.dir_stack_env_mut();
     match action {
         Action::Switch => {
             if !keep_front {
-                if let Err(why) = shell.dir_stack_mut().swap(1) {
+                if let Err(why) = dir_stack.swap(1, env) {
                     return Status::error(format!("ion: pushd: {}", why));
                 }
             }
         }
         Action::RotLeft(num) => {
             if !keep_front {
-                if let Err(why) = shell.dir_stack_mut().rotate_left(num) {
+                if let Err(why) = dir_stack.rotate_left(num, env) {
                     return Status::error(format!("ion: pushd: {}", why));
                 }
             }
         }
         Action::RotRight(num) => {
             if !keep_front {
-                if let Err(why) = shell.dir_stack_mut().rotate_right(num) {
+                if let Err(why) = dir_stack.rotate_right(num, env) {
                     return Status::error(format!("ion: pushd: {}", why));
                 }
             }
         }
         Action::Push(dir) => {
-            if let Err(why) = shell.dir_stack_mut().pushd(&dir, keep_front) {
+            if let Err(why) = dir_stack.pushd(&dir, keep_front, env) {
                 return Status::error(format!("ion: pushd: {}", why));
             }
         }
@@ -602,7 +620,8 @@ pub fn popd(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
             index = 1;
         } else if index == 0 {
             // change to new directory, return if not possible
-            if let Err(why) = shell.dir_stack_mut().set_current_dir_by_index(1) {
+            let (dir_stack, env) = shell.dir_stack_env_mut();
+            if let Err(why) = dir_stack.set_current_dir_by_index(1, env) {
                 return Status::error(format!("ion: popd: {}", why));
             } else {
                 return Status::error(format!("ion: popd: {}: invalid argument", arg));
@@ -611,9 +630,9 @@ pub fn popd(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
     }
 
     // pop element
-    let dir_stack = shell.dir_stack_mut();
+    let (dir_stack, env) = shell.dir_stack_env_mut();
     if dir_stack.popd(index).is_some() {
-        if let Err(err) = dir_stack.set_current_dir_by_index(0) {
+        if let Err(err) = dir_stack.set_current_dir_by_index(0, env) {
             return Status::error(format!("ion: popd: {}", err));
         }
         println!("{}", shell.dir_stack().dirs().map(|dir| dir.display()).format(" "));
@@ -725,44 +744,67 @@ DESCRIPTION
 pub fn false_(args: &[types::Str], _: &mut Shell<'_>) -> Status { Status::FALSE }
 
 #[builtin(
-    desc = "wait for a background job",
+    desc = "wait for background jobs",
     man = "
 SYNOPSIS
-    wait
+    wait [-n] [-p VAR] [-t SECONDS] [JOB...]
 
 DESCRIPTION
-    Wait for the background jobs to finish"
+    Wait for the background jobs to finish. If JOBs are given, wait for each of them to exit or
+    stop instead, and return the exit status of the last one. A JOB is either a job spec, as
+    accepted by kill, or the process ID of a job. The status of a job which has already exited
+    can be retrieved until its number is given to another job.
+
+OPTIONS
+    -n
+        wait for the first of the running JOBs, or of all the running jobs, to exit, and
+        return its exit status. A JOB which has already exited is reported right away. Fails
+        with the status 127 if no job is running.
+    -p VAR
+        with -n, store the process ID of the job which exited in VAR
+    -t SECONDS
+        give up after SECONDS, which may be fractional, and return the status 124
+
+EXAMPLES
+    wait %1 %2
+    wait -n -p pid
+    echo process $pid exited with status $?
+    wait -t 0.5 %server"
 )]
 pub fn wait(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
-    if let Err(err) = shell.wait_for_background() {
-        Status::error(err.to_string())
-    } else {
-        Status::SUCCESS
-    }
+    job_control::wait(shell, &args[1..])
 }
 
 #[builtin(
     desc = "list all jobs running in the background",
     man = "
 SYNOPSIS
-    jobs
+    jobs [-l | -p | -j]
 
 DESCRIPTION
-    Prints a list of all jobs running in the background."
+    Prints a list of all jobs running in the background, with their number, process group ID,
+    state and command.
+
+OPTIONS
+    -l  Lists the IDs of all the processes of each job, rather than its process group ID.
+    -p  Prints the process group ID of each job to stdout, one per line.
+    -j  Prints the jobs to stdout as a JSON array of objects, with the numeric fields id,
+        pgid, pids and started, which is the time the job was started at, in seconds since the
+        Unix epoch, and the string fields state, command and cwd."
 )]
 pub fn jobs(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
-    job_control::jobs(shell);
-    Status::SUCCESS
+    job_control::jobs(shell, &args[1..])
 }
 
 #[builtin(
     desc = "sends jobs to background",
     man = "
 SYNOPSIS
-    bg PID
+    bg [JOB...]
 
 DESCRIPTION
-    bg sends the job to the background resuming it if it has stopped."
+    bg sends the job to the background resuming it if it has stopped. Without a JOB, the
+    current job is resumed. A JOB is a job spec, as accepted by kill, or the number of a job."
 )]
 pub fn bg(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
     job_control::bg(shell, &args[1..])
@@ -772,10 +814,12 @@ pub fn bg(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
     desc = "bring job to the foreground",
     man = "
 SYNOPSIS
-    fg PID
+    fg [JOB...]
 
 DESCRIPTION
-    fg brings the specified job to foreground resuming it if it has stopped."
+    fg brings the specified job to foreground resuming it if it has stopped. Without a JOB, the
+    current job is brought to the foreground. A JOB is a job spec, as accepted by kill, or the
+    number of a job."
 )]
 pub fn fg(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
     job_control::fg(shell, &args[1..])
@@ -785,7 +829,7 @@ pub fn fg(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
     desc = "disown processes",
     man = "
 SYNOPSIS
-    disown [ --help | -r | -h | -a ][PID...]
+    disown [ --help | -r | -h | -a ][JOB...]
 
 DESCRIPTION
     Disowning a process removes that process from the shell's background process table.
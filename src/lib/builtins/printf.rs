@@ -0,0 +1,424 @@
+use super::{echo::escaped, Status};
+use crate as ion_shell;
+use crate::{shell::variables::Variables, types, Shell};
+use builtins_proc::builtin;
+use std::io::{self, Write};
+
+#[builtin(
+    desc = "format and print data",
+    man = "
+SYNOPSIS
+    printf [ -h | --help ] [-v VARIABLE] FORMAT [ARGUMENT]...
+
+DESCRIPTION
+    Print the ARGUMENT(s) to standard output according to FORMAT. The FORMAT is reused as
+    many times as needed to consume all of the arguments. Missing arguments are treated as
+    empty strings, or zeroes for numeric directives.
+
+OPTIONS
+    -v VARIABLE
+        assign the output to the VARIABLE instead of printing it
+
+    Directives
+        %%  a single %
+        %s  the argument as a string
+        %b  the argument as a string, with its escape sequences interpreted
+        %c  the first character of the argument
+        %d  the argument as a signed decimal integer (also %i)
+        %u  the argument as an unsigned decimal integer
+        %o  the argument as an unsigned octal integer
+        %x  the argument as an unsigned hexadecimal integer (%X for uppercase)
+        %f  the argument as a floating-point number
+        %e  the argument in scientific notation (%E for uppercase)
+
+        Each directive may be preceded by flags, a minimum field width and a precision:
+        -  left-justify the field
+        0  pad numbers with zeroes instead of spaces
+        +  print the sign of positive numbers
+        ' ' prefix positive numbers with a space
+
+        The precision, written as .N, is the number of decimals of floating-point numbers,
+        the minimum number of digits of integers and the maximum length of strings.
+
+    Escape Sequences
+        The FORMAT accepts the same escape sequences as echo -e, such as \\n and \\t.
+
+EXAMPLES
+    printf '%-10s|%05d\\n' name 42
+    printf -v hex '%x' 255"
+)]
+pub fn printf(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
+    let (variable, args) = match args.get(1).map(|arg| &**arg) {
+        Some("-v") => match args.get(2) {
+            Some(name) if Variables::is_valid_name(name) => (Some(name), &args[3..]),
+            Some(name) => {
+                return Status::error(format!(
+                    "ion: printf: '{}' is not a valid variable name",
+                    name
+                ))
+            }
+            None => return Status::bad_argument("ion: printf: -v requires a variable name"),
+        },
+        Some("--") => (None, &args[2..]),
+        _ => (None, &args[1..]),
+    };
+    let (format, args) = match args.split_first() {
+        Some(split) => split,
+        None => {
+            return Status::bad_argument(
+                "ion: printf: usage: printf [-v VARIABLE] FORMAT [ARGUMENT]...",
+            )
+        }
+    };
+
+    let mut formatter = Formatter::new(args);
+    let result = formatter.format(format.as_bytes());
+    for error in &formatter.errors {
+        eprintln!("ion: printf: {}", error);
+    }
+
+    let written = match variable {
+        Some(name) => {
+            let value = String::from_utf8_lossy(&formatter.output).into_owned();
+            shell.variables_mut().set(name, types::Str::from(value));
+            Ok(())
+        }
+        None => {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            stdout.write_all(&formatter.output).and_then(|_| stdout.flush())
+        }
+    };
+
+    if let Err(why) = result {
+        Status::error(format!("ion: printf: {}", why))
+    } else if let Err(why) = written {
+        Status::error(format!("ion: printf: {}", why))
+    } else if formatter.errors.is_empty() {
+        Status::SUCCESS
+    } else {
+        Status::error("")
+    }
+}
+
+/// The flags, width and precision of a directive
+#[derive(Debug, Default, Clone, Copy)]
+struct Spec {
+    left:      bool,
+    zero:      bool,
+    plus:      bool,
+    space:     bool,
+    width:     usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Write a field made of an optional sign and a body, padded up to the width
+    fn pad(&self, output: &mut Vec<u8>, sign: &str, body: &str, numeric: bool) {
+        let len = sign.len() + body.chars().count();
+        let fill = self.width.saturating_sub(len);
+        if self.left {
+            output.extend_from_slice(sign.as_bytes());
+            output.extend_from_slice(body.as_bytes());
+            output.extend(std::iter::repeat(b' ').take(fill));
+        } else if self.zero && numeric {
+            output.extend_from_slice(sign.as_bytes());
+            output.extend(std::iter::repeat(b'0').take(fill));
+            output.extend_from_slice(body.as_bytes());
+        } else {
+            output.extend(std::iter::repeat(b' ').take(fill));
+            output.extend_from_slice(sign.as_bytes());
+            output.extend_from_slice(body.as_bytes());
+        }
+    }
+
+    /// The sign to print in front of a number
+    const fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    /// Pad the digits of an integer with zeroes up to the precision
+    fn digits(&self, digits: String) -> String {
+        match self.precision {
+            Some(precision) if precision > digits.len() => {
+                format!("{}{}", "0".repeat(precision - digits.len()), digits)
+            }
+            _ => digits,
+        }
+    }
+}
+
+/// Formats the arguments of `printf`, collecting the output and the invalid arguments
+struct Formatter<'a> {
+    args:   &'a [types::Str],
+    read:   usize,
+    output: Vec<u8>,
+    errors: Vec<String>,
+    stop:   bool,
+}
+
+impl<'a> Formatter<'a> {
+    const fn new(args: &'a [types::Str]) -> Self {
+        Self { args, read: 0, output: Vec::new(), errors: Vec::new(), stop: false }
+    }
+
+    /// Apply the format until all of the arguments have been consumed
+    fn format(&mut self, format: &[u8]) -> Result<(), String> {
+        loop {
+            let start = self.read;
+            self.format_once(format)?;
+            if self.stop || self.read == start || self.read >= self.args.len() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn format_once(&mut self, format: &[u8]) -> Result<(), String> {
+        let mut bytes = format.iter().copied().enumerate().peekable();
+        while let Some((start, byte)) = bytes.next() {
+            match byte {
+                b'\\' => match bytes.next() {
+                    Some((_, b'c')) => {
+                        self.stop = true;
+                        return Ok(());
+                    }
+                    Some((_, byte)) => match escaped(byte) {
+                        Some(byte) => self.output.push(byte),
+                        None => self.output.extend_from_slice(&[b'\\', byte]),
+                    },
+                    None => self.output.push(b'\\'),
+                },
+                b'%' => {
+                    let mut spec = Spec::default();
+                    while let Some(&(_, flag)) = bytes.peek() {
+                        match flag {
+                            b'-' => spec.left = true,
+                            b'0' => spec.zero = true,
+                            b'+' => spec.plus = true,
+                            b' ' => spec.space = true,
+                            _ => break,
+                        }
+                        bytes.next();
+                    }
+                    while let Some(&(_, digit)) = bytes.peek().filter(|(_, c)| c.is_ascii_digit()) {
+                        spec.width = spec.width * 10 + usize::from(digit - b'0');
+                        bytes.next();
+                    }
+                    if bytes.peek().map_or(false, |&(_, c)| c == b'.') {
+                        bytes.next();
+                        let mut precision = 0;
+                        while let Some(&(_, digit)) =
+                            bytes.peek().filter(|(_, c)| c.is_ascii_digit())
+                        {
+                            precision = precision * 10 + usize::from(digit - b'0');
+                            bytes.next();
+                        }
+                        spec.precision = Some(precision);
+                    }
+
+                    match bytes.next() {
+                        Some((_, b'%')) => self.output.push(b'%'),
+                        Some((_, conversion)) => {
+                            self.directive(conversion, spec).map_err(|_| {
+                                let end = bytes.peek().map_or(format.len(), |&(end, _)| end);
+                                format!(
+                                    "{}: invalid directive",
+                                    String::from_utf8_lossy(&format[start..end])
+                                )
+                            })?
+                        }
+                        None => return Err("missing directive at the end of the format".into()),
+                    }
+                    if self.stop {
+                        return Ok(());
+                    }
+                }
+                _ => self.output.push(byte),
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the next argument as described by the conversion, or fail if it is unknown
+    fn directive(&mut self, conversion: u8, spec: Spec) -> Result<(), ()> {
+        match conversion {
+            b's' => {
+                let arg = self.next_arg();
+                match spec.precision {
+                    Some(precision) => {
+                        let end = arg.char_indices().nth(precision).map_or(arg.len(), |(i, _)| i);
+                        spec.pad(&mut self.output, "", &arg[..end], false);
+                    }
+                    None => spec.pad(&mut self.output, "", arg, false),
+                }
+            }
+            b'b' => {
+                let arg = self.next_arg().as_bytes();
+                let mut body = Vec::with_capacity(arg.len());
+                let mut bytes = arg.iter().copied();
+                while let Some(byte) = bytes.next() {
+                    if byte != b'\\' {
+                        body.push(byte);
+                        continue;
+                    }
+                    match bytes.next() {
+                        Some(b'c') => {
+                            self.stop = true;
+                            break;
+                        }
+                        Some(byte) => match escaped(byte) {
+                            Some(byte) => body.push(byte),
+                            None => body.extend_from_slice(&[b'\\', byte]),
+                        },
+                        None => body.push(b'\\'),
+                    }
+                }
+                spec.pad(&mut self.output, "", &String::from_utf8_lossy(&body), false);
+            }
+            b'c' => {
+                let arg = self.next_arg();
+                let end = arg.chars().next().map_or(0, char::len_utf8);
+                spec.pad(&mut self.output, "", &arg[..end], false);
+            }
+            b'd' | b'i' => {
+                let number = self.next_integer();
+                let digits = spec.digits(number.to_string().trim_start_matches('-').into());
+                spec.pad(&mut self.output, spec.sign(number < 0), &digits, true);
+            }
+            b'u' | b'o' | b'x' | b'X' => {
+                let number = self.next_integer() as u64;
+                let digits = match conversion {
+                    b'u' => number.to_string(),
+                    b'o' => format!("{:o}", number),
+                    b'x' => format!("{:x}", number),
+                    _ => format!("{:X}", number),
+                };
+                spec.pad(&mut self.output, "", &spec.digits(digits), true);
+            }
+            b'f' | b'F' | b'e' | b'E' => {
+                let number = self.next_float();
+                let precision = spec.precision.unwrap_or(6);
+                let body = match conversion {
+                    _ if !number.is_finite() => number.abs().to_string(),
+                    b'f' | b'F' => format!("{:.*}", precision, number.abs()),
+                    _ => {
+                        let body = format!("{:.*e}", precision, number.abs());
+                        let (mantissa, exponent) = body.split_at(body.find('e').unwrap_or(0));
+                        let exponent: i32 = exponent[1..].parse().unwrap_or(0);
+                        format!("{}e{:+03}", mantissa, exponent)
+                    }
+                };
+                let body = if conversion.is_ascii_uppercase() { body.to_uppercase() } else { body };
+                let sign = spec.sign(number.is_sign_negative() && !number.is_nan());
+                spec.pad(&mut self.output, sign, &body, number.is_finite());
+            }
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+
+    fn next_arg(&mut self) -> &'a str {
+        let arg = self.args.get(self.read).map_or("", |arg| arg.as_str());
+        self.read += 1;
+        arg
+    }
+
+    /// Read the next argument as an integer, which may be written in hexadecimal with a `0x`
+    /// prefix, or as a quoted character to get its code point
+    fn next_integer(&mut self) -> i64 {
+        let arg = self.next_arg();
+        let trimmed = arg.trim();
+        let (negative, digits) = match trimmed.as_bytes().first() {
+            Some(b'-') => (true, &trimmed[1..]),
+            Some(b'+') => (false, &trimmed[1..]),
+            _ => (false, trimmed),
+        };
+        let number = if digits.starts_with('\'') || digits.starts_with('"') {
+            digits[1..].chars().next().map(|c| i64::from(u32::from(c)))
+        } else if digits.starts_with("0x") || digits.starts_with("0X") {
+            i64::from_str_radix(&digits[2..], 16).ok()
+        } else if trimmed.is_empty() {
+            Some(0)
+        } else {
+            digits.parse::<i64>().ok()
+        };
+        match number {
+            Some(number) if negative => -number,
+            Some(number) => number,
+            None => {
+                self.errors.push(format!("{}: invalid number", arg));
+                0
+            }
+        }
+    }
+
+    fn next_float(&mut self) -> f64 {
+        let arg = self.next_arg();
+        match arg.trim() {
+            "" => 0.0,
+            trimmed => trimmed.parse::<f64>().unwrap_or_else(|_| {
+                self.errors.push(format!("{}: invalid number", arg));
+                0.0
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printf(format: &str, args: &[&str]) -> (String, Vec<String>) {
+        let args: Vec<types::Str> = args.iter().map(|&arg| arg.into()).collect();
+        let mut formatter = Formatter::new(&args);
+        formatter.format(format.as_bytes()).unwrap();
+        (String::from_utf8(formatter.output).unwrap(), formatter.errors)
+    }
+
+    #[test]
+    fn directives() {
+        assert_eq!(
+            printf("%s-%d-%x-%X-%o\\n", &["a", "42", "255", "255", "8"]).0,
+            "a-42-ff-FF-10\n"
+        );
+        assert_eq!(
+            printf("[%-10s][%5s][%.2s]", &["left", "right", "abc"]).0,
+            "[left      ][right][ab]"
+        );
+        assert_eq!(
+            printf("[%05d][%+d][% d][%-4d]", &["-42", "7", "7", "1"]).0,
+            "[-0042][+7][ 7][1   ]"
+        );
+        assert_eq!(printf("%.3d %c %u", &["5", "xyz", "-1"]).0, "005 x 18446744073709551615");
+        assert_eq!(
+            printf("%f %.2f %08.3f", &["1.5", "-2.345", "3.14159"]).0,
+            "1.500000 -2.35 0003.142"
+        );
+        assert_eq!(printf("%e %.1E", &["1500", "0.00025"]).0, "1.500000e+03 2.5E-04");
+        assert_eq!(printf("%d %d %%", &["0x1f", "'A"]).0, "31 65 %");
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(printf("%s=%s;", &["a", "1", "b", "2", "c"]).0, "a=1;b=2;c=;");
+        assert_eq!(printf("%s|%d|%f", &[]).0, "|0|0.000000");
+        assert_eq!(printf("plain", &["ignored"]).0, "plain");
+        assert_eq!(printf("%b|%s", &["a\\tb", "a\\tb"]).0, "a\tb|a\\tb");
+        assert_eq!(printf("%s\\c%s", &["a", "b", "c"]).0, "a");
+        assert_eq!(
+            printf("%d.", &["1", "x", "3"]),
+            ("1.0.3.".into(), vec!["x: invalid number".into()])
+        );
+
+        let args = ["a".into()];
+        assert_eq!(Formatter::new(&args).format(b"%s %y"), Err("%y: invalid directive".into()));
+    }
+}
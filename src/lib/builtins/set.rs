@@ -17,7 +17,7 @@ enum PositionalArgs {
     desc = "Set or unset values of shell options and positional parameters.",
     man = "
 SYNOPSIS
-    set [ --help ] [-e | +e] [- | --] [STRING]...
+    set [ --help ] [-e | +e] [-o OPTION | +o OPTION] [- | --] [STRING]...
 
 DESCRIPTION
     Shell options may be set using the '-' character, and unset using the '+' character.
@@ -25,6 +25,13 @@ DESCRIPTION
 OPTIONS
     -e  Exit immediately if a command exits with a non-zero status.
 
+    -o OPTION
+        Set the shell option named OPTION:
+
+        pipefail
+            The status of a pipeline is the status of its last command which failed, or zero
+            if all of its commands succeeded.
+
     --  Following arguments will be set as positional arguments in the shell.
         If no argument are supplied, arguments will be unset.
 
@@ -52,6 +59,21 @@ pub fn set(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
             }
             "-e" => shell.opts_mut().err_exit = true,
             "+e" => shell.opts_mut().err_exit = false,
+            "-o" | "+o" => {
+                let enable = arg.as_str() == "-o";
+                match args_iter.next().map(types::Str::as_str) {
+                    Some("pipefail") => shell.opts_mut().pipefail = enable,
+                    Some(option) => {
+                        return Status::bad_argument(format!(
+                            "set: '{}' is not a shell option",
+                            option
+                        ))
+                    }
+                    None => {
+                        return Status::bad_argument(format!("set: {} requires an option", arg))
+                    }
+                }
+            }
             _ => {
                 return Status::bad_argument(format!(
                     "set: argument '{}' is not recognized. Try adding `--` before it to pass it \
@@ -17,8 +17,8 @@ DESCRIPTION
 pub fn source(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
     match args.get(1) {
         Some(argument) => {
-            if let Ok(file) = File::open(argument.as_str()) {
-                if let Err(why) = shell.execute_command(file) {
+            if let Ok(file) = File::open(shell.variables().env().path(argument.as_str())) {
+                if let Err(why) = shell.execute_script(argument.as_str(), file) {
                     Status::error(format!("ion: {}", why))
                 } else {
                     Status::SUCCESS
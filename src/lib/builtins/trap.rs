@@ -0,0 +1,94 @@
+use super::Status;
+use crate as ion_shell;
+use crate::{
+    shell::{Shell, Trap},
+    types,
+};
+use builtins_proc::builtin;
+
+#[builtin(
+    desc = "run commands when the shell receives a signal",
+    man = "
+SYNOPSIS
+    trap [ -h | --help ] [-l] [-p [SIGNAL...]] [HANDLER SIGNAL...] [- SIGNAL...]
+
+DESCRIPTION
+    Run HANDLER whenever the shell receives one of the given SIGNALs. HANDLER may be the name of
+    a function or any command string, and is executed in the current shell.
+
+    A trapped signal no longer aborts the shell: execution resumes after the handler has run. If
+    HANDLER is the empty string, the signal is ignored. If HANDLER is '-', the default behavior
+    of the signal is restored.
+
+    With no arguments, the registered handlers are printed.
+
+SIGNALS
+    Signals may be given by name, with or without the SIG prefix, or by number. Only HUP, INT
+    and TERM can be trapped. The following pseudo-signals are also supported:
+
+    EXIT (or 0)
+        run when the shell exits
+    ERR
+        run every time a pipeline returns a non-zero exit status
+
+OPTIONS
+    -l
+        list the names of the signals that can be trapped
+    -p
+        print the handlers bound to the given signals, or all the handlers
+
+EXAMPLES
+    trap 'rm -f $lockfile' EXIT
+    trap cleanup INT TERM
+    trap '' HUP"
+)]
+pub fn trap(args: &[types::Str], shell: &mut Shell<'_>) -> Status {
+    match args.get(1).map(types::Str::as_str) {
+        None => print_traps(shell, &[]),
+        Some("-l") => {
+            for trap in Trap::all() {
+                println!("{}", trap);
+            }
+            Status::SUCCESS
+        }
+        Some("-p") => print_traps(shell, &args[2..]),
+        Some(handler) => {
+            let traps = match parse_traps(&args[2..]) {
+                Ok(traps) if traps.is_empty() => {
+                    return Status::bad_argument("ion: trap: no signal specified")
+                }
+                Ok(traps) => traps,
+                Err(why) => return Status::bad_argument(why),
+            };
+
+            for trap in traps {
+                if handler == "-" {
+                    shell.traps_mut().reset(trap);
+                } else {
+                    shell.traps_mut().set(trap, handler.into());
+                }
+            }
+            Status::SUCCESS
+        }
+    }
+}
+
+fn parse_traps(args: &[types::Str]) -> Result<Vec<Trap>, String> {
+    args.iter()
+        .map(|arg| arg.parse::<Trap>().map_err(|why| format!("ion: trap: {}", why)))
+        .collect()
+}
+
+fn print_traps(shell: &Shell<'_>, args: &[types::Str]) -> Status {
+    let filter = match parse_traps(args) {
+        Ok(filter) => filter,
+        Err(why) => return Status::bad_argument(why),
+    };
+
+    for (trap, handler) in shell.traps().iter() {
+        if filter.is_empty() || filter.contains(&trap) {
+            println!("trap -- '{}' {}", handler, trap);
+        }
+    }
+    Status::SUCCESS
+}
@@ -1,10 +1,18 @@
 use super::{
     super::{is_expression, words::Select, Error, Expander, ExpanderInternal, Index},
+    compile_regex,
     strings::unescape,
     MethodError, Pattern,
 };
-use crate::types::{self, Args};
-use std::char;
+use crate::{
+    shell::Value,
+    types::{self, Args},
+};
+use std::{
+    char,
+    cmp::Ordering,
+    collections::{BTreeMap, HashSet},
+};
 use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Debug, PartialEq, Clone)]
@@ -55,6 +63,18 @@ impl<'a> ArrayMethod<'a> {
         expand_func.slice_array(expand_func.map_values(self.variable)?.into_iter(), &self.selection)
     }
 
+    fn from_json<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let data: Args = match self.json::<E, ()>(expand_func)? {
+            Value::Array(array) => array.iter().map(|value| value.to_string().into()).collect(),
+            Value::BTreeMap(map) => map
+                .iter()
+                .flat_map(|(key, value)| vec![key.clone(), value.to_string().into()])
+                .collect(),
+            value => args![value.to_string()],
+        };
+        expand_func.slice_array(data.into_iter(), &self.selection)
+    }
+
     fn graphemes<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
         let variable = self.resolve_var(expand_func)?;
         let graphemes = UnicodeSegmentation::graphemes(variable.as_str(), true);
@@ -105,6 +125,272 @@ impl<'a> ArrayMethod<'a> {
         }
     }
 
+    /// The capture groups of the first match of the regular expression, the groups which did not
+    /// participate in the match being empty
+    fn regex_captures<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let (regex, input) = self.regex("regex_captures", expand_func)?;
+        let result = regex.captures(&input).into_iter().flat_map(|captures| {
+            captures
+                .iter()
+                .skip(1)
+                .map(|group| types::Str::from(group.map_or("", |group| group.as_str())))
+                .collect::<Vec<_>>()
+        });
+        expand_func.slice_array(result, &self.selection)
+    }
+
+    /// The name, or the index, of each capture group of the first match of the regular
+    /// expression, followed by the group
+    fn regex_named<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let result = self
+            .named_captures::<E, ()>(expand_func)?
+            .into_iter()
+            .flat_map(|(name, group)| vec![name, group.to_string().into()]);
+        expand_func.slice_array(result, &self.selection)
+    }
+
+    /// The capture groups of the first match of the regular expression, by their name, or by
+    /// their index for the unnamed ones
+    fn named_captures<E: Expander, T>(
+        &self,
+        expand_func: &mut E,
+    ) -> Result<types::BTreeMap<T>, Error<E::Error>> {
+        let (regex, input) = self.regex("regex_named", expand_func)?;
+        let mut groups = types::BTreeMap::new();
+        if let Some(captures) = regex.captures(&input) {
+            for (id, name) in regex.capture_names().enumerate().skip(1) {
+                let key = name.map_or_else(|| id.to_string(), ToString::to_string);
+                let value = captures.get(id).map_or("", |group| group.as_str());
+                groups.insert(key.into(), Value::Str(value.into()));
+            }
+        }
+        Ok(groups)
+    }
+
+    /// The regular expression given to a method, along with the string that it is applied to
+    fn regex<E: Expander>(
+        &self,
+        method: &'static str,
+        expand_func: &mut E,
+    ) -> Result<(regex::Regex, types::Str), Error<E::Error>> {
+        let pattern = match self.pattern {
+            Pattern::StringPattern(pattern) => expand_func.expand_string(pattern)?.join(" "),
+            Pattern::Whitespace => {
+                return Err(
+                    MethodError::WrongArgument(method, "requires a regular expression").into()
+                )
+            }
+        };
+        let regex = compile_regex(method, &pattern, false)?;
+        Ok((regex, self.resolve_var(expand_func)?))
+    }
+
+    fn sort<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let mut result = self.resolve_array(expand_func)?;
+        result.sort();
+        expand_func.slice_array(result.into_iter(), &self.selection)
+    }
+
+    fn sort_numeric<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let mut keyed = Vec::new();
+        for element in self.resolve_array(expand_func)? {
+            match element.parse::<f64>() {
+                Ok(number) => keyed.push((number, element)),
+                Err(_) => {
+                    return Err(MethodError::WrongArgument(
+                        "sort_numeric",
+                        "requires an array of numbers",
+                    )
+                    .into())
+                }
+            }
+        }
+        keyed.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        expand_func.slice_array(keyed.into_iter().map(|(_, element)| element), &self.selection)
+    }
+
+    /// Removes the duplicated elements, keeping the first occurrence of each one
+    fn uniq<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let mut seen = HashSet::new();
+        let result = self
+            .resolve_array(expand_func)?
+            .into_iter()
+            .filter(|element| seen.insert(element.clone()));
+        expand_func.slice_array(result, &self.selection)
+    }
+
+    /// Interleaves the elements of the two arrays, stopping at the end of the shortest one
+    fn zip<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let other = self.other_array("zip", expand_func)?;
+        let result = self
+            .resolve_array(expand_func)?
+            .into_iter()
+            .zip(other)
+            .flat_map(|(first, second)| vec![first, second]);
+        expand_func.slice_array(result, &self.selection)
+    }
+
+    fn enumerate<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let result = self
+            .resolve_array(expand_func)?
+            .into_iter()
+            .enumerate()
+            .flat_map(|(id, element)| vec![types::Str::from(id.to_string()), element]);
+        expand_func.slice_array(result, &self.selection)
+    }
+
+    /// Returns the values nested at any depth within the array
+    fn flatten<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let name = if self.variable.starts_with('@') { &self.variable[1..] } else { self.variable };
+        let result = match expand_func.flatten(name) {
+            Ok(result) => result,
+            Err(Error::VarNotFound) if is_expression(self.variable) => {
+                expand_func.expand_string(self.variable)?
+            }
+            Err(why) => return Err(why),
+        };
+        expand_func.slice_array(result.into_iter(), &self.selection)
+    }
+
+    fn take<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let count = self.count("take", expand_func)?;
+        let result = self.resolve_array(expand_func)?.into_iter().take(count);
+        expand_func.slice_array(result, &self.selection)
+    }
+
+    fn skip<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let count = self.count("skip", expand_func)?;
+        let result = self.resolve_array(expand_func)?.into_iter().skip(count);
+        expand_func.slice_array(result, &self.selection)
+    }
+
+    /// The elements of either array, without duplicates
+    fn union<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let other = self.other_array("union", expand_func)?;
+        let mut seen = HashSet::new();
+        let result = self
+            .resolve_array(expand_func)?
+            .into_iter()
+            .chain(other)
+            .filter(|element| seen.insert(element.clone()));
+        expand_func.slice_array(result, &self.selection)
+    }
+
+    /// The elements of the first array which are, or are not, in the second one, without
+    /// duplicates
+    fn intersection<E: Expander>(
+        &self,
+        expand_func: &mut E,
+        method: &'static str,
+        keep: bool,
+    ) -> Result<Args, Error<E::Error>> {
+        let other: HashSet<_> = self.other_array(method, expand_func)?.into_iter().collect();
+        let mut seen = HashSet::new();
+        let result = self
+            .resolve_array(expand_func)?
+            .into_iter()
+            .filter(|element| other.contains(element) == keep && seen.insert(element.clone()));
+        expand_func.slice_array(result, &self.selection)
+    }
+
+    /// The array given as the argument of a method
+    fn other_array<E: Expander>(
+        &self,
+        method: &'static str,
+        expand_func: &mut E,
+    ) -> Result<Args, Error<E::Error>> {
+        match self.pattern {
+            Pattern::StringPattern(array) => expand_func.expand_string(array),
+            Pattern::Whitespace => {
+                Err(MethodError::WrongArgument(method, "requires an array argument").into())
+            }
+        }
+    }
+
+    /// The number of elements given as the argument of a method
+    fn count<E: Expander>(
+        &self,
+        method: &'static str,
+        expand_func: &mut E,
+    ) -> Result<usize, Error<E::Error>> {
+        let count = match self.pattern {
+            Pattern::StringPattern(count) => expand_func.expand_string(count)?.join(" "),
+            Pattern::Whitespace => {
+                return Err(MethodError::WrongArgument(method, "requires an argument").into())
+            }
+        };
+        count.parse::<usize>().map_err(|_| {
+            MethodError::WrongArgument(method, "requires a valid number as an argument").into()
+        })
+    }
+
+    /// The function, or the inline expression, applied by a higher-order method
+    fn function(&self, method: &'static str) -> Result<&'a str, MethodError> {
+        match self.pattern {
+            Pattern::StringPattern(function) => Ok(function),
+            Pattern::Whitespace => Err(MethodError::WrongArgument(method, "requires a function")),
+        }
+    }
+
+    fn map<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let function = self.function("map")?;
+        let mut result = Args::new();
+        for element in self.resolve_array(expand_func)? {
+            result.push(expand_func.apply(function, &element)?.1);
+        }
+        expand_func.slice_array(result.into_iter(), &self.selection)
+    }
+
+    fn filter<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let function = self.function("filter")?;
+        let mut result = Args::new();
+        for element in self.resolve_array(expand_func)? {
+            if expand_func.apply(function, &element)?.0 {
+                result.push(element);
+            }
+        }
+        expand_func.slice_array(result.into_iter(), &self.selection)
+    }
+
+    /// Sorts the elements by the key that the function gives them. The sort is stable, and keys
+    /// that are both numbers are compared as such.
+    fn sort_by<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let function = self.function("sort_by")?;
+        let array = self.resolve_array(expand_func)?;
+        let mut keyed = Vec::with_capacity(array.len());
+        for element in array {
+            keyed.push((expand_func.apply(function, &element)?.1, element));
+        }
+        keyed.sort_by(|(a, _), (b, _)| match (a.parse::<f64>(), b.parse::<f64>()) {
+            (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            _ => a.cmp(b),
+        });
+        expand_func.slice_array(keyed.into_iter().map(|(_, element)| element), &self.selection)
+    }
+
+    fn group_by<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
+        let data: Args = self
+            .groups::<E, ()>(expand_func)?
+            .iter()
+            .flat_map(|(key, value)| vec![key.clone(), value.to_string().into()])
+            .collect();
+        expand_func.slice_array(data.into_iter(), &self.selection)
+    }
+
+    /// Groups the elements into arrays, which are mapped to the key that the function gives them
+    fn groups<E: Expander, T>(
+        &self,
+        expand_func: &mut E,
+    ) -> Result<types::BTreeMap<T>, Error<E::Error>> {
+        let function = self.function("group_by")?;
+        let mut groups = BTreeMap::<types::Str, types::Array<T>>::new();
+        for element in self.resolve_array(expand_func)? {
+            let key = expand_func.apply(function, &element)?.1;
+            groups.entry(key).or_insert_with(Vec::new).push(Value::Str(element));
+        }
+        Ok(groups.into_iter().map(|(key, group)| (key, Value::Array(group))).collect())
+    }
+
     #[inline]
     fn resolve_array<E: Expander>(&self, expand_func: &mut E) -> Result<Args, Error<E::Error>> {
         match expand_func.array(self.variable, &Select::All) {
@@ -127,6 +413,28 @@ impl<'a> ArrayMethod<'a> {
         }
     }
 
+    /// Read the JSON document given to the method, keeping the structure of its values
+    fn json<E: Expander, T>(&self, expand_func: &mut E) -> Result<Value<T>, Error<E::Error>> {
+        let document = self.resolve_var(expand_func)?;
+        Value::from_json(&document).map_err(|why| MethodError::InvalidJson(why).into())
+    }
+
+    /// Whether the method has a result which keeps the structure of its values, that `value`
+    /// returns
+    pub fn is_structured(&self) -> bool {
+        matches!(self.method, "from_json" | "group_by" | "regex_named")
+    }
+
+    /// The result of a method that keeps the structure of its values
+    pub fn value<E: Expander, T>(&self, expand_func: &mut E) -> Result<Value<T>, Error<E::Error>> {
+        match self.method {
+            "from_json" => self.json(expand_func),
+            "group_by" => self.groups(expand_func).map(Value::BTreeMap),
+            "regex_named" => self.named_captures(expand_func).map(Value::BTreeMap),
+            _ => Err(MethodError::InvalidArrayMethod(self.method.to_string()).into()),
+        }
+    }
+
     pub fn handle_as_array<E: Expander>(
         &self,
         expand_func: &mut E,
@@ -134,13 +442,31 @@ impl<'a> ArrayMethod<'a> {
         match self.method {
             "bytes" => self.bytes(expand_func),
             "chars" => self.chars(expand_func),
+            "difference" => self.intersection(expand_func, "difference", false),
+            "enumerate" => self.enumerate(expand_func),
+            "filter" => self.filter(expand_func),
+            "flatten" => self.flatten(expand_func),
+            "from_json" => self.from_json(expand_func),
             "graphemes" => self.graphemes(expand_func),
+            "group_by" => self.group_by(expand_func),
+            "intersection" => self.intersection(expand_func, "intersection", true),
             "keys" => self.map_keys(expand_func).map_err(Error::from),
             "lines" => self.lines(expand_func),
+            "map" => self.map(expand_func),
+            "regex_captures" => self.regex_captures(expand_func),
+            "regex_named" => self.regex_named(expand_func),
             "reverse" => self.reverse(expand_func),
+            "skip" => self.skip(expand_func),
+            "sort" => self.sort(expand_func),
+            "sort_by" => self.sort_by(expand_func),
+            "sort_numeric" => self.sort_numeric(expand_func),
             "split_at" => self.split_at(expand_func),
             "split" => self.split(expand_func),
+            "take" => self.take(expand_func),
+            "union" => self.union(expand_func),
+            "uniq" => self.uniq(expand_func),
             "values" => self.map_values(expand_func).map_err(Error::from),
+            "zip" => self.zip(expand_func),
             _ => Err(MethodError::InvalidArrayMethod(self.method.to_string()).into()),
         }
     }
@@ -300,4 +626,124 @@ mod test {
         let method = ArrayMethod::new("reverse", "@ARRAY", Pattern::StringPattern("3"), None);
         assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["c", "b", "a"]);
     }
+
+    #[test]
+    fn test_from_json() {
+        let document = r#"'{"b": [1, 2], "a": "x y"}'"#;
+        let method = ArrayMethod::new("from_json", document, Pattern::Whitespace, None);
+        assert_eq!(
+            method.handle_as_array(&mut DummyExpander).unwrap(),
+            args!["a", "x y", "b", "1 2"]
+        );
+        let method = ArrayMethod::new("from_json", "'[1, 2'", Pattern::Whitespace, None);
+        assert!(method.handle_as_array(&mut DummyExpander).is_err());
+    }
+
+    #[test]
+    fn test_map() {
+        let method = ArrayMethod::new("map", "[bb a]", Pattern::StringPattern("upper"), None);
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["BB", "A"]);
+        let method = ArrayMethod::new("map", "[bb a]", Pattern::Whitespace, None);
+        assert!(method.handle_as_array(&mut DummyExpander).is_err());
+    }
+
+    #[test]
+    fn test_filter() {
+        let array = "[apple kiwi orange]";
+        let method = ArrayMethod::new("filter", array, Pattern::StringPattern("vowel"), None);
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["apple", "orange"]);
+    }
+
+    #[test]
+    fn test_sort_by() {
+        let array = "[ccc aaaaaaaaaa bb dd a]";
+        let method = ArrayMethod::new("sort_by", array, Pattern::StringPattern("len"), None);
+        assert_eq!(
+            method.handle_as_array(&mut DummyExpander).unwrap(),
+            args!["a", "bb", "dd", "ccc", "aaaaaaaaaa"]
+        );
+    }
+
+    #[test]
+    fn test_group_by() {
+        let method = ArrayMethod::new("group_by", "[a bb c]", Pattern::StringPattern("len"), None);
+        assert_eq!(
+            method.handle_as_array(&mut DummyExpander).unwrap(),
+            args!["1", "a c", "2", "bb"]
+        );
+        let mut groups = types::BTreeMap::new();
+        groups
+            .insert("1".into(), Value::Array(vec![Value::Str("a".into()), Value::Str("c".into())]));
+        groups.insert("2".into(), Value::Array(vec![Value::Str("bb".into())]));
+        assert_eq!(method.value::<_, ()>(&mut DummyExpander).unwrap(), Value::BTreeMap(groups));
+    }
+
+    #[test]
+    fn test_sort() {
+        let method = ArrayMethod::new("sort", "[b 10 a 9]", Pattern::Whitespace, None);
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["10", "9", "a", "b"]);
+        let method = ArrayMethod::new("sort_numeric", "[10 -1 9.5]", Pattern::Whitespace, None);
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["-1", "9.5", "10"]);
+        let method = ArrayMethod::new("sort_numeric", "[10 a]", Pattern::Whitespace, None);
+        assert!(method.handle_as_array(&mut DummyExpander).is_err());
+    }
+
+    #[test]
+    fn test_uniq() {
+        let method = ArrayMethod::new("uniq", "[b a b c a]", Pattern::Whitespace, Some("1.."));
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["a", "c"]);
+    }
+
+    #[test]
+    fn test_zip_enumerate() {
+        let method = ArrayMethod::new("zip", "@ARRAY", Pattern::StringPattern("[1 2]"), None);
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["a", "1", "b", "2"]);
+        let method = ArrayMethod::new("zip", "@ARRAY", Pattern::Whitespace, None);
+        assert!(method.handle_as_array(&mut DummyExpander).is_err());
+        let method = ArrayMethod::new("enumerate", "@ARRAY", Pattern::Whitespace, Some("2.."));
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["1", "b", "2", "c"]);
+    }
+
+    #[test]
+    fn test_take_skip() {
+        let method = ArrayMethod::new("take", "@ARRAY", Pattern::StringPattern("$A"), None);
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["a"]);
+        let method = ArrayMethod::new("skip", "@ARRAY", Pattern::StringPattern("2"), None);
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["c"]);
+        let method = ArrayMethod::new("skip", "@ARRAY", Pattern::StringPattern("x"), None);
+        assert!(method.handle_as_array(&mut DummyExpander).is_err());
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let other = Pattern::StringPattern("[c d b]");
+        let method = ArrayMethod::new("union", "[a b a]", other.clone(), None);
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["a", "b", "c", "d"]);
+        let method = ArrayMethod::new("intersection", "[a b c b]", other.clone(), None);
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["b", "c"]);
+        let method = ArrayMethod::new("difference", "[a b a e]", other, None);
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["a", "e"]);
+    }
+
+    #[test]
+    fn test_regex_captures() {
+        let pattern = Pattern::StringPattern(r"'(\d+)-(?P<month>\d+)(-\d+)?'");
+        let method = ArrayMethod::new("regex_captures", "'on 2024-10'", pattern.clone(), None);
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["2024", "10", ""]);
+        let method = ArrayMethod::new("regex_named", "'on 2024-10'", pattern.clone(), None);
+        assert_eq!(
+            method.handle_as_array(&mut DummyExpander).unwrap(),
+            args!["1", "2024", "3", "", "month", "10"]
+        );
+        let method = ArrayMethod::new("regex_captures", "'nothing'", pattern, None);
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args![]);
+        let method = ArrayMethod::new("regex_captures", "x", Pattern::StringPattern("'('"), None);
+        assert!(method.handle_as_array(&mut DummyExpander).is_err());
+    }
+
+    #[test]
+    fn test_flatten() {
+        let method = ArrayMethod::new("flatten", "[a [b [c]] d]", Pattern::Whitespace, None);
+        assert_eq!(method.handle_as_array(&mut DummyExpander).unwrap(), args!["a", "b", "c", "d"]);
+    }
 }
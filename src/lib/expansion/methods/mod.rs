@@ -6,6 +6,7 @@ pub use self::{arrays::ArrayMethod, strings::StringMethod};
 
 use super::Expander;
 use crate::{parser::lexers::ArgumentSplitter, types};
+use regex::Regex;
 use thiserror::Error;
 
 #[derive(Debug, PartialEq, Clone)]
@@ -35,9 +36,25 @@ pub enum MethodError {
     #[error("{0}: {1}")]
     WrongArgument(&'static str, &'static str),
 
-    /// An invalid regex was provided. This is specific to the `matches` method
-    #[error("regex_replace: error in regular expression '{0}': {1}")]
-    InvalidRegex(String, #[source] regex::Error),
+    /// An invalid regex was provided to a method, or to a case
+    #[error("{0}: error in regular expression '{1}': {2}")]
+    InvalidRegex(&'static str, String, #[source] regex::Error),
+
+    /// The input of `from_json` is not a valid JSON document
+    #[error("from_json: invalid document: {0}")]
+    InvalidJson(#[source] types_rs::JsonError),
+}
+
+/// Compile the regular expression given to `method`. An anchored regular expression only matches
+/// whole strings.
+pub(crate) fn compile_regex(
+    method: &'static str,
+    pattern: &str,
+    anchored: bool,
+) -> Result<Regex, MethodError> {
+    let regex =
+        if anchored { Regex::new(&["^(?:", pattern, ")$"].concat()) } else { Regex::new(pattern) };
+    regex.map_err(|why| MethodError::InvalidRegex(method, pattern.into(), why))
 }
 
 impl<'a, 'b, E: 'b + Expander> MethodArgs<'a, 'b, E> {
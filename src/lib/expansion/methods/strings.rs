@@ -1,10 +1,10 @@
-use super::{super::Select, MethodArgs, MethodError};
+use super::{super::Select, compile_regex, MethodArgs, MethodError};
 use crate::{
     assignments::is_array,
     expansion::{is_expression, Error, Expander, ExpanderInternal, Result},
+    shell::Value,
     types,
 };
-use regex::Regex;
 use std::path::Path;
 use unicode_segmentation::UnicodeSegmentation;
 
@@ -220,12 +220,10 @@ impl<'a> StringMethod<'a> {
                     (args.next(), args.next())
                 };
                 match params {
-                    (Some(replace), Some(with)) => match Regex::new(&replace) {
-                        Ok(re) => output.push_str(&re.replace_all(&get_var!(), &with[..])),
-                        Err(why) => {
-                            return Err(MethodError::InvalidRegex(replace.to_string(), why).into())
-                        }
-                    },
+                    (Some(replace), Some(with)) => {
+                        let re = compile_regex("regex_replace", &replace, false)?;
+                        output.push_str(&re.replace_all(&get_var!(), &with[..]));
+                    }
                     _ => {
                         return Err(MethodError::WrongArgument(
                             "regex_replace",
@@ -246,6 +244,49 @@ impl<'a> StringMethod<'a> {
                     Err(why) => return Err(why),
                 }
             }
+            "any" | "all" => {
+                let all = self.method == "all";
+                if self.pattern.trim().is_empty() {
+                    let method = if all { "all" } else { "any" };
+                    return Err(MethodError::WrongArgument(method, "requires a function").into());
+                }
+                let array = match expand.array(variable, &Select::All) {
+                    Ok(array) => array,
+                    Err(Error::VarNotFound) if is_expression(variable) => {
+                        expand.expand_string(variable)?
+                    }
+                    Err(why) => return Err(why),
+                };
+                // Stops at the first element that decides the result
+                let mut result = all;
+                for element in &array {
+                    if expand.apply(self.pattern, element)?.0 != all {
+                        result = !all;
+                        break;
+                    }
+                }
+                output.push_str(if result { "true" } else { "false" });
+            }
+            "contains" | "index_of" => {
+                if self.pattern.trim().is_empty() {
+                    let method = if self.method == "contains" { "contains" } else { "index_of" };
+                    return Err(MethodError::WrongArgument(method, "requires a value").into());
+                }
+                let value = MethodArgs::new(self.pattern, expand).join(" ")?;
+                let array = match expand.array(variable, &Select::All) {
+                    Ok(array) => array,
+                    Err(Error::VarNotFound) if is_expression(variable) => {
+                        expand.expand_string(variable)?
+                    }
+                    Err(why) => return Err(why),
+                };
+                let position = array.iter().position(|element| *element == value);
+                if self.method == "contains" {
+                    output.push_str(if position.is_some() { "true" } else { "false" });
+                } else {
+                    output.push_str(&position.map_or(-1, |i| i as isize).to_string());
+                }
+            }
             "len" => {
                 if variable.starts_with('@') || is_array(variable) {
                     let expanded = expand.expand_string(variable)?;
@@ -317,6 +358,21 @@ impl<'a> StringMethod<'a> {
                 };
                 output.push_str(&escape(&word));
             }
+            "to_json" => {
+                let json = match expand.to_json(variable) {
+                    Ok(json) => json,
+                    Err(Error::VarNotFound) if is_expression(variable) => {
+                        let value: Value<()> = if variable.starts_with('@') || is_array(variable) {
+                            expand.expand_string(variable)?.into_iter().map(Value::Str).collect()
+                        } else {
+                            Value::Str(expand.expand_string(variable)?.join(" ").into())
+                        };
+                        value.to_json().into()
+                    }
+                    Err(why) => return Err(why),
+                };
+                expand.slice(output, json, &self.selection)?;
+            }
             "or" => {
                 let first_str = match expand.string(variable) {
                     Ok(value) => value,
@@ -738,6 +794,29 @@ mod test {
         assert_eq!(&*output, "RABOOF");
     }
 
+    #[test]
+    fn test_to_json() {
+        let mut output = types::Str::new();
+        let method = StringMethod {
+            method:    "to_json",
+            variable:  "[\"a b\" 1 true]",
+            pattern:   "",
+            selection: None,
+        };
+        method.handle(&mut output, &mut DummyExpander).unwrap();
+        assert_eq!(&*output, r#"["a b",1,true]"#);
+
+        output.clear();
+        let method = StringMethod {
+            method:    "to_json",
+            variable:  "$FOO",
+            pattern:   "",
+            selection: None,
+        };
+        method.handle(&mut output, &mut DummyExpander).unwrap();
+        assert_eq!(&*output, "\"FOOBAR\"");
+    }
+
     #[test]
     fn test_find_succeeding() {
         let mut output = types::Str::new();
@@ -837,4 +916,43 @@ mod test {
         method.handle(&mut output, &mut DummyExpander).unwrap();
         assert_eq!(&*output, "FOOBAR");
     }
+
+    #[test]
+    fn test_any_all() {
+        let method = |method, pattern| StringMethod {
+            method,
+            variable: "[apple kiwi orange]",
+            pattern,
+            selection: None,
+        };
+        let mut output = types::Str::new();
+        method("any", "vowel").handle(&mut output, &mut DummyExpander).unwrap();
+        assert_eq!(&*output, "true");
+        output.clear();
+        method("all", "vowel").handle(&mut output, &mut DummyExpander).unwrap();
+        assert_eq!(&*output, "false");
+        output.clear();
+        method("all", "upper").handle(&mut output, &mut DummyExpander).unwrap();
+        assert_eq!(&*output, "true");
+        assert!(method("any", " ").handle(&mut output, &mut DummyExpander).is_err());
+    }
+
+    #[test]
+    fn test_contains_index_of() {
+        let method =
+            |method, pattern| StringMethod { method, variable: "@ARRAY", pattern, selection: None };
+        let mut output = types::Str::new();
+        method("contains", "b").handle(&mut output, &mut DummyExpander).unwrap();
+        assert_eq!(&*output, "true");
+        output.clear();
+        method("contains", "'a b'").handle(&mut output, &mut DummyExpander).unwrap();
+        assert_eq!(&*output, "false");
+        output.clear();
+        method("index_of", "c").handle(&mut output, &mut DummyExpander).unwrap();
+        assert_eq!(&*output, "2");
+        output.clear();
+        method("index_of", "d").handle(&mut output, &mut DummyExpander).unwrap();
+        assert_eq!(&*output, "-1");
+        assert!(method("index_of", " ").handle(&mut output, &mut DummyExpander).is_err());
+    }
 }
@@ -7,6 +7,7 @@ pub mod pipelines;
 mod words;
 
 use self::braces::BraceToken;
+pub(crate) use self::methods::compile_regex;
 pub use self::{
     loops::ForValueExpression,
     methods::MethodError,
@@ -15,6 +16,7 @@ pub use self::{
 use crate::{
     parser::lexers::assignments::TypeError,
     ranges::{parse_range, Index, Range},
+    shell::variables::Value,
     types::{self, Args},
 };
 use auto_enums::auto_enum;
@@ -23,6 +25,7 @@ use itertools::Itertools;
 use std::{
     error,
     fmt::{self, Write},
+    rc::Rc,
     str,
 };
 use thiserror::Error;
@@ -132,10 +135,52 @@ pub trait Expander: Sized {
         _command: &str,
         _set_cmd_duration: bool,
     ) -> Result<types::Str, Self::Error>;
+    /// Expand a subshell expression into an array.
+    fn command_array(
+        &mut self,
+        command: &str,
+        set_cmd_duration: bool,
+    ) -> Result<Args, Self::Error> {
+        let output = self.command(command, set_cmd_duration)?;
+        Ok(output.split_whitespace().map(Into::into).collect())
+    }
+    /// Expand a subshell expression into a value, which keeps the structure of the value
+    /// returned by the function it calls.
+    fn command_value(&mut self, command: &str) -> Result<Value<Rc<types::Function>>, Self::Error> {
+        Ok(Value::Str(self.command_array(command, true)?.join(" ").into()))
+    }
+    /// Call the function named `function` with `value` as its argument, or evaluate the inline
+    /// expression `function` with `value` bound to `$it`. Returns whether it succeeded, along
+    /// with the value returned by the function, or its output.
+    fn apply(&mut self, function: &str, value: &str) -> Result<(bool, types::Str), Self::Error>;
+    /// Spawn a process substitution, returning the path through which it is reached.
+    fn process_substitution(
+        &mut self,
+        _command: &str,
+        _writable: bool,
+    ) -> Result<types::Str, Self::Error>;
     /// Iterating upon key-value maps.
     fn map_keys(&self, _name: &str) -> Result<Args, Self::Error>;
     /// Iterating upon key-value maps.
     fn map_values(&self, _name: &str) -> Result<Args, Self::Error>;
+    /// Expand a value nested within an array or map variable, which is reached by following
+    /// the selections of `path`, with some selection.
+    fn nested(
+        &self,
+        name: &str,
+        path: &[Select<types::Str>],
+        selection: &Select<types::Str>,
+    ) -> Result<Args, Self::Error> {
+        if path.is_empty() {
+            self.array(name, selection)
+        } else {
+            Err(Error::VarNotFound)
+        }
+    }
+    /// Expand an array or map variable into the values nested within it, at any depth.
+    fn flatten(&self, _name: &str) -> Result<Args, Self::Error> { Err(Error::VarNotFound) }
+    /// Serialize a variable, along with the values nested within it, to JSON.
+    fn to_json(&self, _name: &str) -> Result<types::Str, Self::Error> { Err(Error::VarNotFound) }
     /// Get a string that exists in the shell.
     fn get_string(&mut self, value: &str) -> Result<types::Str, Self::Error> {
         Ok(self.expand_string(value)?.join(" ").into())
@@ -271,6 +316,20 @@ trait ExpanderInternal: Expander {
         }
     }
 
+    /// Expand the selection of an array or map variable, where each selection that follows the
+    /// first one (`[a][b][c]`) reaches into the value nested at the previous one
+    fn select_nested(&mut self, name: &str, index: &str) -> Result<Args, Self::Error> {
+        let mut path = Vec::new();
+        for index in index.split("][") {
+            let value = self.expand_string(index)?.join(" ");
+            path.push(
+                value.parse::<Select<types::Str>>().map_err(|_| Error::IndexParsingError(value))?,
+            );
+        }
+        let selection = path.pop().unwrap_or(Select::All);
+        self.nested(name, &path, &selection)
+    }
+
     fn slice_array<'a, S: Into<types::Str>, T: Iterator<Item = S>>(
         &mut self,
         expanded: T,
@@ -330,6 +389,22 @@ trait ExpanderInternal: Expander {
         Ok(())
     }
 
+    /// Expand a string variable, or the value selected within an array or map variable
+    fn variable(
+        &mut self,
+        output: &mut types::Str,
+        name: &str,
+        selection: &Option<&str>,
+    ) -> Result<(), Self::Error> {
+        match (self.string(name), selection) {
+            (Err(Error::VarNotFound), Some(index)) => {
+                output.push_str(&self.select_nested(name, index)?.join(" "));
+                Ok(())
+            }
+            (value, _) => self.slice(output, value?, selection),
+        }
+    }
+
     fn expand_string_no_glob(&mut self, original: &str) -> Result<Args, Self::Error> {
         let mut token_buffer = Vec::new();
         let mut contains_brace = false;
@@ -382,15 +457,10 @@ trait ExpanderInternal: Expander {
                 }
             }
             WordToken::ArrayVariable(array, quoted, ref index) => {
-                let index = if let Some(index) = index {
-                    let value = self.expand_string(index)?.join(" ");
-                    value
-                        .parse::<Select<types::Str>>()
-                        .map_err(|_| Error::IndexParsingError(value))?
-                } else {
-                    Select::All
+                let array = match index {
+                    Some(index) => self.select_nested(array, index)?,
+                    None => self.array(array, &Select::All)?,
                 };
-                let array = self.array(array, &index)?;
                 if quoted {
                     Ok(args![types::Str::from(array.join(" "))])
                 } else {
@@ -398,20 +468,15 @@ trait ExpanderInternal: Expander {
                 }
             }
             WordToken::ArrayProcess(command, quoted, ref index) => {
-                crate::IonPool::string(|output| {
-                    self.expand_process(output, command, &None)?;
-
-                    if quoted {
-                        Ok(args!(format!(
-                            "{}",
-                            self.slice_array(output.split_whitespace(), index)?
-                                .into_iter()
-                                .format(" ")
-                        )))
-                    } else {
-                        self.slice_array(output.split_whitespace(), index)
-                    }
-                })
+                let array = self.command_array(command, true)?;
+                if quoted {
+                    Ok(args!(format!(
+                        "{}",
+                        self.slice_array(array.into_iter(), index)?.into_iter().format(" ")
+                    )))
+                } else {
+                    self.slice_array(array.into_iter(), index)
+                }
             }
             WordToken::ArrayMethod(ref array_method, quoted) => {
                 let result = array_method.handle_as_array(self)?;
@@ -438,9 +503,10 @@ trait ExpanderInternal: Expander {
             WordToken::Process(command, ref index) => {
                 self.expand_process(&mut output, command, index)?
             }
-            WordToken::Variable(text, ref index) => {
-                self.slice(&mut output, self.string(text)?, index)?;
+            WordToken::ProcessSubstitution(command, writable) => {
+                output.push_str(&self.process_substitution(command, writable)?)
             }
+            WordToken::Variable(text, ref index) => self.variable(&mut output, text, index)?,
             WordToken::Arithmetic(s) => self.expand_arithmetic(&mut output, s),
             _ => unreachable!(),
         }
@@ -542,21 +608,19 @@ trait ExpanderInternal: Expander {
                     output.pop(); // Pop out the last unneeded whitespace token
                 }
                 WordToken::ArrayVariable(array, _, ref index) => {
-                    let index = if let Some(index) = index {
-                        let value = self.expand_string(index)?.join(" ");
-                        value
-                            .parse::<Select<types::Str>>()
-                            .map_err(|_| Error::IndexParsingError(value))?
-                    } else {
-                        Select::All
+                    let array = match index {
+                        Some(index) => self.select_nested(array, index)?,
+                        None => self.array(array, &Select::All)?,
                     };
-                    let _ =
-                        write!(&mut output, "{}", self.array(array, &index)?.iter().format(" "));
+                    let _ = write!(&mut output, "{}", array.iter().format(" "));
                 }
                 WordToken::ArrayProcess(command, _, ref index)
                 | WordToken::Process(command, ref index) => {
                     self.expand_process(&mut output, command, index)?;
                 }
+                WordToken::ProcessSubstitution(command, writable) => {
+                    output.push_str(&self.process_substitution(command, *writable)?);
+                }
                 WordToken::ArrayMethod(ref method, _) => {
                     method.handle(&mut output, self)?;
                 }
@@ -578,9 +642,7 @@ trait ExpanderInternal: Expander {
                 WordToken::Whitespace(text) => {
                     output.push_str(text);
                 }
-                WordToken::Variable(text, ref index) => {
-                    self.slice(&mut output, self.string(text)?, index)?;
-                }
+                WordToken::Variable(text, ref index) => self.variable(&mut output, text, index)?,
                 WordToken::Arithmetic(s) => self.expand_arithmetic(&mut output, s),
             }
         }
@@ -715,6 +777,14 @@ pub(crate) mod test {
             Ok(cmd.into())
         }
 
+        fn process_substitution(
+            &mut self,
+            cmd: &str,
+            writable: bool,
+        ) -> Result<types::Str, Self::Error> {
+            Ok(format!("/dev/fd/{}{}", if writable { ">" } else { "<" }, cmd).into())
+        }
+
         fn tilde(&self, input: &str) -> Result<types::Str, Self::Error> { Ok(input.into()) }
 
         fn map_keys<'a>(&'a self, _name: &str) -> Result<Args, Self::Error> {
@@ -724,6 +794,19 @@ pub(crate) mod test {
         fn map_values<'a>(&'a self, _name: &str) -> Result<Args, Self::Error> {
             Err(Error::VarNotFound)
         }
+
+        fn apply(
+            &mut self,
+            function: &str,
+            value: &str,
+        ) -> Result<(bool, types::Str), Self::Error> {
+            match function {
+                "upper" => Ok((true, value.to_uppercase().into())),
+                "vowel" => Ok((value.starts_with(|c: char| "aeiou".contains(c)), value.into())),
+                "len" => Ok((true, value.len().to_string().into())),
+                _ => Err(Error::VarNotFound),
+            }
+        }
     }
 
     #[test]
@@ -740,6 +823,14 @@ pub(crate) mod test {
         assert_eq!(output.as_str(), "foo not bar😉😉");
     }
 
+    #[test]
+    fn expand_process_substitution() {
+        let expanded = DummyExpander.expand_string("<(sort $B | uniq)").unwrap();
+        assert_eq!(args!["/dev/fd/<sort $B | uniq"], expanded);
+        let expanded = DummyExpander.expand_string(">(gzip > x.gz)").unwrap();
+        assert_eq!(args!["/dev/fd/>gzip > x.gz"], expanded);
+    }
+
     #[test]
     fn expand_variable_normal_variable() {
         let input = "$FOO:NOT:$BAR";
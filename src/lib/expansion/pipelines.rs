@@ -4,7 +4,7 @@ use crate::{
     types,
 };
 use itertools::Itertools;
-use std::fmt;
+use std::{fmt, os::unix::io::RawFd};
 
 /// What to redirect to the next command
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -30,6 +30,44 @@ pub struct Redirection {
     pub append: bool,
 }
 
+/// The numbered file descriptors that commands may redirect are those below this one. The
+/// descriptors above it are kept for the shell's own use.
+pub const FD_LIMIT: RawFd = 10;
+
+/// How a file is opened on a numbered file descriptor
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FdMode {
+    /// Open the file for reading (`3<file`)
+    Read,
+    /// Truncate the file and open it for writing (`3>file`)
+    Write,
+    /// Open the file for writing at its end (`3>>file`)
+    Append,
+}
+
+/// A redirection acting on an arbitrary, numbered file descriptor of a command
+#[derive(Debug, PartialEq, Clone)]
+pub enum FdRedirection {
+    /// Open a file on the descriptor (`3>file`, `3>>file`, `3<file`)
+    File {
+        /// The descriptor to redirect
+        fd:   RawFd,
+        /// The file to open
+        file: types::Str,
+        /// How to open the file
+        mode: FdMode,
+    },
+    /// Make the descriptor a copy of another one (`2>&1`, `<&4`)
+    Duplicate {
+        /// The descriptor to redirect
+        fd:     RawFd,
+        /// The descriptor to copy
+        source: RawFd,
+    },
+    /// Close the descriptor (`>&-`, `3<&-`)
+    Close(RawFd),
+}
+
 /// Represents input that a process could initially receive from `stdin`
 #[derive(Debug, PartialEq, Clone)]
 pub enum Input {
@@ -37,8 +75,10 @@ pub enum Input {
     /// process
     File(types::Str),
     /// A string literal that is written to the `stdin` of a process.
-    /// If there is a second string, that second string is the EOF phrase for the heredoc.
     HereString(types::Str),
+    /// The body of a heredoc, which is written as is to the `stdin` of a process. The body is
+    /// expanded like a double-quoted string first if the flag is set.
+    Heredoc(types::Str, bool),
 }
 
 impl<'a> fmt::Display for RedirectFrom {
@@ -61,6 +101,7 @@ impl<'a> fmt::Display for Input {
         match self {
             Input::File(ref file) => write!(f, "< {}", file),
             Input::HereString(ref string) => write!(f, "<<< '{}'", string),
+            Input::Heredoc(ref body, _) => write!(f, "<< '{}'", body),
         }
     }
 }
@@ -82,6 +123,24 @@ impl<'a> fmt::Display for Redirection {
     }
 }
 
+impl<'a> fmt::Display for FdRedirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FdRedirection::File { fd, ref file, mode: FdMode::Read } => {
+                write!(f, "{}< {}", fd, file)
+            }
+            FdRedirection::File { fd, ref file, mode: FdMode::Write } => {
+                write!(f, "{}> {}", fd, file)
+            }
+            FdRedirection::File { fd, ref file, mode: FdMode::Append } => {
+                write!(f, "{}>> {}", fd, file)
+            }
+            FdRedirection::Duplicate { fd, source } => write!(f, "{}>&{}", fd, source),
+            FdRedirection::Close(fd) => write!(f, "{}>&-", fd),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 /// Where should the pipeline be run
 pub enum PipeType {
@@ -120,6 +179,8 @@ pub struct PipeItem<T> {
     pub outputs: Vec<Redirection>,
     /// A list of inputs
     pub inputs:  Vec<Input>,
+    /// Redirections of numbered file descriptors, applied in order
+    pub fds:     Vec<FdRedirection>,
 }
 
 impl<'a> PipeItem<RefinedJob<'a>> {
@@ -141,6 +202,10 @@ impl<'a> PipeItem<Job> {
             .map(|input| match input {
                 Input::File(ref s) => shell.get_string(s).map(Input::File),
                 Input::HereString(ref s) => shell.get_string(s).map(Input::HereString),
+                Input::Heredoc(ref body, true) => {
+                    shell.get_string(&double_quoted(body)).map(|body| Input::Heredoc(body, false))
+                }
+                Input::Heredoc(..) => Ok(input.clone()),
             })
             .collect::<Result<_, _>>()?;
 
@@ -156,17 +221,55 @@ impl<'a> PipeItem<Job> {
             })
             .collect::<Result<_, _>>()?;
 
-        Ok(PipeItem { job, outputs, inputs })
+        let fds = self
+            .fds
+            .iter()
+            .map(|fd| match fd {
+                FdRedirection::File { fd, ref file, mode } => shell
+                    .get_string(file.as_str())
+                    .map(|file| FdRedirection::File { fd: *fd, file, mode: *mode }),
+                _ => Ok(fd.clone()),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(PipeItem { job, outputs, inputs, fds })
     }
 
     /// Create a new pipeitem with the given job and redirections
-    pub const fn new(job: Job, outputs: Vec<Redirection>, inputs: Vec<Input>) -> Self {
-        Self { job, outputs, inputs }
+    pub const fn new(
+        job: Job,
+        outputs: Vec<Redirection>,
+        inputs: Vec<Input>,
+        fds: Vec<FdRedirection>,
+    ) -> Self {
+        Self { job, outputs, inputs, fds }
     }
 }
 
+/// Wrap the body of a heredoc in double quotes, so that it is expanded as a single string
+fn double_quoted(body: &str) -> String {
+    let mut quoted = String::with_capacity(body.len() + 2);
+    let mut escaped = false;
+    quoted.push('"');
+    for character in body.chars() {
+        if character == '"' && !escaped {
+            quoted.push('\\');
+        }
+        escaped = character == '\\' && !escaped;
+        quoted.push(character);
+    }
+    if escaped {
+        quoted.push('\\');
+    }
+    quoted.push('"');
+    quoted
+}
+
 impl<'a> fmt::Display for PipeItem<RefinedJob<'a>> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.job.env {
+            write!(f, "{}={} ", name, value)?;
+        }
         write!(f, "{}", self.job.args.iter().format(" "))?;
         for input in &self.inputs {
             write!(f, " {}", input)?;
@@ -174,6 +277,9 @@ impl<'a> fmt::Display for PipeItem<RefinedJob<'a>> {
         for output in &self.outputs {
             write!(f, " {}", output)?;
         }
+        for fd in &self.fds {
+            write!(f, " {}", fd)?;
+        }
         write!(
             f,
             "{}",
@@ -193,6 +299,7 @@ impl<'a> Pipeline<RefinedJob<'a>> {
         self.items.len() > 1
             || self.items.iter().any(|it| !it.outputs.is_empty())
             || self.items.iter().any(|it| !it.inputs.is_empty())
+            || self.items.iter().any(|it| !it.fds.is_empty())
             || self.pipe != PipeType::Normal
     }
 }
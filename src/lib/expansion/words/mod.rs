@@ -110,6 +110,9 @@ pub enum WordToken<'a> {
     ArrayProcess(&'a str, bool, Option<&'a str>),
     /// A process that expands to a scalar value
     Process(&'a str, Option<&'a str>),
+    /// A process that expands to the path of a pipe connected to it. The flag is set when the
+    /// path is written to (`>(cmd)`) rather than read from (`<(cmd)`)
+    ProcessSubstitution(&'a str, bool),
     /// A method on a scalar value
     StringMethod(StringMethod<'a>),
     /// A method on a array value
@@ -387,6 +390,40 @@ impl<'a> WordIterator<'a> {
         panic!("ion: fatal error with syntax validation: unterminated process");
     }
 
+    /// Contains the logic for parsing process substitutions.
+    fn process_substitution<I>(&mut self, iterator: &mut I, writable: bool) -> WordToken<'a>
+    where
+        I: Iterator<Item = u8>,
+    {
+        let start = self.read;
+        let mut level = 0;
+        while let Some(character) = iterator.next() {
+            match character {
+                _ if self.backsl => self.backsl = false,
+                b'\\' => self.backsl = true,
+                b'\'' if self.quotes == Quotes::Single => self.quotes = Quotes::None,
+                b'\'' if self.quotes == Quotes::None => self.quotes = Quotes::Single,
+                b'"' if self.quotes == Quotes::Double => self.quotes = Quotes::None,
+                b'"' if self.quotes == Quotes::None => self.quotes = Quotes::Double,
+                b'(' if self.quotes != Quotes::Single => level += 1,
+                b')' if self.quotes != Quotes::Single => {
+                    if level == 0 {
+                        let output = &self.data[start..self.read];
+                        self.read += 1;
+                        return WordToken::ProcessSubstitution(output, writable);
+                    } else {
+                        level -= 1;
+                    }
+                }
+                _ => (),
+            }
+            self.read += 1;
+        }
+
+        // The validator at the frontend should catch unterminated processes.
+        panic!("ion: fatal error with syntax validation: unterminated process substitution");
+    }
+
     fn braced_array_variable<I>(&mut self, iterator: &mut I) -> WordToken<'a>
     where
         I: Iterator<Item = u8>,
@@ -458,34 +495,50 @@ impl<'a> WordIterator<'a> {
                                 let variable = &self.data[start..self.read];
                                 self.read += 1;
                                 start = self.read;
+                                // Parentheses and quotes within the pattern are balanced
+                                let mut quotes = Quotes::None;
                                 while let Some(character) = iterator.next() {
-                                    if character == b')' {
-                                        let pattern = &self.data[start..self.read].trim();
-                                        self.read += 1;
-                                        return if let Some(&b'[') =
-                                            self.data.as_bytes().get(self.read)
-                                        {
+                                    match character {
+                                        b'\'' if quotes == Quotes::Single => quotes = Quotes::None,
+                                        b'\'' if quotes == Quotes::None => quotes = Quotes::Single,
+                                        b'"' if quotes == Quotes::Double => quotes = Quotes::None,
+                                        b'"' if quotes == Quotes::None => quotes = Quotes::Double,
+                                        b'(' if quotes == Quotes::None => depth += 1,
+                                        b')' if quotes == Quotes::None && depth != 0 => depth -= 1,
+                                        b')' if quotes == Quotes::None => {
+                                            let pattern = &self.data[start..self.read].trim();
+                                            self.read += 1;
+                                            return if let Some(&b'[') =
+                                                self.data.as_bytes().get(self.read)
+                                            {
+                                                let _ = iterator.next();
+                                                WordToken::ArrayMethod(
+                                                    ArrayMethod::new(
+                                                        method,
+                                                        variable.trim(),
+                                                        Pattern::StringPattern(pattern),
+                                                        Some(self.read_selection(iterator)),
+                                                    ),
+                                                    self.quotes == Quotes::Double,
+                                                )
+                                            } else {
+                                                WordToken::ArrayMethod(
+                                                    ArrayMethod::new(
+                                                        method,
+                                                        variable.trim(),
+                                                        Pattern::StringPattern(pattern),
+                                                        None,
+                                                    ),
+                                                    self.quotes == Quotes::Double,
+                                                )
+                                            };
+                                        }
+                                        // Backslashes are literal within single quotes
+                                        b'\\' if quotes != Quotes::Single => {
+                                            self.read += 1;
                                             let _ = iterator.next();
-                                            WordToken::ArrayMethod(
-                                                ArrayMethod::new(
-                                                    method,
-                                                    variable.trim(),
-                                                    Pattern::StringPattern(pattern),
-                                                    Some(self.read_selection(iterator)),
-                                                ),
-                                                self.quotes == Quotes::Double,
-                                            )
-                                        } else {
-                                            WordToken::ArrayMethod(
-                                                ArrayMethod::new(
-                                                    method,
-                                                    variable.trim(),
-                                                    Pattern::StringPattern(pattern),
-                                                    None,
-                                                ),
-                                                self.quotes == Quotes::Double,
-                                            )
-                                        };
+                                        }
+                                        _ => (),
                                     }
                                     self.read += 1;
                                 }
@@ -557,7 +610,8 @@ impl<'a> WordIterator<'a> {
         self.read += 1;
         let start = self.read;
         for character in iterator {
-            if let b']' = character {
+            // Selections may be chained to reach into nested values: `[a][b]`
+            if character == b']' && self.data.as_bytes().get(self.read + 1) != Some(&b'[') {
                 let value = &self.data[start..self.read];
                 self.read += 1;
                 return value;
@@ -856,6 +910,11 @@ impl<'a> Iterator for WordIterator<'a> {
                         }
                     }
                 }
+                b'<' | b'>' if self.quotes == Quotes::None && iterator.peek() == Some(&b'(') => {
+                    let _ = iterator.next();
+                    self.read += 2;
+                    return Some(self.process_substitution(&mut iterator, character == b'>'));
+                }
                 b'{' => match self.quotes {
                     Quotes::None => {
                         self.read += 1;
@@ -55,6 +55,55 @@ fn array_expressions() {
     compare(input, expected);
 }
 
+#[test]
+fn array_method_with_parentheses() {
+    let input = "@map(@array echo $((it * 2))) @filter(@array test ($it) = x)[0]";
+    let expected = &[
+        WordToken::ArrayMethod(
+            ArrayMethod::new("map", "@array", Pattern::StringPattern("echo $((it * 2))"), None),
+            false,
+        ),
+        WordToken::Whitespace(" "),
+        WordToken::ArrayMethod(
+            ArrayMethod::new(
+                "filter",
+                "@array",
+                Pattern::StringPattern("test ($it) = x"),
+                Some("0"),
+            ),
+            false,
+        ),
+    ];
+    compare(input, expected);
+}
+
+#[test]
+fn array_method_with_quoted_parentheses() {
+    let input = "@split($x '(') @regex_captures($s '[)]') @split($x \")\") @split($x '\\')";
+    let expected = &[
+        WordToken::ArrayMethod(
+            ArrayMethod::new("split", "$x", Pattern::StringPattern("'('"), None),
+            false,
+        ),
+        WordToken::Whitespace(" "),
+        WordToken::ArrayMethod(
+            ArrayMethod::new("regex_captures", "$s", Pattern::StringPattern("'[)]'"), None),
+            false,
+        ),
+        WordToken::Whitespace(" "),
+        WordToken::ArrayMethod(
+            ArrayMethod::new("split", "$x", Pattern::StringPattern("\")\""), None),
+            false,
+        ),
+        WordToken::Whitespace(" "),
+        WordToken::ArrayMethod(
+            ArrayMethod::new("split", "$x", Pattern::StringPattern("'\\'"), None),
+            false,
+        ),
+    ];
+    compare(input, expected);
+}
+
 #[test]
 fn array_variables() {
     let input = "@array @array[0] @{array[1..]}";
@@ -121,6 +170,17 @@ fn string_keys() {
     compare(input, expected);
 }
 
+#[test]
+fn nested_keys() {
+    let input = "$cfg[servers][0][host] @cfg[$key][0]";
+    let expected = &[
+        WordToken::Variable("cfg", Some("servers][0][host")),
+        WordToken::Whitespace(" "),
+        WordToken::ArrayVariable("cfg", false, Some("$key][0")),
+    ];
+    compare(input, expected);
+}
+
 #[test]
 fn nested_processes() {
     let input = "echo $(echo $(echo one)) $(echo one $(echo two) three)";
@@ -153,6 +213,25 @@ fn words_process_with_quotes() {
     compare(input, expected);
 }
 
+#[test]
+fn process_substitutions() {
+    let input = "diff <(sort a | uniq) <(cat <(echo ')'))  tee >(gzip > x.gz) \"<(x)\"";
+    let expected = &[
+        WordToken::Normal("diff".into(), false, false),
+        WordToken::Whitespace(" "),
+        WordToken::ProcessSubstitution("sort a | uniq", false),
+        WordToken::Whitespace(" "),
+        WordToken::ProcessSubstitution("cat <(echo ')')", false),
+        WordToken::Whitespace("  "),
+        WordToken::Normal("tee".into(), false, false),
+        WordToken::Whitespace(" "),
+        WordToken::ProcessSubstitution("gzip > x.gz", true),
+        WordToken::Whitespace(" "),
+        WordToken::Normal("<(x)".into(), false, false),
+    ];
+    compare(input, expected);
+}
+
 #[test]
 fn test_words() {
     let input = "echo $ABC \"${ABC}\" one{$ABC,$ABC} ~ $(echo foo) \"$(seq 1 100)\"";
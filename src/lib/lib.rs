@@ -46,9 +46,10 @@
 //!     // Register the builtins
 //!     shell.builtins_mut().add("layout", set_layout, "Set the application layout");
 //!
-//!     // Read a file and execute it
-//!     if let Ok(file) = File::open("/home/user/.config/my-application/config.ion") {
-//!         if let Err(why) = shell.execute_command(file) {
+//!     // Read a file and execute it. Errors point to the offending line of the script
+//!     let config = "/home/user/.config/my-application/config.ion";
+//!     if let Ok(file) = File::open(config) {
+//!         if let Err(why) = shell.execute_script(config, file) {
 //!             println!("ERROR: my-application: error in config file: {}", why);
 //!         }
 //!     }
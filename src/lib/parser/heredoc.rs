@@ -0,0 +1,135 @@
+/// The header of a multi-line block of input, written as `<<DELIM`, `<<'DELIM'` or `<<-DELIM`.
+///
+/// The body of the block starts on the line following the header, and ends with the first line
+/// that consists of the delimiter alone. Quoting the delimiter keeps the body from being
+/// expanded, and a leading `-` allows the closing delimiter to be indented and strips the
+/// indentation that all lines of the body have in common.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Heredoc<'a> {
+    delimiter: &'a [u8],
+    strip:     bool,
+    expand:    bool,
+}
+
+impl<'a> Heredoc<'a> {
+    /// Parse the header that follows a `<<` operator, returning it along with its length
+    pub fn parse(data: &'a [u8]) -> Option<(Self, usize)> {
+        let strip = data.first() == Some(&b'-');
+        let mut start = if strip { 1 } else { 0 };
+        start += data[start..].iter().take_while(|&&c| c == b' ' || c == b'\t').count();
+
+        let (delimiter, expand, end) = match data.get(start) {
+            Some(&quote) if quote == b'\'' || quote == b'"' => {
+                let len = data[start + 1..].iter().position(|&c| c == quote)?;
+                (&data[start + 1..start + 1 + len], false, start + len + 2)
+            }
+            _ => {
+                let len = data[start..]
+                    .iter()
+                    .position(|&c| c.is_ascii_whitespace() || b";&|<>()'\"".contains(&c))
+                    .unwrap_or(data.len() - start);
+                (&data[start..start + len], true, start + len)
+            }
+        };
+
+        if delimiter.is_empty() {
+            None
+        } else {
+            Some((Self { delimiter, strip, expand }, end))
+        }
+    }
+
+    /// The phrase that ends the body
+    pub const fn delimiter(&self) -> &'a [u8] { self.delimiter }
+
+    /// Whether variables and processes in the body should be expanded
+    pub const fn expand(&self) -> bool { self.expand }
+
+    /// Whether `line` ends the body
+    pub fn is_end(&self, line: &[u8]) -> bool {
+        let line = if self.strip {
+            let indent = line.iter().take_while(|&&c| c == b' ' || c == b'\t').count();
+            &line[indent..]
+        } else {
+            line
+        };
+        line == self.delimiter
+    }
+
+    /// Split the body from `data`, which starts with the newline that follows the header.
+    ///
+    /// Returns the lines of the body, each terminated by a newline, along with the length of
+    /// `data` that was consumed, including the closing delimiter.
+    pub fn body(&self, data: &'a str) -> Option<(String, usize)> {
+        if !data.starts_with('\n') {
+            return None;
+        }
+
+        let mut read = 1;
+        let mut lines = Vec::new();
+        loop {
+            let rest = &data[read..];
+            let (line, len) = match rest.find('\n') {
+                Some(pos) => (&rest[..pos], pos + 1),
+                None => (rest, rest.len()),
+            };
+            if self.is_end(line.as_bytes()) {
+                read += len;
+                break;
+            } else if len == line.len() {
+                return None;
+            }
+            lines.push(line);
+            read += len;
+        }
+
+        let indent = if self.strip {
+            lines
+                .iter()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| line.len() - line.trim_start_matches(|c| c == ' ' || c == '\t').len())
+                .min()
+                .unwrap_or(0)
+        } else {
+            0
+        };
+
+        let mut body = String::with_capacity(read);
+        for line in lines {
+            body.push_str(line.get(indent..).unwrap_or_else(|| line.trim_start()));
+            body.push('\n');
+        }
+        Some((body, read))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers() {
+        let (heredoc, len) = Heredoc::parse(b"EOF | cat").unwrap();
+        assert_eq!((heredoc.delimiter(), heredoc.expand(), len), (&b"EOF"[..], true, 3));
+        let (heredoc, len) = Heredoc::parse(b"-'END' > out").unwrap();
+        assert_eq!((heredoc.delimiter(), heredoc.expand(), len), (&b"END"[..], false, 6));
+        let (heredoc, len) = Heredoc::parse(b" \"a b\"\n").unwrap();
+        assert_eq!((heredoc.delimiter(), heredoc.expand(), len), (&b"a b"[..], false, 6));
+        assert_eq!(Heredoc::parse(b" | cat"), None);
+        assert_eq!(Heredoc::parse(b"'EOF"), None);
+    }
+
+    #[test]
+    fn bodies() {
+        let (heredoc, len) = Heredoc::parse(b"EOF").unwrap();
+        let data = "\none\n  two\nEOF \nEOF\n| cat";
+        assert_eq!(heredoc.body(data), Some(("one\n  two\nEOF \n".into(), 20)));
+        assert_eq!(heredoc.body("\nEOF"), Some((String::new(), 4)));
+        assert_eq!(heredoc.body("\none\n  EOF"), None);
+        assert_eq!(len, 3);
+
+        let (heredoc, _) = Heredoc::parse(b"-EOF").unwrap();
+        let data = "\n    one\n\n      two\n  EOF\n";
+        assert_eq!(heredoc.body(data), Some(("one\n\n  two\n".into(), data.len())));
+    }
+}
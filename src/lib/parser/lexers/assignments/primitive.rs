@@ -28,23 +28,26 @@ impl Primitive {
             "bool" => Some(Primitive::Boolean),
             "int" => Some(Primitive::Integer),
             "float" => Some(Primitive::Float),
-            _ => {
+            _ if data.ends_with(']') => {
                 let open_bracket = data.find('[')?;
-                let close_bracket = data.rfind(']')?;
                 let kind = &data[..open_bracket];
-                let inner = &data[open_bracket + 1..close_bracket];
+                let inner = Box::new(Self::parse(&data[open_bracket + 1..data.len() - 1])?);
 
-                if kind == "hmap" {
-                    Some(Primitive::HashMap(Box::new(Self::parse(inner)?)))
-                } else if kind == "bmap" {
-                    Some(Primitive::BTreeMap(Box::new(Self::parse(inner)?)))
-                } else {
-                    // It's an array
-                    Some(Primitive::Array(Box::new(Self::parse(inner)?)))
+                match kind {
+                    "hmap" => Some(Primitive::HashMap(inner)),
+                    "bmap" => Some(Primitive::BTreeMap(inner)),
+                    "" | "array" => Some(Primitive::Array(inner)),
+                    _ => None,
                 }
             }
+            _ => None,
         }
     }
+
+    /// Whether values of this type hold other values, which must keep their own structure
+    pub(crate) const fn is_collection(&self) -> bool {
+        matches!(self, Primitive::Array(_) | Primitive::HashMap(_) | Primitive::BTreeMap(_))
+    }
 }
 
 impl Display for Primitive {
@@ -67,3 +70,27 @@ impl Display for Primitive {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_types() {
+        let int = || Box::new(Primitive::Integer);
+        assert_eq!(Primitive::parse("array[int]"), Some(Primitive::Array(int())));
+        assert_eq!(
+            Primitive::parse("hmap[array[int]]"),
+            Some(Primitive::HashMap(Box::new(Primitive::Array(int()))))
+        );
+        assert_eq!(
+            Primitive::parse("[bmap[[int]]]"),
+            Some(Primitive::Array(Box::new(Primitive::BTreeMap(Box::new(
+                Primitive::Array(int())
+            )))))
+        );
+        assert_eq!(Primitive::parse("list[int]"), None);
+        assert_eq!(Primitive::parse("hmap[int]]"), None);
+        assert_eq!(Primitive::parse("[int]x"), None);
+    }
+}
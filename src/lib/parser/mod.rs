@@ -2,18 +2,21 @@
 //!
 //! The `Terminator` takes input data and creates string with the good size
 //! The `StatementSplitter` than takes the data and produces statements, with the help of
-//! `parse_and_validate`
+//! `parse_and_validate`. Each statement is tagged with the `Span` it was read from.
 
+mod heredoc;
 /// The terminal tokens associated with the parsing process
 pub mod lexers;
 /// Parse the pipelines to a Pipeline struct
 pub mod pipelines;
 mod quotes;
+mod span;
 mod statement;
 
 pub use self::{
     quotes::Terminator,
-    statement::{parse_and_validate, Error, StatementSplitter},
+    span::{Span, Spanned},
+    statement::{parse_and_validate, Error, Group, Parameter, ParameterKind, StatementSplitter},
 };
 
 #[cfg(fuzzing)]
@@ -1,11 +1,17 @@
-use std::iter::Peekable;
+use std::{iter::Peekable, os::unix::io::RawFd};
 use thiserror::Error;
 
 use crate::{
     builtins::BuiltinMap,
-    expansion::pipelines::{Input, PipeItem, PipeType, Pipeline, RedirectFrom, Redirection},
-    parser::lexers::arguments::{Field, Levels, LevelsError},
-    shell::Job,
+    expansion::pipelines::{
+        FdMode, FdRedirection, Input, PipeItem, PipeType, Pipeline, RedirectFrom, Redirection,
+        FD_LIMIT,
+    },
+    parser::{
+        heredoc::Heredoc,
+        lexers::arguments::{Field, Levels, LevelsError},
+    },
+    shell::{variables::Variables, Job},
     types::*,
 };
 
@@ -18,15 +24,27 @@ pub enum PipelineParsingError {
     /// No file was provided after the redirection output
     #[error("expected file argument after redirection for output")]
     NoRedirection,
-    /// Heredocs are deprecated and were used
-    #[error("heredocs are not a part of Ion. Use redirection and/or cat instead")]
-    HeredocsDeprecated,
+    /// No delimiter was given to the heredoc
+    #[error("expected delimiter after '<<'")]
+    NoHeredocDelimiter,
+    /// The body of the heredoc was never closed
+    #[error("heredoc is missing its closing delimiter '{0}'")]
+    UnterminatedHeredoc(String),
     /// No string was given to the herestring
     #[error("expected string argument after '<<<'")]
     NoHereStringArg,
     /// No file was provided after the input redirection
     #[error("expected file argument after redirection for input")]
     NoRedirectionArg,
+    /// No file descriptor was provided after `>&` or `<&`
+    #[error("expected file descriptor or '-' after '>&' or '<&'")]
+    NoFdTarget,
+    /// The target of `>&` or `<&` is not a file descriptor
+    #[error("'{0}' is not a valid file descriptor")]
+    InvalidFdTarget(String),
+    /// The descriptor is above those which may be redirected, as it may be used by the shell
+    #[error("file descriptor {0} is out of range: only 0 to 9 may be redirected")]
+    FdOutOfRange(RawFd),
 
     // quotes
     /// Unterminated double quotes
@@ -53,6 +71,7 @@ trait AddItem<'a> {
         args: Args,
         outputs: Vec<Redirection>,
         inputs: Vec<Input>,
+        fds: Vec<FdRedirection>,
     );
 }
 
@@ -63,10 +82,13 @@ impl<'a> AddItem<'a> for Pipeline<Job> {
         args: Args,
         outputs: Vec<Redirection>,
         inputs: Vec<Input>,
+        fds: Vec<FdRedirection>,
     ) {
         if !args.is_empty() {
             //            let builtin = builtins.contains(&args[0]);
-            self.items.push(PipeItem::new(Job::new(args, redirection), outputs, inputs));
+            let env = Collector::env_prefix(&mut args);
+            let job = Job::new(args, redirection).with_env(env);
+            self.items.push(PipeItem::new(job, outputs, inputs, fds));
         }
     }
 }
@@ -78,6 +100,23 @@ pub struct Collector<'a> {
 }
 
 impl<'a> Collector<'a> {
+    /// Split the leading `NAME=value` words from the arguments of a command, as the
+    /// environment variables to set for that command alone. Words that are not followed by a
+    /// command are left as they are.
+    fn env_prefix(args: &mut Args) -> Vec<(Str, Str)> {
+        let assignment = |arg: &Str| {
+            arg.find('=').filter(|&pos| Variables::is_valid_name(&arg[..pos])).map(|pos| {
+                let (name, value) = arg.split_at(pos);
+                (Str::from(name), Str::from(&value[1..]))
+            })
+        };
+        let len = args.iter().take_while(|arg| assignment(arg).is_some()).count();
+        if len == args.len() {
+            return Vec::new();
+        }
+        args.drain(..len).filter_map(|arg| assignment(&arg)).collect()
+    }
+
     /// Add a new argument that is re
     fn push_arg<I>(
         &self,
@@ -114,12 +153,113 @@ impl<'a> Collector<'a> {
             .map(|file| outputs.push(Redirection { from, file: file.into(), append }))
     }
 
+    /// Attempt to add a redirection of an output file descriptor, which is stdout by default
+    fn push_redir_from_fd<I>(
+        &self,
+        fd: Option<RawFd>,
+        outputs: &mut Vec<Redirection>,
+        fds: &mut Vec<FdRedirection>,
+        bytes: &mut Peekable<I>,
+    ) -> Result<(), PipelineParsingError>
+    where
+        I: Iterator<Item = (usize, u8)>,
+    {
+        if let Some(&(_, b'&')) = bytes.peek() {
+            bytes.next();
+            return self.fd_target(fd.unwrap_or(1), bytes).map(|target| fds.push(target));
+        }
+        match fd {
+            None | Some(1) => self.push_redir_to_output(RedirectFrom::Stdout, outputs, bytes),
+            Some(2) => self.push_redir_to_output(RedirectFrom::Stderr, outputs, bytes),
+            Some(fd) => {
+                let mode = if let Some(&(_, b'>')) = bytes.peek() {
+                    bytes.next();
+                    FdMode::Append
+                } else {
+                    FdMode::Write
+                };
+                self.arg(bytes)?
+                    .ok_or(PipelineParsingError::NoRedirection)
+                    .map(|file| fds.push(FdRedirection::File { fd, file: file.into(), mode }))
+            }
+        }
+    }
+
+    /// Attempt to add a redirection of an input file descriptor, which is stdin by default
+    fn push_redir_to_fd<I>(
+        &self,
+        fd: Option<RawFd>,
+        inputs: &mut Vec<Input>,
+        fds: &mut Vec<FdRedirection>,
+        bytes: &mut Peekable<I>,
+    ) -> Result<(), PipelineParsingError>
+    where
+        I: Iterator<Item = (usize, u8)>,
+    {
+        if let Some(&(_, b'&')) = bytes.peek() {
+            bytes.next();
+            return self.fd_target(fd.unwrap_or(0), bytes).map(|target| fds.push(target));
+        }
+        let file = self.arg(bytes)?.ok_or(PipelineParsingError::NoRedirectionArg)?;
+        match fd {
+            None | Some(0) => inputs.push(Input::File(file.into())),
+            Some(fd) => fds.push(FdRedirection::File { fd, file: file.into(), mode: FdMode::Read }),
+        }
+        Ok(())
+    }
+
+    /// Parse what follows `>&` or `<&`: either a descriptor to copy or `-` to close `fd`
+    fn fd_target<I>(
+        &self,
+        fd: RawFd,
+        bytes: &mut Peekable<I>,
+    ) -> Result<FdRedirection, PipelineParsingError>
+    where
+        I: Iterator<Item = (usize, u8)>,
+    {
+        match self.arg(bytes)? {
+            Some("-") => Ok(FdRedirection::Close(fd)),
+            Some(source) => match source.parse() {
+                Ok(source) if source >= FD_LIMIT => Err(PipelineParsingError::FdOutOfRange(source)),
+                Ok(source) => Ok(FdRedirection::Duplicate { fd, source }),
+                Err(_) => Err(PipelineParsingError::InvalidFdTarget(source.into())),
+            },
+            None => Err(PipelineParsingError::NoFdTarget),
+        }
+    }
+
+    /// If the digits at `start` are immediately followed by `>` or `<`, return the file
+    /// descriptor they represent along with their length
+    fn fd_prefix(&self, start: usize) -> Option<(RawFd, usize)> {
+        let len = self.data[start..].bytes().take_while(u8::is_ascii_digit).count();
+        match self.peek(start + len) {
+            Some(b'>') | Some(b'<') => {
+                self.data[start..start + len].parse().ok().map(|fd| (fd, len))
+            }
+            _ => None,
+        }
+    }
+
+    /// Parse the header and body of the heredoc that starts at `start`, returning the input
+    /// it represents along with their length
+    fn heredoc(&self, start: usize) -> Result<(Input, usize), PipelineParsingError> {
+        let (heredoc, len) = Heredoc::parse(&self.data.as_bytes()[start..])
+            .ok_or(PipelineParsingError::NoHeredocDelimiter)?;
+        let (body, read) = heredoc.body(&self.data[start + len..]).ok_or_else(|| {
+            PipelineParsingError::UnterminatedHeredoc(
+                String::from_utf8_lossy(heredoc.delimiter()).into_owned(),
+            )
+        })?;
+        Ok((Input::Heredoc(body.into(), heredoc.expand()), len + read))
+    }
+
     fn parse<'builtins>(&self) -> Result<Pipeline<Job>, PipelineParsingError> {
         let mut bytes = self.data.bytes().enumerate().peekable();
         let mut args = Args::with_capacity(ARG_DEFAULT_SIZE);
         let mut pipeline = Pipeline::new();
         let mut outputs: Vec<Redirection> = Vec::new();
         let mut inputs: Vec<Input> = Vec::new();
+        let mut fds: Vec<FdRedirection> = Vec::new();
 
         while let Some(&(i, b)) = bytes.peek() {
             // Determine what production rule we are using based on the first character
@@ -144,6 +284,7 @@ impl<'a> Collector<'a> {
                                 std::mem::replace(&mut args, Args::with_capacity(ARG_DEFAULT_SIZE)),
                                 std::mem::replace(&mut outputs, Vec::new()),
                                 std::mem::replace(&mut inputs, Vec::new()),
+                                std::mem::replace(&mut fds, Vec::new()),
                             );
                         }
                         Some(&(_, b'!')) => {
@@ -178,6 +319,7 @@ impl<'a> Collector<'a> {
                                 std::mem::replace(&mut args, Args::with_capacity(ARG_DEFAULT_SIZE)),
                                 std::mem::replace(&mut outputs, Vec::new()),
                                 std::mem::replace(&mut inputs, Vec::new()),
+                                std::mem::replace(&mut fds, Vec::new()),
                             );
                         }
                         Some(_) | None => self.push_arg(&mut args, &mut bytes)?,
@@ -190,11 +332,16 @@ impl<'a> Collector<'a> {
                         std::mem::replace(&mut args, Args::with_capacity(ARG_DEFAULT_SIZE)),
                         std::mem::replace(&mut outputs, Vec::new()),
                         std::mem::replace(&mut inputs, Vec::new()),
+                        std::mem::replace(&mut fds, Vec::new()),
                     );
                 }
+                // Process substitutions are arguments rather than redirections
+                b'<' | b'>' if self.peek(i + 1) == Some(b'(') => {
+                    self.push_arg(&mut args, &mut bytes)?
+                }
                 b'>' => {
                     bytes.next();
-                    self.push_redir_to_output(RedirectFrom::Stdout, &mut outputs, &mut bytes)?;
+                    self.push_redir_from_fd(None, &mut outputs, &mut fds, &mut bytes)?;
                 }
                 b'<' => {
                     bytes.next();
@@ -210,15 +357,35 @@ impl<'a> Collector<'a> {
                                 return Err(PipelineParsingError::NoHereStringArg);
                             }
                         } else {
-                            return Err(PipelineParsingError::HeredocsDeprecated);
+                            bytes.next();
+                            let (body, len) = self.heredoc(i + 2)?;
+                            for _ in 0..len {
+                                bytes.next();
+                            }
+                            inputs.push(body);
                         }
-                    } else if let Some(file) = self.arg(&mut bytes)? {
-                        // Otherwise interpret it as stdin redirection
-                        inputs.push(Input::File(file.into()));
                     } else {
-                        return Err(PipelineParsingError::NoRedirectionArg);
+                        // Otherwise interpret it as stdin redirection
+                        self.push_redir_to_fd(None, &mut inputs, &mut fds, &mut bytes)?;
                     }
                 }
+                // A number directly followed by an arrow is the file descriptor to redirect
+                b'0'..=b'9' => match self.fd_prefix(i) {
+                    Some((fd, _)) if fd >= FD_LIMIT => {
+                        return Err(PipelineParsingError::FdOutOfRange(fd))
+                    }
+                    Some((fd, len)) => {
+                        for _ in 0..len {
+                            bytes.next();
+                        }
+                        if let Some((_, b'>')) = bytes.next() {
+                            self.push_redir_from_fd(Some(fd), &mut outputs, &mut fds, &mut bytes)?;
+                        } else {
+                            self.push_redir_to_fd(Some(fd), &mut inputs, &mut fds, &mut bytes)?;
+                        }
+                    }
+                    None => self.push_arg(&mut args, &mut bytes)?,
+                },
                 // Skip over whitespace between jobs
                 b' ' | b'\t' => {
                     bytes.next();
@@ -228,7 +395,7 @@ impl<'a> Collector<'a> {
             }
         }
 
-        pipeline.add_item(RedirectFrom::None, args, outputs, inputs);
+        pipeline.add_item(RedirectFrom::None, args, outputs, inputs, fds);
         Ok(pipeline)
     }
 
@@ -331,6 +498,10 @@ impl<'a> Collector<'a> {
                     bytes.next();
                     bytes.next();
                 }
+                // An argument may start with a process substitution
+                b'<' | b'>' if start == Some(i) && self.peek(i + 1) == Some(b'(') => {
+                    bytes.next();
+                }
                 // If we see a byte from the follow set, we've definitely reached the end of
                 // the arguments
                 b'&' | b'|' | b'<' | b'>' | b' ' | b'\t' if levels.are_rooted() => {
@@ -421,7 +592,10 @@ mod tests {
     use crate::{
         builtins::BuiltinMap,
         parser::{
-            pipelines::{Input, PipeItem, PipeType, Pipeline, RedirectFrom, Redirection},
+            pipelines::{
+                FdMode, FdRedirection, Input, PipeItem, PipeType, Pipeline, RedirectFrom,
+                Redirection,
+            },
             statement::parse,
         },
         shell::{flow_control::Statement, Job, Shell},
@@ -838,6 +1012,7 @@ mod tests {
                         Input::HereString("\"herestring\"".into()),
                     ],
                     outputs: Vec::new(),
+                    fds:     Vec::new(),
                 },
                 PipeItem {
                     job:     Job::new(args!["tr", "'x'", "'y'"], RedirectFrom::None),
@@ -859,6 +1034,7 @@ mod tests {
                             append: false,
                         },
                     ],
+                    fds:     Vec::new(),
                 },
             ],
             pipe:  PipeType::Normal,
@@ -877,11 +1053,13 @@ mod tests {
                     job:     Job::new(args!["cat"], RedirectFrom::Stdout),
                     inputs:  Vec::new(),
                     outputs: Vec::new(),
+                    fds:     Vec::new(),
                 },
                 PipeItem {
                     job:     Job::new(args!["echo", "hello"], RedirectFrom::Stdout),
                     inputs:  Vec::new(),
                     outputs: Vec::new(),
+                    fds:     Vec::new(),
                 },
                 PipeItem {
                     job:     Job::new(args!["cat"], RedirectFrom::None),
@@ -891,6 +1069,7 @@ mod tests {
                         file:   "other".into(),
                         append: true,
                     }],
+                    fds:     Vec::new(),
                 },
             ],
             pipe:  PipeType::Normal,
@@ -910,12 +1089,14 @@ mod tests {
 
                     inputs:  Vec::new(),
                     outputs: Vec::new(),
+                    fds:     Vec::new(),
                 },
                 PipeItem {
                     job: Job::new(args!["echo", "hello"], RedirectFrom::Stdout),
 
                     inputs:  Vec::new(),
                     outputs: Vec::new(),
+                    fds:     Vec::new(),
                 },
                 PipeItem {
                     job: Job::new(args!["cat"], RedirectFrom::None),
@@ -926,6 +1107,7 @@ mod tests {
                         file:   "other".into(),
                         append: true,
                     }],
+                    fds:     Vec::new(),
                 },
             ],
             pipe:  PipeType::Normal,
@@ -983,12 +1165,54 @@ mod tests {
 
                 inputs:  vec![Input::HereString("$(cat math.txt)".into())],
                 outputs: vec![],
+                fds:     Vec::new(),
             }],
             pipe:  PipeType::Normal,
         };
         assert_eq!(Statement::Pipeline(expected), parse(input, &BuiltinMap::new()).unwrap());
     }
 
+    #[test]
+    fn heredocs() {
+        let input = "cat <<EOF\n $HOME;\n  'two'\nEOF\n | tr a b <<-'END'\n  one\n\t END\n > out";
+        let expected = Pipeline {
+            items: vec![
+                PipeItem {
+                    job: Job::new(args!["cat"], RedirectFrom::Stdout),
+
+                    inputs:  vec![Input::Heredoc(" $HOME;\n  'two'\n".into(), true)],
+                    outputs: Vec::new(),
+                    fds:     Vec::new(),
+                },
+                PipeItem {
+                    job: Job::new(args!["tr", "a", "b"], RedirectFrom::None),
+
+                    inputs:  vec![Input::Heredoc("one\n".into(), false)],
+                    outputs: vec![Redirection {
+                        from:   RedirectFrom::Stdout,
+                        file:   "out".into(),
+                        append: false,
+                    }],
+                    fds:     Vec::new(),
+                },
+            ],
+            pipe:  PipeType::Normal,
+        };
+        assert_eq!(parse(input, &BuiltinMap::new()).unwrap(), Statement::Pipeline(expected));
+    }
+
+    #[test]
+    fn invalid_heredocs() {
+        assert_eq!(
+            super::Collector::new("cat << | tr a b").parse(),
+            Err(super::PipelineParsingError::NoHeredocDelimiter)
+        );
+        assert_eq!(
+            super::Collector::new("cat <<EOF\none\n EOF").parse(),
+            Err(super::PipelineParsingError::UnterminatedHeredoc("EOF".into()))
+        );
+    }
+
     #[test]
     // FIXME: May need updating after resolution of which part of the pipe
     // the input redirection shoud be associated with.
@@ -1001,6 +1225,7 @@ mod tests {
 
                     inputs:  Vec::new(),
                     outputs: Vec::new(),
+                    fds:     Vec::new(),
                 },
                 PipeItem {
                     job: Job::new(args!["tr", "'o'", "'x'"], RedirectFrom::None),
@@ -1011,6 +1236,7 @@ mod tests {
                         file:   "out.log".into(),
                         append: false,
                     }],
+                    fds:     Vec::new(),
                 },
             ],
             pipe:  PipeType::Normal,
@@ -1047,12 +1273,160 @@ mod tests {
                     file:   "foo\\'bar".into(),
                     append: true,
                 }],
+                fds:     Vec::new(),
+            }],
+            pipe:  PipeType::Normal,
+        };
+        assert_eq!(parse(input, &BuiltinMap::new()).unwrap(), Statement::Pipeline(expected));
+    }
+
+    #[test]
+    fn env_prefixes() {
+        let input = "LANG=C TZ='Europe/Paris' date +%H | A=1 cat x=y | B=2";
+        let expected = Pipeline {
+            items: vec![
+                PipeItem {
+                    job:     Job::new(args!["date", "+%H"], RedirectFrom::Stdout).with_env(vec![
+                        ("LANG".into(), "C".into()),
+                        ("TZ".into(), "'Europe/Paris'".into()),
+                    ]),
+                    inputs:  Vec::new(),
+                    outputs: Vec::new(),
+                    fds:     Vec::new(),
+                },
+                PipeItem {
+                    job:     Job::new(args!["cat", "x=y"], RedirectFrom::Stdout)
+                        .with_env(vec![("A".into(), "1".into())]),
+                    inputs:  Vec::new(),
+                    outputs: Vec::new(),
+                    fds:     Vec::new(),
+                },
+                PipeItem {
+                    job:     Job::new(args!["B=2"], RedirectFrom::None),
+                    inputs:  Vec::new(),
+                    outputs: Vec::new(),
+                    fds:     Vec::new(),
+                },
+            ],
+            pipe:  PipeType::Normal,
+        };
+        assert_eq!(parse(input, &BuiltinMap::new()).unwrap(), Statement::Pipeline(expected));
+    }
+
+    #[test]
+    fn numbered_fd_redirections() {
+        let input = "cmd 3> log 4>> all 5< in 1> out 2>> err 0< stdin args";
+        let expected = Pipeline {
+            items: vec![PipeItem {
+                job:     Job::new(args!["cmd", "args"], RedirectFrom::None),
+                inputs:  vec![Input::File("stdin".into())],
+                outputs: vec![
+                    Redirection {
+                        from:   RedirectFrom::Stdout,
+                        file:   "out".into(),
+                        append: false,
+                    },
+                    Redirection {
+                        from:   RedirectFrom::Stderr,
+                        file:   "err".into(),
+                        append: true,
+                    },
+                ],
+                fds:     vec![
+                    FdRedirection::File { fd: 3, file: "log".into(), mode: FdMode::Write },
+                    FdRedirection::File { fd: 4, file: "all".into(), mode: FdMode::Append },
+                    FdRedirection::File { fd: 5, file: "in".into(), mode: FdMode::Read },
+                ],
             }],
             pipe:  PipeType::Normal,
         };
         assert_eq!(parse(input, &BuiltinMap::new()).unwrap(), Statement::Pipeline(expected));
     }
 
+    #[test]
+    fn fd_duplication() {
+        let input = "cmd 2>&1 >&2 <&4 3<&0 >&- 7>&- | cat 2 >file";
+        let expected = Pipeline {
+            items: vec![
+                PipeItem {
+                    job:     Job::new(args!["cmd"], RedirectFrom::Stdout),
+                    inputs:  Vec::new(),
+                    outputs: Vec::new(),
+                    fds:     vec![
+                        FdRedirection::Duplicate { fd: 2, source: 1 },
+                        FdRedirection::Duplicate { fd: 1, source: 2 },
+                        FdRedirection::Duplicate { fd: 0, source: 4 },
+                        FdRedirection::Duplicate { fd: 3, source: 0 },
+                        FdRedirection::Close(1),
+                        FdRedirection::Close(7),
+                    ],
+                },
+                PipeItem {
+                    job:     Job::new(args!["cat", "2"], RedirectFrom::None),
+                    inputs:  Vec::new(),
+                    outputs: vec![Redirection {
+                        from:   RedirectFrom::Stdout,
+                        file:   "file".into(),
+                        append: false,
+                    }],
+                    fds:     Vec::new(),
+                },
+            ],
+            pipe:  PipeType::Normal,
+        };
+        assert_eq!(parse(input, &BuiltinMap::new()).unwrap(), Statement::Pipeline(expected));
+    }
+
+    #[test]
+    fn process_substitutions() {
+        let input = "diff <(sort a) <(sort b) > out | tee >(gzip > x.gz) < <(echo in)";
+        let expected = Pipeline {
+            items: vec![
+                PipeItem {
+                    job:     Job::new(
+                        args!["diff", "<(sort a)", "<(sort b)"],
+                        RedirectFrom::Stdout,
+                    ),
+                    inputs:  Vec::new(),
+                    outputs: vec![Redirection {
+                        from:   RedirectFrom::Stdout,
+                        file:   "out".into(),
+                        append: false,
+                    }],
+                    fds:     Vec::new(),
+                },
+                PipeItem {
+                    job:     Job::new(args!["tee", ">(gzip > x.gz)"], RedirectFrom::None),
+                    inputs:  vec![Input::File("<(echo in)".into())],
+                    outputs: Vec::new(),
+                    fds:     Vec::new(),
+                },
+            ],
+            pipe:  PipeType::Normal,
+        };
+        assert_eq!(parse(input, &BuiltinMap::new()).unwrap(), Statement::Pipeline(expected));
+    }
+
+    #[test]
+    fn invalid_fd_targets() {
+        assert_eq!(
+            super::Collector::new("cmd >&file").parse(),
+            Err(super::PipelineParsingError::InvalidFdTarget("file".into()))
+        );
+        assert_eq!(
+            super::Collector::new("cmd 2>&").parse(),
+            Err(super::PipelineParsingError::NoFdTarget)
+        );
+        assert_eq!(
+            super::Collector::new("cmd 11>file").parse(),
+            Err(super::PipelineParsingError::FdOutOfRange(11))
+        );
+        assert_eq!(
+            super::Collector::new("cmd 2>&12").parse(),
+            Err(super::PipelineParsingError::FdOutOfRange(12))
+        );
+    }
+
     fn assert_parse_error(s: &str) {
         assert!(super::Collector::new(s).parse().is_err());
     }
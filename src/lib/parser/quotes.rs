@@ -1,4 +1,5 @@
-use std::{iter::Peekable, str};
+use super::{heredoc::Heredoc, Span};
+use std::{collections::VecDeque, iter::Peekable, ops::Range, rc::Rc, str};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum Quotes {
@@ -22,6 +23,15 @@ pub struct Terminator<I: Iterator<Item = u8>> {
     whitespace: bool,
     empty:      bool,
     subshell:   usize,
+    group:      usize,
+    // Whether the next word is in command position, where a `(` opens a group
+    command:    bool,
+    parens:     usize,
+    positions:  Vec<usize>,
+    // The rest of a line that opens heredocs, held back until their bodies have been read
+    heredocs:   Vec<usize>,
+    line:       Vec<(u8, usize)>,
+    queue:      VecDeque<(u8, usize)>,
 }
 
 impl<'a> From<&'a str> for Terminator<std::str::Bytes<'a>> {
@@ -33,6 +43,7 @@ pub struct RearPeekable<I: Iterator> {
     iter: Peekable<I>,
     now:  Option<I::Item>,
     last: Option<I::Item>,
+    raw:  Vec<I::Item>,
 }
 
 impl<I> Iterator for RearPeekable<I>
@@ -46,6 +57,9 @@ where
     fn next(&mut self) -> Option<I::Item> {
         self.last = self.now;
         self.now = self.iter.next();
+        if let Some(item) = self.now {
+            self.raw.push(item);
+        }
         self.now
     }
 
@@ -69,14 +83,61 @@ impl<I: Iterator<Item = u8>> Iterator for Terminator<I> {
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        if self.terminated {
-            return None;
+        loop {
+            if let Some((character, position)) = self.queue.pop_front() {
+                self.positions.push(position);
+                return Some(character);
+            } else if self.terminated {
+                return None;
+            }
+
+            let next = self.read();
+            if !self.queue.is_empty() {
+                continue;
+            } else if self.heredocs.is_empty() {
+                return next;
+            }
+
+            match next {
+                Some(character) => {
+                    let position = self.positions.pop().unwrap_or_default();
+                    self.line.push((character, position));
+                }
+                // The input ended before the bodies of the heredocs
+                None => {
+                    self.heredocs.clear();
+                    self.queue.extend(self.line.drain(..));
+                    if self.queue.is_empty() {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<I: Iterator<Item = u8>> Terminator<I> {
+    /// Consumes lines until a statement is formed or the iterator runs dry, and returns the
+    /// underlying `String`.
+    pub fn terminate(&mut self) -> Option<String> {
+        let stmt = self.collect::<Vec<_>>();
+        let stmt = unsafe { String::from_utf8_unchecked(stmt) };
+
+        if self.empty {
+            None
+        } else {
+            Some(stmt)
         }
+    }
 
+    fn read(&mut self) -> Option<u8> {
+        let emitted = self.positions.len();
         let prev_whitespace = self.whitespace;
         self.whitespace = false;
 
-        let mut next = if prev_whitespace && self.array == 0 && !self.and_or && !self.empty {
+        let mut next = if prev_whitespace
+            && ((self.array == 0 && !self.and_or && !self.empty) || !self.heredocs.is_empty())
+        {
             self.inner.find(|&c| c == b'\n' || !c.is_ascii_whitespace())
         } else if prev_whitespace {
             self.inner.find(|&c| !c.is_ascii_whitespace())
@@ -96,26 +157,47 @@ impl<I: Iterator<Item = u8>> Iterator for Terminator<I> {
         } else if let Some(character) = next {
             next = self.handle_char(character, prev_whitespace);
             self.empty &= character.is_ascii_whitespace();
+            if let Some(character) = next.filter(|c| !c.is_ascii_whitespace()) {
+                self.command = [b';', b'&', b'|', b'(', b'{'].contains(&character);
+            }
         } else if self.subshell == 0 && self.array == 0 && !self.and_or && !self.empty {
             self.terminated = true;
         }
 
+        // Remember where each byte was read from, unless a line continuation already did
+        if next.is_some() && self.positions.len() == emitted {
+            self.positions.push(self.inner.raw.len() - 1);
+        }
+
         next
     }
-}
 
-impl<I: Iterator<Item = u8>> Terminator<I> {
-    /// Consumes lines until a statement is formed or the iterator runs dry, and returns the
-    /// underlying `String`.
-    pub fn terminate(&mut self) -> Option<String> {
-        let stmt = self.collect::<Vec<_>>();
-        let stmt = unsafe { String::from_utf8_unchecked(stmt) };
+    /// The number of lines that were consumed from the underlying input
+    pub fn lines(&self) -> usize { self.inner.raw.iter().filter(|&&c| c == b'\n').count() }
 
-        if self.empty {
-            None
-        } else {
-            Some(stmt)
-        }
+    /// The location of the `range` of bytes of the terminated statement, where `line` is the
+    /// number of the first line that was consumed from the input.
+    pub fn span(&self, file: Option<Rc<str>>, line: usize, range: Range<usize>) -> Span {
+        let raw = &self.inner.raw;
+        let start = self.positions.get(range.start).copied().unwrap_or_default();
+        let end = range
+            .end
+            .checked_sub(1)
+            .and_then(|last| self.positions.get(last))
+            .map_or(start, |&last| last + 1)
+            .max(start);
+        let line_start = raw[..start].iter().rposition(|&c| c == b'\n').map_or(0, |pos| pos + 1);
+        let line_end =
+            raw[start..].iter().position(|&c| c == b'\n').map_or(raw.len(), |pos| start + pos);
+
+        let chars = |bytes: &[u8]| String::from_utf8_lossy(bytes).chars().count();
+        Span::new(
+            file,
+            line + raw[..line_start].iter().filter(|&&c| c == b'\n').count(),
+            chars(&raw[line_start..start]) + 1,
+            chars(&raw[start..end.min(line_end)]),
+            String::from_utf8_lossy(&raw[line_start..line_end]).into(),
+        )
     }
 
     fn handle_char(&mut self, character: u8, prev_whitespace: bool) -> Option<u8> {
@@ -128,7 +210,9 @@ impl<I: Iterator<Item = u8>> Terminator<I> {
                 self.quotes = Quotes::Double;
                 Some(b'"')
             }
-            b'(' if self.inner.prev() == Some(&b'$') || self.inner.prev() == Some(&b'@') => {
+            b'(' if [Some(&b'$'), Some(&b'@'), Some(&b'<'), Some(&b'>')]
+                .contains(&self.inner.prev()) =>
+            {
                 self.subshell += 1;
                 Some(b'(')
             }
@@ -136,6 +220,42 @@ impl<I: Iterator<Item = u8>> Terminator<I> {
                 self.subshell -= 1;
                 Some(b')')
             }
+            // Groups of statements may span lines, which then separate their statements
+            b'(' if self.subshell == 0 && self.array == 0 && self.command => {
+                self.group += 1;
+                self.and_or = false;
+                Some(b'(')
+            }
+            // Any other parentheses, as those of methods, only have to be matched, so that they
+            // don't close a group
+            b'(' if self.subshell == 0 && self.array == 0 => {
+                self.parens += 1;
+                self.and_or = false;
+                Some(b'(')
+            }
+            b')' if self.parens > 0 => {
+                self.parens -= 1;
+                self.and_or = false;
+                Some(b')')
+            }
+            b'{' if self.subshell == 0
+                && self.array == 0
+                && self.inner.peek().map_or(false, |&c| c == b';' || c.is_ascii_whitespace()) =>
+            {
+                self.group += 1;
+                self.and_or = false;
+                Some(b'{')
+            }
+            b')' | b'}'
+                if self.group > 0
+                    && (character == b')'
+                        || [Some(&b' '), Some(&b'\t'), Some(&b'\n'), Some(&b';')]
+                            .contains(&self.inner.prev())) =>
+            {
+                self.group -= 1;
+                self.and_or = false;
+                Some(character)
+            }
             b'[' => {
                 self.array += 1;
                 Some(b'[')
@@ -146,9 +266,10 @@ impl<I: Iterator<Item = u8>> Terminator<I> {
             }
             b'#' if prev_whitespace || self.inner.prev().is_none() => {
                 self.inner.find(|&c| c == b'\n');
-                if self.array == 0 && self.subshell == 0 && !self.and_or && !self.empty {
-                    self.terminated = true;
-                    None
+                if !self.heredocs.is_empty() {
+                    self.read_heredocs()
+                } else if self.array == 0 && self.subshell == 0 && !self.and_or && !self.empty {
+                    self.end_of_line()
                 } else {
                     self.whitespace = true;
                     Some(b' ')
@@ -158,19 +279,23 @@ impl<I: Iterator<Item = u8>> Terminator<I> {
                 if self.inner.peek() == Some(&b'\n') {
                     self.whitespace = true;
                     self.inner.next();
-                    self.next()
+                    self.read()
                 } else {
                     self.skip_next = true;
                     Some(character)
                 }
             }
+            b'<' if self.opens_heredoc() => {
+                self.heredocs.push(self.line.len() + 1);
+                Some(b'<')
+            }
+            b'\n' if !self.heredocs.is_empty() => self.read_heredocs(),
             b'&' | b'|' if self.inner.prev() == Some(&character) => {
                 self.and_or = true;
                 Some(character)
             }
             b'\n' if self.array == 0 && self.subshell == 0 && !self.and_or && !self.empty => {
-                self.terminated = true;
-                None
+                self.end_of_line()
             }
             _ if character.is_ascii_whitespace() => {
                 self.whitespace = true;
@@ -183,10 +308,90 @@ impl<I: Iterator<Item = u8>> Terminator<I> {
         }
     }
 
+    /// Whether the `<` that was just read completes a `<<` operator, which is neither part of a
+    /// herestring nor of a process substitution
+    fn opens_heredoc(&mut self) -> bool {
+        let next = self.inner.peek().copied();
+        let raw = &self.inner.raw;
+        self.subshell == 0
+            && self.array == 0
+            && raw.len() >= 2
+            && raw[raw.len() - 2] == b'<'
+            && (raw.len() < 3 || raw[raw.len() - 3] != b'<')
+            && next != Some(b'<')
+            && next != Some(b'(')
+    }
+
+    /// Read the bodies of the heredocs that were opened on the line which just ended, and queue
+    /// the line with each body placed right after the header that introduced it.
+    fn read_heredocs(&mut self) -> Option<u8> {
+        let newline = self.inner.raw.len() - 1;
+        let line = std::mem::replace(&mut self.line, Vec::new());
+        let bytes = line.iter().map(|&(c, _)| c).collect::<Vec<_>>();
+        let mut read = 0;
+        let mut unterminated = false;
+
+        for start in std::mem::replace(&mut self.heredocs, Vec::new()) {
+            let (heredoc, len) = match Heredoc::parse(&bytes[start..]) {
+                Some(header) if !unterminated => header,
+                _ => continue,
+            };
+            self.queue.extend(&line[read..start + len]);
+            self.queue.push_back((b'\n', newline));
+            read = start + len;
+
+            loop {
+                let begin = self.inner.raw.len();
+                let end = self.inner.find(|&c| c == b'\n');
+                let raw = &self.inner.raw;
+                if heredoc.is_end(&raw[begin..raw.len() - end.map_or(0, |_| 1)]) {
+                    let position = self.inner.raw.len() - 1;
+                    let closing = heredoc.delimiter().iter().chain(b"\n");
+                    self.queue.extend(closing.map(|&c| (c, position)));
+                    break;
+                }
+                self.queue.extend(self.inner.raw[begin..].iter().copied().zip(begin..));
+                if end.is_none() {
+                    unterminated = true;
+                    break;
+                }
+            }
+        }
+        self.queue.extend(&line[read..]);
+
+        let next = if self.array == 0 && self.subshell == 0 && !self.and_or {
+            self.end_of_line()
+        } else {
+            self.whitespace = true;
+            Some(b' ')
+        };
+        if let Some(character) = next {
+            self.queue.push_back((character, newline));
+        }
+        None
+    }
+
+    /// A line that ends a statement terminates it, unless the statement is part of a group
+    fn end_of_line(&mut self) -> Option<u8> {
+        if self.group > 0 {
+            self.whitespace = true;
+            self.command = true;
+            Some(b';')
+        } else {
+            self.terminated = true;
+            None
+        }
+    }
+
     /// Create a new reader on the provided input
     pub fn new(inner: I) -> Self {
         Self {
-            inner:      RearPeekable { iter: inner.peekable(), now: None, last: None },
+            inner:      RearPeekable {
+                iter: inner.peekable(),
+                now:  None,
+                last: None,
+                raw:  Vec::new(),
+            },
             array:      0,
             skip_next:  false,
             quotes:     Quotes::None,
@@ -195,6 +400,82 @@ impl<I: Iterator<Item = u8>> Terminator<I> {
             whitespace: false,
             empty:      true,
             subshell:   0,
+            group:      0,
+            command:    true,
+            parens:     0,
+            positions:  Vec::new(),
+            heredocs:   Vec::new(),
+            line:       Vec::new(),
+            queue:      VecDeque::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans() {
+        let mut bytes = "\necho  one; echo \\\n  two\nfalse".bytes();
+
+        let mut terminator = Terminator::new(&mut bytes);
+        assert_eq!(terminator.terminate(), Some(" echo one; echo two".into()));
+        let span = terminator.span(None, 1, 11..19);
+        assert_eq!((span.line(), span.column(), span.source()), (2, 12, "echo  one; echo \\"));
+        assert_eq!(terminator.lines(), 3);
+
+        let mut terminator = Terminator::new(&mut bytes);
+        assert_eq!(terminator.terminate(), Some("false".into()));
+        let span = terminator.span(None, 4, 0..5);
+        assert_eq!((span.line(), span.column(), span.source()), (4, 1, "false"));
+    }
+
+    #[test]
+    fn heredocs() {
+        let mut bytes = "cat <<EOF | tr a b; cat <<-'END'   # comment\n a  'b\n# c\nEOF\n  x\n  \
+                         END\necho <<<x\n"
+            .bytes();
+
+        let mut terminator = Terminator::new(&mut bytes);
+        assert_eq!(
+            terminator.terminate(),
+            Some("cat <<EOF\n a  'b\n# c\nEOF\n | tr a b; cat <<-'END'\n  x\nEND\n ".into())
+        );
+        let span = terminator.span(None, 1, 49..52);
+        assert_eq!((span.line(), span.column(), span.source()), (5, 1, "  x"));
+        assert_eq!(terminator.lines(), 6);
+
+        let mut terminator = Terminator::new(&mut bytes);
+        assert_eq!(terminator.terminate(), Some("echo <<<x".into()));
+
+        let mut terminator = Terminator::new("cat <<EOF &&\n one\nEOF\necho two\n".bytes());
+        assert_eq!(terminator.terminate(), Some("cat <<EOF\n one\nEOF\n && echo two".into()));
+
+        // Without a closing delimiter, the body extends to the end of the input
+        let mut terminator = Terminator::new("cat <<EOF\n one".bytes());
+        assert_eq!(terminator.terminate(), Some("cat <<EOF\n one".into()));
+    }
+
+    #[test]
+    fn groups() {
+        let mut bytes =
+            "(\n  cd /tmp # comment\n  ls\n) > log\n{ a\n  b; } | c\necho $(a\nb) {a,b}\n".bytes();
+
+        let mut terminator = Terminator::new(&mut bytes);
+        assert_eq!(terminator.terminate(), Some("(;cd /tmp ;ls;) > log".into()));
+        let mut terminator = Terminator::new(&mut bytes);
+        assert_eq!(terminator.terminate(), Some("{ a;b; } | c".into()));
+        let mut terminator = Terminator::new(&mut bytes);
+        assert_eq!(terminator.terminate(), Some("echo $(a b) {a,b}".into()));
+
+        // Only a `(` in command position opens a group
+        let mut bytes = "echo (\necho a(b\ntrue && (a\nb) | (\necho @len(c)\nd)\n".bytes();
+        let mut terminator = Terminator::new(&mut bytes);
+        assert_eq!(terminator.terminate(), Some("echo (".into()));
+        let mut terminator = Terminator::new(&mut bytes);
+        assert_eq!(terminator.terminate(), Some("echo a(b".into()));
+        let mut terminator = Terminator::new(&mut bytes);
+        assert_eq!(terminator.terminate(), Some("true && (a;b) | (;echo @len(c);d)".into()));
+    }
+}
@@ -0,0 +1,117 @@
+use std::{fmt, rc::Rc};
+
+/// The location of a statement in the source it was read from
+///
+/// Along with the line and column of the statement, the span keeps the text of the line where it
+/// starts, so that errors can point to the offending statement.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Span {
+    file:   Option<Rc<str>>,
+    line:   usize,
+    column: usize,
+    length: usize,
+    source: Rc<str>,
+}
+
+impl Span {
+    /// Create a new span. Lines and columns start at 1
+    #[must_use]
+    pub fn new(
+        file: Option<Rc<str>>,
+        line: usize,
+        column: usize,
+        length: usize,
+        source: Rc<str>,
+    ) -> Self {
+        Self { file, line, column, length, source }
+    }
+
+    /// The file the statement was read from, if any
+    #[must_use]
+    pub fn file(&self) -> Option<&str> { self.file.as_deref() }
+
+    /// The line number where the statement starts
+    #[must_use]
+    pub const fn line(&self) -> usize { self.line }
+
+    /// The column where the statement starts, in characters
+    #[must_use]
+    pub const fn column(&self) -> usize { self.column }
+
+    /// The text of the line where the statement starts
+    #[must_use]
+    pub fn source(&self) -> &str { &self.source }
+
+    /// Narrow the span to a statement of `length` characters, starting `offset` characters after
+    /// the current column
+    #[must_use]
+    pub fn narrow(&self, offset: usize, length: usize) -> Self {
+        Self { column: self.column + offset, length, ..self.clone() }
+    }
+}
+
+impl Default for Span {
+    fn default() -> Self { Self::new(None, 0, 0, 0, "".into()) }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = self.line.to_string();
+        let margin = " ".repeat(line.len());
+        let source = self.source.trim_end();
+
+        // Keep the tabulations so that the carets are aligned with the source
+        let indent = source
+            .chars()
+            .take(self.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let length = source.chars().count().saturating_sub(indent.chars().count());
+
+        writeln!(f, "{}--> {}:{}:{}", margin, self.file().unwrap_or("ion"), line, self.column)?;
+        writeln!(f, "{} |", margin)?;
+        writeln!(f, "{} | {}", line, source)?;
+        write!(f, "{} | {}{}", margin, indent, "^".repeat(self.length.min(length).max(1)))
+    }
+}
+
+/// An item along with the location it was parsed from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Spanned<T> {
+    /// The parsed item
+    pub item: T,
+    /// Where the item was found
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    /// Attach a location to an item
+    pub const fn new(item: T, span: Span) -> Self { Self { item, span } }
+}
+
+impl<T> From<T> for Spanned<T> {
+    fn from(item: T) -> Self { Self::new(item, Span::default()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let span = Span::new(Some("script.ion".into()), 12, 5, 9, "    echo $foo\n".into());
+        assert_eq!(
+            span.to_string(),
+            "  --> script.ion:12:5\n   |\n12 |     echo $foo\n   |     ^^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn display_narrowed() {
+        let span = Span::new(None, 3, 2, 19, "\tfoo; bar; echo $foo".into());
+        assert_eq!(
+            span.narrow(10, 9).to_string(),
+            " --> ion:3:12\n  |\n3 | \tfoo; bar; echo $foo\n  | \t          ^^^^^^^^^"
+        );
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{parser::lexers::ArgumentSplitter, shell::flow_control::Case};
+use crate::{
+    expansion::compile_regex, parser::lexers::ArgumentSplitter, shell::flow_control::Case,
+};
 use std::str::FromStr;
 use thiserror::Error;
 
@@ -12,6 +14,18 @@ pub enum Error {
     ExtraBind(String),
     #[error("extra variable, '{0}', was given to case")]
     ExtraVar(String),
+    #[error("{0}")]
+    InvalidRegex(String),
+}
+
+/// The pattern of a regular expression case, written as `re"..."` or `re'...'`
+fn regex_pattern(value: &str) -> Option<&str> {
+    let quote = value.as_bytes().get(2).filter(|&&quote| quote == b'"' || quote == b'\'')?;
+    if value.len() > 3 && value.starts_with("re") && value.as_bytes()[value.len() - 1] == *quote {
+        Some(&value[3..value.len() - 1])
+    } else {
+        None
+    }
 }
 
 impl FromStr for Case {
@@ -72,8 +86,16 @@ impl FromStr for Case {
                 Some(inner) => return Err(Error::ExtraVar(inner.into())),
                 None => (),
             }
+            // Regular expressions are compiled once, when the case is parsed
+            let pattern = argument.and_then(regex_pattern);
+            let regex = match pattern.map(|pattern| compile_regex("case", pattern, true)) {
+                Some(Ok(regex)) => Some(regex),
+                Some(Err(why)) => return Err(Error::InvalidRegex(why.to_string())),
+                None => None,
+            };
             return Ok(Case {
-                value: argument.filter(|&val| val != "_").map(Into::into),
+                value: pattern.or(argument).filter(|&val| val != "_").map(Into::into),
+                regex,
                 binding: binding.map(Into::into),
                 conditional,
                 statements: Vec::new(),
@@ -91,6 +113,7 @@ mod tests {
         assert_eq!(
             Ok(Case {
                 value:       Some("test".into()),
+                regex:       None,
                 binding:     Some("test".into()),
                 conditional: Some("exists".into()),
                 statements:  Vec::new(),
@@ -100,6 +123,7 @@ mod tests {
         assert_eq!(
             Ok(Case {
                 value:       Some("test".into()),
+                regex:       None,
                 binding:     Some("test".into()),
                 conditional: None,
                 statements:  Vec::new(),
@@ -109,11 +133,24 @@ mod tests {
         assert_eq!(
             Ok(Case {
                 value:       Some("test".into()),
+                regex:       None,
                 binding:     None,
                 conditional: None,
                 statements:  Vec::new(),
             }),
             "test".parse::<Case>()
         );
+        assert_eq!(
+            Ok(Case {
+                value:       Some(r"(\d+)-(\w+)".into()),
+                regex:       compile_regex("case", r"(\d+)-(\w+)", true).ok(),
+                binding:     Some("groups".into()),
+                conditional: None,
+                statements:  Vec::new(),
+            }),
+            r#"re"(\d+)-(\w+)" @ groups"#.parse::<Case>()
+        );
+        assert_eq!(Some("re".into()), "re".parse::<Case>().unwrap().value);
+        assert!(r#"re"(unclosed""#.parse::<Case>().is_err());
     }
 }
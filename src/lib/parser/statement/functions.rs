@@ -1,4 +1,7 @@
-use crate::parser::lexers::assignments::{KeyBuf, KeyIterator, TypeError};
+use crate::parser::lexers::{
+    assignments::{KeyBuf, KeyIterator, Primitive, TypeError},
+    ArgumentSplitter,
+};
 use thiserror::Error;
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Error)]
@@ -7,44 +10,112 @@ pub enum FunctionParseError {
     RepeatedArgument(String),
     #[error("{0}")]
     TypeError(#[source] TypeError),
+    #[error("required argument '{0}' follows an optional one")]
+    RequiredAfterOptional(String),
+    #[error("argument '{0}' follows the rest argument")]
+    ArgumentAfterRest(String),
+    #[error("the rest argument '@{0}' can not be optional, and must be an array")]
+    InvalidRest(String),
+}
+
+/// A parameter of a function
+#[derive(Debug, PartialEq, Clone)]
+pub struct Parameter {
+    /// The name and the type of the parameter
+    pub key:  KeyBuf,
+    /// How the parameter is given its value
+    pub kind: ParameterKind,
+}
+
+/// How a parameter is given its value by the arguments of a call
+#[derive(Debug, PartialEq, Clone)]
+pub enum ParameterKind {
+    /// The argument must be supplied: `name:type`
+    Required,
+    /// The variable is not defined when the argument is missing: `name:type?`
+    Optional,
+    /// The value to expand when the argument is missing: `name:type=value`
+    Default(String),
+    /// All the remaining arguments, as an array: `@name:[type]`
+    Rest,
+}
+
+impl From<KeyBuf> for Parameter {
+    fn from(key: KeyBuf) -> Self { Self { key, kind: ParameterKind::Required } }
 }
 
 /// The arguments expression given to a function declaration goes into here, which will be
-/// converted into a tuple consisting of a `KeyIterator` iterator, which will collect type
-/// information, and an optional description of the function.
-pub fn parse_function(arg: &str) -> (KeyIterator<'_>, Option<&str>) {
+/// converted into a tuple consisting of an `ArgumentSplitter` iterator over the declarations of
+/// the parameters, and an optional description of the function.
+pub fn parse_function(arg: &str) -> (ArgumentSplitter<'_>, Option<&str>) {
     let mut parts = arg.splitn(2, "--");
     let (args, description) = (parts.next().unwrap().trim(), parts.next().map(str::trim));
-    (KeyIterator::new(args), description)
+    (ArgumentSplitter::new(args), description)
+}
+
+/// Split the declaration of a parameter into its key and the way it is given its value
+fn parameter_kind(arg: &str) -> (&str, ParameterKind) {
+    if arg.starts_with('@') {
+        (&arg[1..], ParameterKind::Rest)
+    } else if let Some(pos) = arg.find('=') {
+        (&arg[..pos], ParameterKind::Default(arg[pos + 1..].into()))
+    } else if arg.ends_with('?') {
+        (&arg[..arg.len() - 1], ParameterKind::Optional)
+    } else {
+        (arg, ParameterKind::Required)
+    }
 }
 
-/// All type information will be collected from the `KeyIterator` and stored into a vector. If a
-/// type or argument error is detected, then that error will be returned instead. This is required
-/// because of lifetime restrictions on `KeyIterator`, which will not live for the remainder of the
-/// declared function's lifetime.
-pub fn collect_arguments(args: KeyIterator<'_>) -> Result<Vec<KeyBuf>, FunctionParseError> {
-    let mut keybuf: Vec<KeyBuf> = Vec::new();
+/// All type information will be collected from the declarations of the parameters and stored into
+/// a vector. If a type or argument error is detected, then that error will be returned instead.
+/// This is required because of lifetime restrictions on `KeyIterator`, which will not live for the
+/// remainder of the declared function's lifetime.
+pub fn collect_arguments(args: ArgumentSplitter<'_>) -> Result<Vec<Parameter>, FunctionParseError> {
+    let mut parameters: Vec<Parameter> = Vec::new();
     for arg in args {
-        match arg {
-            Ok(key) => {
-                let key: KeyBuf = key.into();
-                if keybuf.iter().any(|k| k.name == key.name) {
-                    return Err(FunctionParseError::RepeatedArgument(key.name));
-                } else {
-                    keybuf.push(key);
-                }
+        let (key, kind) = parameter_kind(arg);
+        let mut key: KeyBuf = match KeyIterator::new(key).next() {
+            Some(Ok(key)) => key.into(),
+            Some(Err(e)) => return Err(FunctionParseError::TypeError(e)),
+            None => return Err(FunctionParseError::TypeError(TypeError::Invalid(arg.into()))),
+        };
+
+        if parameters.iter().any(|p| p.key.name == key.name) {
+            return Err(FunctionParseError::RepeatedArgument(key.name));
+        }
+        match (parameters.last().map(|p| &p.kind), &kind) {
+            (Some(ParameterKind::Rest), _) => {
+                return Err(FunctionParseError::ArgumentAfterRest(key.name));
+            }
+            (Some(ParameterKind::Optional), ParameterKind::Required)
+            | (Some(ParameterKind::Default(_)), ParameterKind::Required) => {
+                return Err(FunctionParseError::RequiredAfterOptional(key.name));
             }
-            Err(e) => return Err(FunctionParseError::TypeError(e)),
+            _ => (),
+        }
+        if kind == ParameterKind::Rest {
+            key.kind = match key.kind {
+                _ if arg.ends_with('?') || arg.contains('=') => {
+                    return Err(FunctionParseError::InvalidRest(key.name));
+                }
+                Primitive::Str => Primitive::Array(Box::new(Primitive::Str)),
+                kind @ Primitive::Array(_) => kind,
+                _ => return Err(FunctionParseError::InvalidRest(key.name)),
+            };
         }
+
+        parameters.push(Parameter { key, kind });
     }
-    Ok(keybuf)
+    Ok(parameters)
 }
 
 #[cfg(test)]
 mod tests {
     use crate::parser::{
         lexers::assignments::{KeyBuf, Primitive},
-        statement::functions::{collect_arguments, parse_function, FunctionParseError},
+        statement::functions::{
+            collect_arguments, parse_function, FunctionParseError, Parameter, ParameterKind,
+        },
     };
 
     #[test]
@@ -54,10 +125,11 @@ mod tests {
         assert_eq!(
             args,
             Ok(vec![
-                KeyBuf { name: "a".into(), kind: Primitive::Integer },
-                KeyBuf { name: "b".into(), kind: Primitive::Boolean },
-                KeyBuf { name: "c".into(), kind: Primitive::Array(Box::new(Primitive::Str)) },
-                KeyBuf { name: "d".into(), kind: Primitive::Str },
+                KeyBuf { name: "a".into(), kind: Primitive::Integer }.into(),
+                KeyBuf { name: "b".into(), kind: Primitive::Boolean }.into(),
+                KeyBuf { name: "c".into(), kind: Primitive::Array(Box::new(Primitive::Str)) }
+                    .into(),
+                KeyBuf { name: "d".into(), kind: Primitive::Str }.into(),
             ])
         );
         assert_eq!(description, Some("description"))
@@ -70,4 +142,41 @@ mod tests {
         assert_eq!(args, Err(FunctionParseError::RepeatedArgument("a".into())));
         assert_eq!(description, Some("failed def"));
     }
+
+    #[test]
+    fn function_optional_args() {
+        let (arg_iter, _) = parse_function("a b:int=3 c='x y' d:[int]? @e:[int]");
+        let parameter = |name: &str, kind, param_kind| Parameter {
+            key:  KeyBuf { name: name.into(), kind },
+            kind: param_kind,
+        };
+        let ints = || Primitive::Array(Box::new(Primitive::Integer));
+        assert_eq!(
+            collect_arguments(arg_iter),
+            Ok(vec![
+                parameter("a", Primitive::Str, ParameterKind::Required),
+                parameter("b", Primitive::Integer, ParameterKind::Default("3".into())),
+                parameter("c", Primitive::Str, ParameterKind::Default("'x y'".into())),
+                parameter("d", ints(), ParameterKind::Optional),
+                parameter("e", ints(), ParameterKind::Rest),
+            ])
+        );
+
+        let (arg_iter, _) = parse_function("@rest");
+        assert_eq!(
+            collect_arguments(arg_iter).unwrap()[0].key.kind,
+            Primitive::Array(Box::new(Primitive::Str))
+        );
+
+        let errors = vec![
+            ("a? b", FunctionParseError::RequiredAfterOptional("b".into())),
+            ("a=1 b:int", FunctionParseError::RequiredAfterOptional("b".into())),
+            ("@a b?", FunctionParseError::ArgumentAfterRest("b".into())),
+            ("@a:int", FunctionParseError::InvalidRest("a".into())),
+            ("@a?", FunctionParseError::InvalidRest("a?".into())),
+        ];
+        for (args, error) in errors {
+            assert_eq!(collect_arguments(parse_function(args).0), Err(error));
+        }
+    }
 }
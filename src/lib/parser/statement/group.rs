@@ -0,0 +1,55 @@
+/// A group of statements that is used as a single command of a pipeline.
+///
+/// `(statements)` runs the statements in a fork of the shell, so that changes to the working
+/// directory, variables or functions do not outlive the group, while `{ statements }` runs them
+/// in the shell itself, within a new scope of variables.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Group<'a> {
+    /// The statements of the group
+    pub body:     &'a str,
+    /// Whether the group is run in a fork of the shell
+    pub subshell: bool,
+}
+
+impl<'a> Group<'a> {
+    /// Recognize the group that makes up the command `arg`, if any
+    #[must_use]
+    pub fn parse(arg: &'a str) -> Option<Self> {
+        if arg.starts_with('(') && arg.ends_with(')') {
+            Some(Self { body: &arg[1..arg.len() - 1], subshell: true })
+        } else if Self::opens_block(arg.as_bytes()) && Self::closes_block(arg.as_bytes()) {
+            Some(Self { body: &arg[1..arg.len() - 1], subshell: false })
+        } else {
+            None
+        }
+    }
+
+    /// Whether `data` starts with the brace of a `{ statements }` group, rather than with a
+    /// brace expansion
+    #[must_use]
+    pub fn opens_block(data: &[u8]) -> bool {
+        data.first() == Some(&b'{')
+            && data.get(1).map_or(false, |&c| c == b';' || c.is_ascii_whitespace())
+    }
+
+    fn closes_block(data: &[u8]) -> bool {
+        data.len() >= 3
+            && data.ends_with(b"}")
+            && [b';', b' ', b'\t', b'\n'].contains(&data[data.len() - 2])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups() {
+        assert_eq!(Group::parse("(cd /; ls)"), Some(Group { body: "cd /; ls", subshell: true }));
+        assert_eq!(Group::parse("{ a; b; }"), Some(Group { body: " a; b; ", subshell: false }));
+        assert_eq!(Group::parse("{;echo x }"), Some(Group { body: ";echo x ", subshell: false }));
+        assert_eq!(Group::parse("{a,b}"), None);
+        assert_eq!(Group::parse("{ a}"), None);
+        assert_eq!(Group::parse("echo"), None);
+    }
+}
@@ -1,17 +1,26 @@
 mod case;
 mod functions;
+mod group;
 mod parse;
 mod splitter;
 
+#[cfg(test)]
+pub use self::parse::parse;
 pub use self::{
-    parse::parse,
+    functions::{Parameter, ParameterKind},
+    group::Group,
+    parse::parse_at,
     splitter::{StatementSplitter, StatementVariant},
 };
 use super::{
     pipelines::PipelineParsingError,
     statement::{case::Error as CaseError, functions::FunctionParseError},
+    Span, Spanned,
+};
+use crate::{
+    builtins::BuiltinMap,
+    shell::{flow_control::Statement, BlockError},
 };
-use crate::{builtins::BuiltinMap, shell::flow_control::Statement};
 use std::char;
 use thiserror::Error;
 
@@ -68,12 +77,30 @@ pub enum Error {
         Function names may only contain alphanumeric characters"
     )]
     InvalidFunctionName(String),
+    /// A block was deferred
+    #[error("only a single statement may be deferred, not a block")]
+    DeferredBlock,
+    /// A statement leaving a loop or a function was deferred
+    #[error("control flow statements such as `break` and `return` can not be deferred")]
+    DeferredJump,
+    /// An import was not given a module, optionally followed by a namespace
+    #[error("usage: import MODULE [as NAMESPACE]")]
+    InvalidImport,
+    /// The variable of a catch block was invalid
+    #[error("'{0}' is not a valid variable name for the caught error")]
+    InvalidCatchVariable(String),
     /// The arguments did not match the function's signature
     #[error("function argument error: {0}")]
     InvalidFunctionArgument(#[source] FunctionParseError),
+    /// A group of statements was given arguments
+    #[error("unexpected argument '{0}' after a group of statements")]
+    GroupArgument(String),
     /// Error occured during parsing of a pipeline
     #[error("{0}")]
     Pipeline(#[source] PipelineParsingError),
+    /// The statements of a group did not form complete blocks
+    #[error("{0}")]
+    Block(#[source] BlockError),
 }
 
 impl From<FunctionParseError> for Error {
@@ -88,14 +115,25 @@ impl From<PipelineParsingError> for Error {
     fn from(cause: PipelineParsingError) -> Self { Error::Pipeline(cause) }
 }
 
+impl From<BlockError> for Error {
+    fn from(cause: BlockError) -> Self { Error::Block(cause) }
+}
+
 /// Parses a given statement string and return's the corresponding mapped
-/// `Statement`
-pub fn parse_and_validate<'b>(statement: StatementVariant, builtins: &BuiltinMap<'b>) -> Result {
-    match statement {
+/// `Statement`, along with the location it was found at
+pub fn parse_and_validate<'b>(
+    statement: StatementVariant,
+    span: Span,
+    builtins: &BuiltinMap<'b>,
+) -> std::result::Result<Spanned<Statement>, Error> {
+    let statement = match statement {
         StatementVariant::And(statement) => {
-            Ok(Statement::And(Box::new(parse(statement, builtins)?)))
+            Statement::And(Box::new(parse_at(statement, &span, builtins)?))
+        }
+        StatementVariant::Or(statement) => {
+            Statement::Or(Box::new(parse_at(statement, &span, builtins)?))
         }
-        StatementVariant::Or(statement) => Ok(Statement::Or(Box::new(parse(statement, builtins)?))),
-        StatementVariant::Default(statement) => parse(statement, builtins),
-    }
+        StatementVariant::Default(statement) => parse_at(statement, &span, builtins)?,
+    };
+    Ok(Spanned::new(statement, span))
 }
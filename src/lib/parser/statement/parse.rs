@@ -1,30 +1,75 @@
 use super::{
-    super::pipelines,
+    super::{pipelines, Span},
     functions::{collect_arguments, parse_function},
-    Error,
+    parse_and_validate, Error, Group, StatementSplitter,
 };
 use crate::{
     builtins::BuiltinMap,
+    expansion::pipelines::Pipeline,
     parser::lexers::{assignment_lexer, ArgumentSplitter},
     shell::{
-        flow_control::{Case, ElseIf, ExportAction, IfMode, LocalAction, Statement},
+        flow_control::{
+            Block, Case, Catch, ElseIf, ExportAction, IfMode, LocalAction, Statement, TryMode,
+        },
         variables::Variables,
+        BlockError, Job, Shell,
     },
     types,
 };
-use std::char;
+use std::{char, rc::Rc};
 
+/// Parse the statements of a group into a complete block, attributing them to `span`
+fn group_block(body: &str, span: &Span, builtins: &BuiltinMap<'_>) -> Result<Block, Error> {
+    let mut flow_control = Block::new();
+    let mut block = Block::new();
+    for statement in StatementSplitter::new(body) {
+        let statement = parse_and_validate(statement?, span.clone(), builtins)?;
+        if let Some(statement) = Shell::insert_statement(&mut flow_control, statement)? {
+            block.push(statement);
+        }
+    }
+    match flow_control.last() {
+        Some(statement) => Err(BlockError::UnclosedBlock(statement.item.to_string()).into()),
+        None => Ok(block),
+    }
+}
+
+/// Collect the pipeline in `cmd`, along with the statements of its groups
+fn pipeline(cmd: &str, span: &Span, builtins: &BuiltinMap<'_>) -> Result<Pipeline<Job>, Error> {
+    let mut pipeline = pipelines::Collector::run(cmd, builtins)?;
+    for item in &mut pipeline.items {
+        if let Some(group) = Group::parse(item.job.command()) {
+            if let Some(arg) = item.job.args.get(1) {
+                return Err(Error::GroupArgument(arg.to_string()));
+            }
+            let body = group_block(group.body, span, builtins)?;
+            item.job.group = Some((Rc::new(body), group.subshell));
+        }
+    }
+    Ok(pipeline)
+}
+
+/// Parse the statement in `code`, without a location for the statements of its groups
+#[cfg(test)]
 pub fn parse(code: &str, builtins: &BuiltinMap<'_>) -> super::Result {
+    parse_at(code, &Span::default(), builtins)
+}
+
+/// Parse the statement in `code`, attributing the statements of its groups to `span`
+pub fn parse_at(code: &str, span: &Span, builtins: &BuiltinMap<'_>) -> super::Result {
     let cmd = code.trim();
     match cmd {
         "return" => Ok(Statement::Return(None)),
         _ if cmd.starts_with("return ") => {
             Ok(Statement::Return(Some(cmd[7..].trim_start().into())))
         }
+        _ if cmd.starts_with("return-value ") => {
+            Ok(Statement::ReturnValue(cmd[13..].trim_start().into()))
+        }
         "end" => Ok(Statement::End),
         "break" => Ok(Statement::Break),
         "continue" => Ok(Statement::Continue),
-        "for" | "match" | "case" => Err(Error::IncompleteFlowControl),
+        "for" | "match" | "case" | "defer" | "return-value" => Err(Error::IncompleteFlowControl),
         "let" => Ok(Statement::Let(LocalAction::List)),
         _ if cmd.starts_with("let ") => {
             // Split the let expression and ensure that the statement is valid.
@@ -60,7 +105,7 @@ pub fn parse(code: &str, builtins: &BuiltinMap<'_>) -> super::Result {
             }
         }
         _ if cmd.starts_with("if ") => Ok(Statement::If {
-            expression: vec![parse(cmd[3..].trim_start(), builtins)?],
+            expression: vec![parse_at(cmd[3..].trim_start(), span, builtins)?],
             success:    Vec::new(),
             else_if:    Vec::new(),
             failure:    Vec::new(),
@@ -71,15 +116,30 @@ pub fn parse(code: &str, builtins: &BuiltinMap<'_>) -> super::Result {
             let cmd = cmd[4..].trim_start();
             if !cmd.is_empty() && cmd.starts_with("if ") {
                 Ok(Statement::ElseIf(ElseIf {
-                    expression: vec![parse(cmd[3..].trim_start(), builtins)?],
+                    expression: vec![parse_at(cmd[3..].trim_start(), span, builtins)?],
                     success:    Vec::new(),
                 }))
             } else {
                 Ok(Statement::Else)
             }
         }
+        "try" => Ok(Statement::Try {
+            body:    Vec::new(),
+            catch:   None,
+            finally: Vec::new(),
+            mode:    TryMode::Try,
+        }),
+        "catch" => Ok(Statement::Catch(Catch { binding: None, statements: Vec::new() })),
+        _ if cmd.starts_with("catch ") => {
+            let name = cmd[6..].trim_start();
+            if !Variables::is_valid_name(name) {
+                return Err(Error::InvalidCatchVariable(name.into()));
+            }
+            Ok(Statement::Catch(Catch { binding: Some(name.into()), statements: Vec::new() }))
+        }
+        "finally" => Ok(Statement::Finally),
         _ if cmd.starts_with("while ") => {
-            let pipeline = pipelines::Collector::run(cmd[6..].trim_start(), builtins)?;
+            let pipeline = pipeline(cmd[6..].trim_start(), span, builtins)?;
             Ok(Statement::While {
                 expression: vec![Statement::Pipeline(pipeline)],
                 statements: Vec::new(),
@@ -123,32 +183,51 @@ pub fn parse(code: &str, builtins: &BuiltinMap<'_>) -> super::Result {
                 statements:  Vec::new(),
             })
         }
+        _ if cmd.starts_with("import ") => {
+            let args: Vec<&str> = ArgumentSplitter::new(cmd[7..].trim_start()).collect();
+            match args[..] {
+                [module] => Ok(Statement::Import { module: module.into(), namespace: None }),
+                [module, "as", namespace] => Ok(Statement::Import {
+                    module:    module.into(),
+                    namespace: Some(namespace.into()),
+                }),
+                _ => Err(Error::InvalidImport),
+            }
+        }
+        "import" => Err(Error::InvalidImport),
         _ if cmd.starts_with("time ") => {
             // Ignore embedded time calls
             let mut timed = cmd[4..].trim_start();
             while timed.starts_with("time ") {
                 timed = timed[4..].trim_start();
             }
-            Ok(Statement::Time(Box::new(parse(timed, builtins)?)))
+            Ok(Statement::Time(Box::new(parse_at(timed, span, builtins)?)))
         }
         _ if cmd.eq("time") => Ok(Statement::Time(Box::new(Statement::Default))),
+        _ if cmd.starts_with("defer ") => match parse_at(cmd[6..].trim_start(), span, builtins)? {
+            statement if statement.is_block() || statement == Statement::End => {
+                Err(Error::DeferredBlock)
+            }
+            statement if statement.is_jump() => Err(Error::DeferredJump),
+            statement => Ok(Statement::Defer(Box::new(statement))),
+        },
         _ if cmd.starts_with("and ") => {
-            Ok(Statement::And(Box::new(parse(cmd[3..].trim_start(), builtins)?)))
+            Ok(Statement::And(Box::new(parse_at(cmd[3..].trim_start(), span, builtins)?)))
         }
         _ if cmd.eq("and") => Ok(Statement::And(Box::new(Statement::Default))),
         _ if cmd.starts_with("or ") => {
-            Ok(Statement::Or(Box::new(parse(cmd[2..].trim_start(), builtins)?)))
+            Ok(Statement::Or(Box::new(parse_at(cmd[2..].trim_start(), span, builtins)?)))
         }
         _ if cmd.eq("or") => Ok(Statement::Or(Box::new(Statement::Default))),
         _ if cmd.starts_with("not ") => {
-            Ok(Statement::Not(Box::new(parse(cmd[3..].trim_start(), builtins)?)))
+            Ok(Statement::Not(Box::new(parse_at(cmd[3..].trim_start(), span, builtins)?)))
         }
         _ if cmd.starts_with("! ") => {
-            Ok(Statement::Not(Box::new(parse(cmd[1..].trim_start(), builtins)?)))
+            Ok(Statement::Not(Box::new(parse_at(cmd[1..].trim_start(), span, builtins)?)))
         }
         _ if cmd.eq("not") | cmd.eq("!") => Ok(Statement::Not(Box::new(Statement::Default))),
         _ if cmd.is_empty() || cmd.starts_with('#') => Ok(Statement::Default),
-        _ => Ok(Statement::Pipeline(pipelines::Collector::run(cmd, builtins)?)),
+        _ => Ok(Statement::Pipeline(pipeline(cmd, span, builtins)?)),
     }
 }
 
@@ -157,7 +236,7 @@ mod tests {
     use super::*;
     use crate::{
         builtins::BuiltinMap,
-        expansion::pipelines::{PipeItem, PipeType, Pipeline, RedirectFrom},
+        expansion::pipelines::{PipeItem, PipeType, Pipeline, RedirectFrom, Redirection},
         parser::lexers::assignments::{KeyBuf, Primitive},
         shell::{flow_control::Statement, Job},
     };
@@ -198,6 +277,7 @@ mod tests {
                     ),
                     outputs: Vec::new(),
                     inputs:  Vec::new(),
+                    fds:     Vec::new(),
                 }],
                 pipe:  PipeType::Normal,
             })],
@@ -247,6 +327,65 @@ mod tests {
         assert_eq!(correct_parse, parsed_if);
     }
 
+    #[test]
+    fn parsing_tries() {
+        let builtins = BuiltinMap::new();
+        let correct_parse = Statement::Try {
+            body:    vec![],
+            catch:   None,
+            finally: vec![],
+            mode:    TryMode::Try,
+        };
+        assert_eq!(parse("  try ", &builtins).unwrap(), correct_parse);
+
+        let catch = |binding: Option<&str>| {
+            Statement::Catch(Catch { binding: binding.map(Into::into), statements: vec![] })
+        };
+        assert_eq!(parse("catch", &builtins).unwrap(), catch(None));
+        assert_eq!(parse("catch  err ", &builtins).unwrap(), catch(Some("err")));
+        assert!(parse("catch @err", &builtins).is_err());
+        assert_eq!(parse("finally", &builtins).unwrap(), Statement::Finally);
+    }
+
+    #[test]
+    fn parsing_defers() {
+        let builtins = BuiltinMap::new();
+        assert!(matches!(parse("defer  echo done", &builtins), Ok(Statement::Defer(_))));
+        assert!(parse("defer", &builtins).is_err());
+        assert!(parse("defer break", &builtins).is_err());
+        assert!(parse("defer return 1", &builtins).is_err());
+        assert!(parse("defer return-value [a b]", &builtins).is_err());
+        assert!(parse("defer and continue", &builtins).is_err());
+        assert!(parse("defer end", &builtins).is_err());
+        assert!(parse("defer if true", &builtins).is_err());
+    }
+
+    #[test]
+    fn parsing_imports() {
+        let builtins = BuiltinMap::new();
+        assert_eq!(
+            parse("import  strings", &builtins).unwrap(),
+            Statement::Import { module: "strings".into(), namespace: None }
+        );
+        assert_eq!(
+            parse("import ~/lib/net.ion as net", &builtins).unwrap(),
+            Statement::Import { module: "~/lib/net.ion".into(), namespace: Some("net".into()) }
+        );
+        assert!(parse("import", &builtins).is_err());
+        assert!(parse("import strings net", &builtins).is_err());
+    }
+
+    #[test]
+    fn parsing_return_values() {
+        let builtins = BuiltinMap::new();
+        assert_eq!(
+            parse("return-value  [a b]", &builtins).unwrap(),
+            Statement::ReturnValue("[a b]".into())
+        );
+        assert_eq!(parse("return 1", &builtins).unwrap(), Statement::Return(Some("1".into())));
+        assert!(parse("return-value", &builtins).is_err());
+    }
+
     #[test]
     fn parsing_functions() {
         // Default case where spaced normally
@@ -273,8 +412,8 @@ mod tests {
             description: None,
             name:        "bob".into(),
             args:        vec![
-                KeyBuf { name: "a".into(), kind: Primitive::Str },
-                KeyBuf { name: "b".into(), kind: Primitive::Str },
+                KeyBuf { name: "a".into(), kind: Primitive::Str }.into(),
+                KeyBuf { name: "b".into(), kind: Primitive::Str }.into(),
             ],
             statements:  Vec::default(),
         };
@@ -289,8 +428,8 @@ mod tests {
             description: Some("bob is a nice function".into()),
             name:        "bob".into(),
             args:        vec![
-                KeyBuf { name: "a".into(), kind: Primitive::Str },
-                KeyBuf { name: "b".into(), kind: Primitive::Str },
+                KeyBuf { name: "a".into(), kind: Primitive::Str }.into(),
+                KeyBuf { name: "b".into(), kind: Primitive::Str }.into(),
             ],
             statements:  vec![],
         };
@@ -302,4 +441,45 @@ mod tests {
             parse("fn bob a b      --bob is a nice function", &BuiltinMap::new()).unwrap();
         assert_eq!(correct_parse, parsed_if);
     }
+
+    #[test]
+    fn parsing_groups() {
+        let parsed = parse("(cd /tmp; ls) > log", &BuiltinMap::new()).unwrap();
+        let correct_parse = Statement::Pipeline(Pipeline {
+            items: vec![PipeItem {
+                job:     Job::new(args!["(cd /tmp; ls)"], RedirectFrom::None),
+                outputs: vec![Redirection {
+                    from:   RedirectFrom::Stdout,
+                    file:   "log".into(),
+                    append: false,
+                }],
+                inputs:  Vec::new(),
+                fds:     Vec::new(),
+            }],
+            pipe:  PipeType::Normal,
+        });
+        assert_eq!(correct_parse, parsed);
+
+        let parsed = parse("{ if true; echo one; end; echo two }", &BuiltinMap::new()).unwrap();
+        match parsed {
+            Statement::Pipeline(pipeline) => match &pipeline.items[0].job.group {
+                Some((body, false)) => assert_eq!(body.len(), 2),
+                group => panic!("expected a group in the shell, found {:?}", group),
+            },
+            statement => panic!("expected a pipeline, found {:?}", statement),
+        }
+        assert!(matches!(
+            parse("{ if true; echo one }", &BuiltinMap::new()),
+            Err(Error::Block(BlockError::UnclosedBlock(_)))
+        ));
+
+        assert_eq!(
+            parse("{ echo one; echo two } arg", &BuiltinMap::new()),
+            Err(Error::GroupArgument("arg".into()))
+        );
+        assert_eq!(
+            parse("{ echo (one) } | cat", &BuiltinMap::new()),
+            Err(Error::InvalidCharacter('(', 7))
+        );
+    }
 }
@@ -2,7 +2,9 @@
 // - Rewrite this in the same style as shell_expand::words.
 // - Validate syntax in methods
 
-use super::Error;
+use super::{Error, Group};
+use crate::parser::heredoc::Heredoc;
+use std::ops::Range;
 
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 enum LogicalOp {
@@ -23,7 +25,9 @@ pub enum StatementVariant<'a> {
 pub struct StatementSplitter<'a> {
     data:             &'a str,
     read:             usize,
+    range:            Range<usize>,
     paren_level:      u8,
+    group_level:      u8,
     brace_level:      u8,
     math_paren_level: i8,
     logical:          LogicalOp,
@@ -39,7 +43,9 @@ impl<'a> StatementSplitter<'a> {
         Self {
             data,
             read: 0,
+            range: 0..0,
             paren_level: 0,
+            group_level: 0,
             brace_level: 0,
             math_paren_level: 0,
             logical: LogicalOp::None,
@@ -50,16 +56,40 @@ impl<'a> StatementSplitter<'a> {
         }
     }
 
-    fn get_statement(&self, start: usize, end: usize) -> StatementVariant<'a> {
+    /// The byte range in the input data of the last statement that was returned
+    pub fn range(&self) -> Range<usize> { self.range.clone() }
+
+    fn trimmed(&mut self, start: usize, end: usize) -> &'a str {
+        let data = self.data;
+        let statement = data[start..end].trim();
+        let offset = end - data[start..end].trim_start().len();
+        self.range = offset..offset + statement.len();
+        statement
+    }
+
+    fn get_statement(&mut self, start: usize, end: usize) -> StatementVariant<'a> {
         if self.logical == LogicalOp::And {
-            StatementVariant::And(self.data[start + 1..end].trim())
+            StatementVariant::And(self.trimmed(start + 1, end))
         } else if self.logical == LogicalOp::Or {
-            StatementVariant::Or(self.data[start + 1..end].trim())
+            StatementVariant::Or(self.trimmed(start + 1, end))
         } else {
-            StatementVariant::Default(self.data[start..end].trim())
+            StatementVariant::Default(self.trimmed(start, end))
         }
     }
 
+    /// Whether the byte at `i` starts a command, so that it may open a group of statements.
+    /// Groups are not recognized within processes and methods.
+    fn at_command(&self, start: usize, i: usize) -> bool {
+        const KEYWORDS: &[&str] = &["if", "else", "while", "and", "or", "not", "!", "time"];
+        self.paren_level == self.group_level
+            && self.data[start..i]
+                .rsplit(|c| ['|', '&', ';', '(', '{'].contains(&c))
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .all(|word| KEYWORDS.contains(&word))
+    }
+
     fn get_statement_from(&self, input: &'a str) -> StatementVariant<'a> {
         if self.logical == LogicalOp::And {
             StatementVariant::And(input)
@@ -111,13 +141,46 @@ impl<'a> Iterator for StatementSplitter<'a> {
                 }
                 // Array expansion
                 b'@' | b'$' => self.variable = true,
+                // Process substitution
+                b'<' | b'>' if !self.quotes && bytes.peek().map(|&(_, c)| c) == Some(b'(') => {
+                    self.variable = true
+                }
+                // Heredocs carry their bodies, which are skipped as a whole
+                b'<' if !self.quotes
+                    && self.paren_level == 0
+                    && last != Some(b'<')
+                    && bytes.peek().map(|&(_, c)| c) == Some(b'<') =>
+                {
+                    self.variable = false;
+                    let header = &self.data.as_bytes()[i + 2..];
+                    if header.first() != Some(&b'<') {
+                        let body = Heredoc::parse(header).and_then(|(heredoc, len)| {
+                            heredoc.body(&self.data[i + 2 + len..]).map(|(_, read)| len + read)
+                        });
+                        let end = i + 2 + body.unwrap_or(0);
+                        while bytes.peek().map_or(false, |&(j, _)| j < end) {
+                            bytes.next();
+                        }
+                    }
+                }
                 b'{' if [Some(b'$'), Some(b'@')].contains(&last) => self.vbrace = true,
+                b'{' if !self.quotes
+                    && Group::opens_block(&self.data.as_bytes()[i..])
+                    && self.at_command(start, i) =>
+                {
+                    self.paren_level += 1;
+                    self.group_level += 1;
+                }
                 b'(' if self.math_paren_level > 0 => self.math_paren_level += 1,
                 b'(' if self.variable && last == Some(b'(') => {
                     self.math_paren_level = 1;
                     self.paren_level -= 1;
                 }
                 b'(' if self.variable => self.paren_level += 1,
+                b'(' if !self.quotes && self.at_command(start, i) => {
+                    self.paren_level += 1;
+                    self.group_level += 1;
+                }
                 b'(' if error.is_none() && !self.quotes => {
                     error = Some(Error::InvalidCharacter(character as char, i + 1))
                 }
@@ -141,8 +204,21 @@ impl<'a> Iterator for StatementSplitter<'a> {
                     }
                     self.variable = false;
                 }
-                b')' => self.paren_level -= 1,
+                b')' => {
+                    if self.paren_level == self.group_level {
+                        self.group_level -= 1;
+                    }
+                    self.paren_level -= 1;
+                }
                 b'}' if self.vbrace => self.vbrace = false,
+                b'}' if !self.quotes
+                    && self.group_level > 0
+                    && self.paren_level == self.group_level
+                    && [Some(b' '), Some(b'\t'), Some(b';')].contains(&last) =>
+                {
+                    self.paren_level -= 1;
+                    self.group_level -= 1;
+                }
                 // [^A-Za-z0-9_]
                 0..=37 | 39..=47 | 58 | 60..=64 | 91..=94 | 96 | 126..=127 => self.variable = false,
                 _ if self.quotes => {}
@@ -194,8 +270,13 @@ impl<'a> Iterator for StatementSplitter<'a> {
             } else if self.math_paren_level != 0 {
                 Some(Err(Error::UnterminatedArithmetic))
             } else {
-                let output = self.data[start..].trim();
+                let output = self.trimmed(start, self.data.len());
                 output.as_bytes().get(0).map(|c| match c {
+                    b'{' if Group::opens_block(output.as_bytes()) => {
+                        let stmt = self.get_statement_from(output);
+                        self.logical = LogicalOp::None;
+                        Ok(stmt)
+                    }
                     b'>' | b'<' | b'^' => Err(Error::ExpectedCommandButFound("redirection")),
                     b'|' => Err(Error::ExpectedCommandButFound("pipe")),
                     b'&' => Err(Error::ExpectedCommandButFound("&")),
@@ -267,6 +348,42 @@ fn process_with_statements() {
     }
 }
 
+#[test]
+fn process_substitutions() {
+    let command = "diff <(sort a; echo) <(sort b | uniq) && tee >(gzip > x.gz)";
+    let results = StatementSplitter::new(command).collect::<Vec<_>>();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0], Ok(StatementVariant::Default("diff <(sort a; echo) <(sort b | uniq)")));
+    assert_eq!(results[1], Ok(StatementVariant::And("tee >(gzip > x.gz)")));
+}
+
+#[test]
+fn heredocs() {
+    let command = "cat <<EOF\n;x\nEOF\n | tr a b; cat <<-'END'\n a; 'b\n  END\n && cat <<<x; echo";
+    let results = StatementSplitter::new(command).collect::<Vec<_>>();
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[0], Ok(StatementVariant::Default("cat <<EOF\n;x\nEOF\n | tr a b")));
+    assert_eq!(results[1], Ok(StatementVariant::Default("cat <<-'END'\n a; 'b\n  END")));
+    assert_eq!(results[2], Ok(StatementVariant::And("cat <<<x")));
+    assert_eq!(results[3], Ok(StatementVariant::Default("echo")));
+}
+
+#[test]
+fn groups() {
+    let command = "(cd /tmp; ls) > log; { a; (b) } | c && if (d; e); echo {a,b} (f)";
+    let results = StatementSplitter::new(command).collect::<Vec<_>>();
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[0], Ok(StatementVariant::Default("(cd /tmp; ls) > log")));
+    assert_eq!(results[1], Ok(StatementVariant::Default("{ a; (b) } | c")));
+    assert_eq!(results[2], Ok(StatementVariant::And("if (d; e)")));
+    assert_eq!(results[3], Err(Error::InvalidCharacter('(', 62)));
+
+    let command = "{ echo }; (echo";
+    let results = StatementSplitter::new(command).collect::<Vec<_>>();
+    assert_eq!(results[0], Ok(StatementVariant::Default("{ echo }")));
+    assert_eq!(results[1], Err(Error::UnterminatedSubshell));
+}
+
 #[test]
 fn quotes() {
     let command = "echo \"This ;'is a test\"; echo 'This ;\" is also a test'";
@@ -324,3 +441,15 @@ fn variants() {
     );
     assert_eq!(results[4], Ok(StatementVariant::Default(r#"echo "Have a good day!""#)));
 }
+
+#[test]
+fn ranges() {
+    let command = "echo one;  echo two &&echo three";
+    let mut splitter = StatementSplitter::new(command);
+    assert_eq!(splitter.next(), Some(Ok(StatementVariant::Default("echo one"))));
+    assert_eq!(splitter.range(), 0..8);
+    assert_eq!(splitter.next(), Some(Ok(StatementVariant::Default("echo two"))));
+    assert_eq!(splitter.range(), 11..19);
+    assert_eq!(splitter.next(), Some(Ok(StatementVariant::And("echo three"))));
+    assert_eq!(splitter.range(), 22..32);
+}
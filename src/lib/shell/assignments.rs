@@ -7,9 +7,9 @@ use crate::{
     builtins::Status,
     parser::lexers::assignments::{Key, Operator, Primitive},
     shell::{flow_control::Function, Value, Variables},
+    types,
 };
 use std::{
-    env,
     io::{self, BufWriter, Write},
     rc::Rc,
     result::Result,
@@ -62,7 +62,7 @@ fn list_vars(shell: &Shell<'_>) -> Result<(), io::Error> {
 /// Represents: A variable store capable of setting local variables or
 /// exporting variables to some global environment
 impl<'b> Shell<'b> {
-    /// Export a variable to the process environment given a binding
+    /// Export a variable to the environment of the commands given a binding
     pub fn export(&mut self, action: &ExportAction) -> Status {
         match action {
             ExportAction::Assign(ref keys, op, ref vals) => {
@@ -76,14 +76,14 @@ impl<'b> Shell<'b> {
                             // TODO: handle operators here in the same way as local
                             .and_then(|rhs| match &rhs {
                                 Value::Array(_) if operator == Operator::Equal => {
-                                    env::set_var(key.name, format!("{}", rhs));
+                                    self.variables.env_mut().set(key.name, format!("{}", rhs));
                                     Ok(())
                                 }
                                 Value::Array(_) => Err("arithmetic operators on array \
                                                         expressions aren't supported yet."
                                     .to_string()),
                                 Value::Str(_) => {
-                                    env::set_var(&key.name, &format!("{}", rhs));
+                                    self.variables.env_mut().set(key.name, format!("{}", rhs));
                                     Ok(())
                                 }
                                 _ => Err(format!(
@@ -102,7 +102,7 @@ impl<'b> Shell<'b> {
             }
             ExportAction::LocalExport(ref key) => match self.variables.get_str(key) {
                 Ok(var) => {
-                    env::set_var(key, &*var);
+                    self.variables.env_mut().set(key.as_str(), var.as_str());
                     Status::SUCCESS
                 }
                 Err(_) => {
@@ -112,7 +112,8 @@ impl<'b> Shell<'b> {
             ExportAction::List => {
                 let stdout = io::stdout();
                 let mut stdout = stdout.lock();
-                for (key, val) in env::vars() {
+                for (key, val) in self.variables.env().iter() {
+                    let (key, val) = (key.to_string_lossy(), val.to_string_lossy());
                     let _ = writeln!(stdout, "{} = \"{}\"", key, val);
                 }
                 Status::SUCCESS
@@ -124,7 +125,7 @@ impl<'b> Shell<'b> {
     pub(crate) fn calculate<'a>(
         &mut self,
         actions: AssignmentActions<'a>,
-    ) -> Result<Vec<(Key<'a>, Value<Rc<Function>>)>, String> {
+    ) -> Result<Vec<(Key<'a>, Vec<types::Str>, Value<Rc<Function>>)>, String> {
         let mut backup: Vec<_> = Vec::with_capacity(4);
         for action in actions {
             let Action(key, operator, expression) = action.map_err(|e| e.to_string())?;
@@ -144,34 +145,30 @@ impl<'b> Shell<'b> {
                 continue;
             }
 
-            let rhs = value_check(self, expression, &key.kind)
+            let path = self.index_path(&key)?;
+            let mut rhs = value_check(self, expression, &key.kind)
                 .map_err(|why| format!("{}: {}", key.name, why))?;
+            // A value assigned to an index must have the type of the others at the same depth
+            let variable = self.variables.get(key.name).filter(|_| !path.is_empty());
+            if let Some(kind) = variable.and_then(|value| element_kind(value, &path)) {
+                rhs =
+                    structure_check(rhs, &kind).map_err(|why| format!("{}: {}", key.name, why))?;
+            }
 
-            match (&rhs, &key.kind) {
-                (Value::HashMap(_), Primitive::Indexed(..)) => {
-                    return Err("cannot insert hmap into index".to_string())
-                }
-                (Value::BTreeMap(_), Primitive::Indexed(..)) => {
-                    return Err("cannot insert bmap into index".to_string())
-                }
-                (Value::Array(_), Primitive::Indexed(..)) => {
-                    return Err("multi-dimensional arrays are not yet supported".to_string())
-                }
-                _ if [Operator::Equal, Operator::OptionalEqual].contains(&operator) => {
-                    backup.push((key, rhs))
-                }
-                _ => {
-                    let lhs = self.variables.get(key.name).ok_or_else(|| {
-                        format!("cannot update non existing variable `{}`", key.name)
-                    })?;
-                    let val = apply(operator, lhs, rhs).map_err(|_| {
-                        format!(
-                            "type error: variable `{}` of type `{}` does not support operator",
-                            key.name, key.kind
-                        )
-                    })?;
-                    backup.push((key, val));
-                }
+            if [Operator::Equal, Operator::OptionalEqual].contains(&operator) {
+                backup.push((key, path, rhs))
+            } else {
+                let lhs = self
+                    .variables
+                    .get(key.name)
+                    .ok_or_else(|| format!("cannot update non existing variable `{}`", key.name))?;
+                let val = apply(operator, lhs, rhs).map_err(|_| {
+                    format!(
+                        "type error: variable `{}` of type `{}` does not support operator",
+                        key.name, key.kind
+                    )
+                })?;
+                backup.push((key, path, val));
             }
         }
         Ok(backup)
@@ -187,8 +184,8 @@ impl<'b> Shell<'b> {
             LocalAction::Assign(ref keys, op, ref vals) => {
                 let actions = AssignmentActions::new(keys, *op, vals);
                 if let Err(why) = self.calculate(actions).and_then(|apply| {
-                    for (key, value) in apply {
-                        self.assign(&key, value)?
+                    for (key, path, value) in apply {
+                        self.assign(&key, &path, value)?
                     }
                     Ok(())
                 }) {
@@ -201,6 +198,49 @@ impl<'b> Shell<'b> {
     }
 }
 
+/// The type of a value, as far as its structure tells, or `None` if it holds an empty collection
+fn kind_of(value: &Value<Rc<Function>>) -> Option<Primitive> {
+    match value {
+        Value::Str(_) => Some(Primitive::Str),
+        Value::Array(array) => Some(Primitive::Array(Box::new(kind_of(array.first()?)?))),
+        Value::HashMap(hmap) => Some(Primitive::HashMap(Box::new(kind_of(hmap.values().next()?)?))),
+        Value::BTreeMap(bmap) => {
+            Some(Primitive::BTreeMap(Box::new(kind_of(bmap.values().next()?)?)))
+        }
+        _ => None,
+    }
+}
+
+/// The type expected of a value assigned at the end of `path` within `value`: that of the value
+/// which it replaces, or else that of the other values of the same collection
+fn element_kind(mut value: &Value<Rc<Function>>, path: &[types::Str]) -> Option<Primitive> {
+    for (depth, index) in path.iter().enumerate() {
+        let element = match value {
+            Value::HashMap(hmap) => hmap.get(index),
+            Value::BTreeMap(bmap) => bmap.get(index),
+            Value::Array(array) => index.parse::<usize>().ok().and_then(|index| array.get(index)),
+            _ => None,
+        };
+        value = match element {
+            Some(element) => element,
+            None => {
+                // Each of the indexes left reaches one level further into the type
+                let mut kind = kind_of(value)?;
+                for _ in depth..path.len() {
+                    kind = match kind {
+                        Primitive::Array(inner)
+                        | Primitive::HashMap(inner)
+                        | Primitive::BTreeMap(inner) => *inner,
+                        _ => return None,
+                    };
+                }
+                return Some(kind);
+            }
+        };
+    }
+    kind_of(value)
+}
+
 // This should logically be a method over operator, but Value is only accessible in the main repo
 // TODO: too much allocations occur over here. We need to expand variables before they get
 // parsed
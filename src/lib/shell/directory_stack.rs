@@ -1,8 +1,8 @@
+use super::Environment;
 #[cfg(target_os = "redox")]
 use redox_users::All;
 use std::{
     collections::VecDeque,
-    env::{self, set_current_dir},
     io,
     path::{Component, Path, PathBuf},
 };
@@ -26,21 +26,15 @@ pub enum DirStackError {
     NoOtherDir,
 }
 
-fn set_current_dir_ion(dir: &Path) -> Result<(), DirStackError> {
-    set_current_dir(dir).map_err(|cause| DirStackError::DirChangeFailure {
+fn set_current_dir_ion(dir: &Path, env: &mut Environment) -> Result<(), DirStackError> {
+    env.set_cwd(dir.to_path_buf()).map_err(|cause| DirStackError::DirChangeFailure {
         cause,
         dir: dir.to_string_lossy().into(),
     })?;
 
-    env::set_var(
-        "OLDPWD",
-        env::var("PWD")
-            .ok()
-            .and_then(|pwd| if pwd.is_empty() { None } else { Some(pwd) })
-            .unwrap_or_else(|| "?".into()),
-    );
-
-    env::set_var("PWD", dir.to_str().unwrap_or("?"));
+    let old_pwd = env.get("PWD").filter(|pwd| !pwd.is_empty()).unwrap_or("?").to_owned();
+    env.set("OLDPWD", old_pwd);
+    env.set("PWD", dir.to_str().unwrap_or("?"));
     Ok(())
 }
 
@@ -50,10 +44,6 @@ pub struct DirectoryStack {
     max_depth: Option<usize>,
 }
 
-impl Default for DirectoryStack {
-    fn default() -> Self { Self::new() }
-}
-
 impl DirectoryStack {
     fn normalize_path(&mut self, dir: &Path) -> PathBuf {
         // Create a clone of the current directory.
@@ -84,22 +74,26 @@ impl DirectoryStack {
     pub fn max_depth(&mut self) -> Option<usize> { self.max_depth }
 
     // pushd -<num>
-    pub fn rotate_right(&mut self, num: usize) -> Result<(), DirStackError> {
+    pub fn rotate_right(&mut self, num: usize, env: &mut Environment) -> Result<(), DirStackError> {
         self.dirs.rotate_right(num);
-        self.set_current_dir_by_index(0)
+        self.set_current_dir_by_index(0, env)
     }
 
     // pushd +<num>
-    pub fn rotate_left(&mut self, num: usize) -> Result<(), DirStackError> {
+    pub fn rotate_left(&mut self, num: usize, env: &mut Environment) -> Result<(), DirStackError> {
         self.dirs.rotate_left(num);
-        self.set_current_dir_by_index(0)
+        self.set_current_dir_by_index(0, env)
     }
 
     // sets current_dir to the element referred by index
-    pub fn set_current_dir_by_index(&self, index: usize) -> Result<(), DirStackError> {
+    pub fn set_current_dir_by_index(
+        &self,
+        index: usize,
+        env: &mut Environment,
+    ) -> Result<(), DirStackError> {
         let dir = self.dirs.get(index).ok_or_else(|| DirStackError::OutOfRange { index })?;
 
-        set_current_dir_ion(dir)
+        set_current_dir_ion(dir, env)
     }
 
     pub fn dir_from_bottom(&self, num: usize) -> Option<&PathBuf> {
@@ -126,32 +120,39 @@ impl DirectoryStack {
         }
     }
 
-    pub fn change_and_push_dir(&mut self, dir: &Path) -> Result<(), DirStackError> {
+    pub fn change_and_push_dir(
+        &mut self,
+        dir: &Path,
+        env: &mut Environment,
+    ) -> Result<(), DirStackError> {
         let new_dir = self.normalize_path(dir);
-        set_current_dir_ion(&new_dir)?;
+        set_current_dir_ion(&new_dir, env)?;
         self.push_dir(new_dir);
         Ok(())
     }
 
-    fn get_previous_dir(&self) -> Option<String> {
-        env::var("OLDPWD").ok().filter(|pwd| !pwd.is_empty() && pwd != "?")
+    fn get_previous_dir(env: &Environment) -> Option<String> {
+        env.get("OLDPWD").filter(|pwd| !pwd.is_empty() && *pwd != "?").map(Into::into)
     }
 
-    pub fn switch_to_previous_directory(&mut self) -> Result<(), DirStackError> {
-        let prev = self.get_previous_dir().ok_or(DirStackError::NoPreviousDir)?;
+    pub fn switch_to_previous_directory(
+        &mut self,
+        env: &mut Environment,
+    ) -> Result<(), DirStackError> {
+        let prev = Self::get_previous_dir(env).ok_or(DirStackError::NoPreviousDir)?;
 
         self.popd(0);
         println!("{}", prev);
-        self.change_and_push_dir(Path::new(&prev))
+        self.change_and_push_dir(Path::new(&prev), env)
     }
 
-    pub fn switch_to_home_directory(&mut self) -> Result<(), DirStackError> {
-        match env::var_os("HOME") {
-            Some(home) => self.change_and_push_dir(Path::new(&home)),
+    pub fn switch_to_home_directory(&mut self, env: &mut Environment) -> Result<(), DirStackError> {
+        match env.get("HOME").map(PathBuf::from) {
+            Some(home) => self.change_and_push_dir(&home, env),
             #[cfg(not(target_os = "redox"))]
             None => users::get_user_by_uid(users::get_current_uid())
                 .map_or(Err(DirStackError::FailedFetchHome), |user| {
-                    self.change_and_push_dir(user.home_dir())
+                    self.change_and_push_dir(user.home_dir(), env)
                 }),
             #[cfg(target_os = "redox")]
             None => {
@@ -160,7 +161,7 @@ impl DirectoryStack {
                         .ok()
                         .and_then(|id| users.get_by_id(id))
                         .map_or(Err(DirStackError::FailedFetchHome), |user| {
-                            self.change_and_push_dir(Path::new(&user.home))
+                            self.change_and_push_dir(Path::new(&user.home), env)
                         })
                 } else {
                     Err(DirStackError::FailedFetchHome)
@@ -169,19 +170,24 @@ impl DirectoryStack {
         }
     }
 
-    pub fn swap(&mut self, index: usize) -> Result<(), DirStackError> {
+    pub fn swap(&mut self, index: usize, env: &mut Environment) -> Result<(), DirStackError> {
         if self.dirs.len() <= index {
             return Err(DirStackError::NoOtherDir);
         }
         self.dirs.swap(0, index);
-        self.set_current_dir_by_index(0)
+        self.set_current_dir_by_index(0, env)
     }
 
-    pub fn pushd(&mut self, path: &Path, keep_front: bool) -> Result<(), DirStackError> {
+    pub fn pushd(
+        &mut self,
+        path: &Path,
+        keep_front: bool,
+        env: &mut Environment,
+    ) -> Result<(), DirStackError> {
         let index = if keep_front { 1 } else { 0 };
         let new_dir = self.normalize_path(path);
         self.insert_dir(index, new_dir);
-        self.set_current_dir_by_index(index)
+        self.set_current_dir_by_index(index, env)
     }
 
     /// Attempts to set the current directory to the directory stack's previous directory,
@@ -190,16 +196,17 @@ impl DirectoryStack {
 
     pub fn clear(&mut self) { self.dirs.truncate(1) }
 
-    /// Create a new `DirectoryStack` containing the current working directory,
-    /// if available.
-    pub fn new() -> Self {
+    /// Create a new `DirectoryStack` containing the working directory of the environment,
+    /// if available, and set `PWD` accordingly.
+    pub fn new(env: &mut Environment) -> Self {
         let mut dirs: VecDeque<PathBuf> = VecDeque::new();
-        if let Ok(curr_dir) = env::current_dir() {
-            env::set_var("PWD", curr_dir.to_str().unwrap_or("?"));
+        let curr_dir = env.cwd().to_path_buf();
+        if !curr_dir.as_os_str().is_empty() {
+            env.set("PWD", curr_dir.to_str().unwrap_or("?"));
             dirs.push_front(curr_dir);
         } else {
             eprintln!("ion: failed to get current directory when building directory stack");
-            env::set_var("PWD", "?");
+            env.set("PWD", "?");
         }
         Self { dirs, max_depth: None }
     }
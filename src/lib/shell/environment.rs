@@ -0,0 +1,200 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::{OsStr, OsString},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The environment that is given to the commands run by a shell, along with the directory that
+/// they are run from.
+///
+/// Each shell starts from a copy of the environment of the process and then keeps its own, so
+/// that several shells can live in the same process without clobbering each other's `PATH` or
+/// working directory. The commands and redirections of a shell are given its own directory, but
+/// builtins which take paths, such as `test`, still resolve them against that of the process.
+/// When syncing is enabled, every change is also applied to the process, for the code that reads
+/// its environment or resolves relative paths directly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Environment {
+    vars: BTreeMap<OsString, OsString>,
+    cwd:  PathBuf,
+    sync: bool,
+}
+
+impl Default for Environment {
+    fn default() -> Self { Self::from_process() }
+}
+
+impl Environment {
+    /// An environment without any variable, in the working directory of the process
+    #[must_use]
+    pub fn empty() -> Self {
+        Self { vars: BTreeMap::new(), cwd: env::current_dir().unwrap_or_default(), sync: false }
+    }
+
+    /// A copy of the environment and of the working directory of the process
+    #[must_use]
+    pub fn from_process() -> Self {
+        Self {
+            vars: env::vars_os().collect(),
+            cwd:  env::current_dir().unwrap_or_default(),
+            sync: false,
+        }
+    }
+
+    /// Get the value of a variable, if it is valid UTF-8
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(OsStr::new(name)).and_then(|value| value.to_str())
+    }
+
+    /// Set a variable, replacing its previous value
+    pub fn set<K: Into<OsString>, V: Into<OsString>>(&mut self, name: K, value: V) {
+        let (name, value) = (name.into(), value.into());
+        if self.sync {
+            env::set_var(&name, &value);
+        }
+        self.vars.insert(name, value);
+    }
+
+    /// Remove a variable, returning its value if it was set
+    pub fn remove<K: AsRef<OsStr>>(&mut self, name: K) -> Option<OsString> {
+        if self.sync {
+            env::remove_var(&name);
+        }
+        self.vars.remove(name.as_ref())
+    }
+
+    /// Set the given variables, returning their previous values so that they can be restored
+    pub fn apply<'a, I>(&mut self, vars: I) -> Vec<(OsString, Option<OsString>)>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        vars.into_iter()
+            .map(|(name, value)| {
+                let previous = self.vars.get(OsStr::new(name)).cloned();
+                self.set(name, value);
+                (name.into(), previous)
+            })
+            .collect()
+    }
+
+    /// Restore the variables that were replaced by [`Environment::apply`]
+    pub fn restore(&mut self, previous: Vec<(OsString, Option<OsString>)>) {
+        for (name, value) in previous.into_iter().rev() {
+            match value {
+                Some(value) => self.set(name, value),
+                None => {
+                    self.remove(&name);
+                }
+            }
+        }
+    }
+
+    /// Iterate over the variables, sorted by name
+    pub fn iter(&self) -> impl Iterator<Item = (&OsStr, &OsStr)> {
+        self.vars.iter().map(|(name, value)| (name.as_os_str(), value.as_os_str()))
+    }
+
+    /// The directory that the commands are run from
+    #[must_use]
+    pub fn cwd(&self) -> &Path { &self.cwd }
+
+    /// Change the directory that the commands are run from, which must be an absolute path
+    pub fn set_cwd(&mut self, dir: PathBuf) -> io::Result<()> {
+        if self.sync {
+            env::set_current_dir(&dir)?;
+        } else if !fs::metadata(&dir)?.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::Other, "not a directory"));
+        }
+        self.cwd = dir;
+        Ok(())
+    }
+
+    /// A path relative to the directory that the commands are run from
+    #[must_use]
+    pub fn path<P: AsRef<Path>>(&self, path: P) -> PathBuf { self.cwd.join(path) }
+
+    /// Whether changes are also applied to the process
+    #[must_use]
+    pub const fn is_synced(&self) -> bool { self.sync }
+
+    /// Apply the changes to the process as well. When enabled, the environment and the working
+    /// directory of the process are first replaced with those of the shell.
+    pub fn set_sync(&mut self, sync: bool) {
+        if sync && !self.sync {
+            for (name, _) in env::vars_os() {
+                if !self.vars.contains_key(&name) {
+                    env::remove_var(name);
+                }
+            }
+            for (name, value) in &self.vars {
+                env::set_var(name, value);
+            }
+            let _ = env::set_current_dir(&self.cwd);
+        }
+        self.sync = sync;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test_derive::serial;
+
+    #[test]
+    #[serial]
+    fn isolated_from_process() {
+        env::set_var("ION_ENV_TEST", "process");
+        let mut first = Environment::from_process();
+        let mut second = Environment::from_process();
+        assert_eq!(first.get("ION_ENV_TEST"), Some("process"));
+
+        first.set("ION_ENV_TEST", "first");
+        second.remove("ION_ENV_TEST");
+        assert_eq!(first.get("ION_ENV_TEST"), Some("first"));
+        assert_eq!(second.get("ION_ENV_TEST"), None);
+        assert_eq!(env::var("ION_ENV_TEST").unwrap(), "process");
+
+        first.set_sync(true);
+        assert_eq!(env::var("ION_ENV_TEST").unwrap(), "first");
+        first.set("ION_ENV_TEST", "synced");
+        assert_eq!(env::var("ION_ENV_TEST").unwrap(), "synced");
+        first.remove("ION_ENV_TEST");
+        assert!(env::var("ION_ENV_TEST").is_err());
+    }
+
+    #[test]
+    fn apply_and_restore() {
+        let mut env = Environment::empty();
+        env.set("A", "1");
+        let previous = env.apply(vec![("A", "2"), ("B", "3"), ("B", "4")]);
+        assert_eq!((env.get("A"), env.get("B")), (Some("2"), Some("4")));
+        env.restore(previous);
+        assert_eq!((env.get("A"), env.get("B")), (Some("1"), None));
+    }
+
+    #[test]
+    fn keeps_non_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        let mut env = Environment::empty();
+        let value = OsStr::from_bytes(b"\xff");
+        env.set("BYTES", value);
+        assert_eq!(env.get("BYTES"), None);
+        assert_eq!(env.iter().collect::<Vec<_>>(), vec![(OsStr::new("BYTES"), value)]);
+    }
+
+    #[test]
+    #[serial]
+    fn own_directory() {
+        let process = env::current_dir().unwrap();
+        let mut env = Environment::empty();
+        env.set_cwd("/".into()).unwrap();
+        assert_eq!(env.cwd(), Path::new("/"));
+        assert_eq!(env.path("a"), Path::new("/a"));
+        assert!(env.set_cwd("/nonexistent".into()).is_err());
+        assert_eq!(env.cwd(), Path::new("/"));
+        assert_eq!(env::current_dir().unwrap(), process);
+    }
+}
@@ -1,22 +1,25 @@
 use super::{
-    flow_control::{Block, Case, ElseIf, Function, IfMode, Statement},
+    flow_control::{Block, Case, Catch, ElseIf, Function, IfMode, Statement, TryMode},
     pipe_exec::PipelineError,
     signals, Shell,
 };
 use crate::{
-    assignments::is_array,
+    assignments::{is_array, value_check},
     builtins::Status,
     expansion::{
         pipelines::{PipeItem, Pipeline},
-        Expander, ForValueExpression,
+        Expander, ForValueExpression, WordIterator, WordToken,
     },
-    parser::{parse_and_validate, StatementSplitter, Terminator},
-    shell::{IonError, Job, Value},
+    parser::{
+        lexers::assignments::Primitive, parse_and_validate, Span, Spanned, StatementSplitter,
+        Terminator,
+    },
+    shell::{IonError, Job, Trap, Value},
     types,
 };
 use itertools::Itertools;
 use nix::unistd::Pid;
-use std::{rc::Rc, time::SystemTime};
+use std::{mem, rc::Rc, time::SystemTime};
 use thiserror::Error;
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
@@ -30,7 +33,7 @@ pub enum Condition {
 type Result = std::result::Result<Condition, IonError>;
 
 /// The block order was invalid
-#[derive(Debug, Error, PartialEq, Eq, Hash)]
+#[derive(Debug, Error, PartialEq, Eq, Hash, Clone)]
 pub enum BlockError {
     /// A case block was found outside a match block
     #[error("Case found outside of Match block")]
@@ -55,6 +58,29 @@ pub enum BlockError {
     #[error("ElseIf found after Else")]
     ElseWrongOrder,
 
+    /// A catch block was found without its corresponding try block
+    #[error("found Catch without Try block")]
+    LoneCatch,
+    /// A finally block was found without its corresponding try block
+    #[error("found Finally without Try block")]
+    LoneFinally,
+    /// Multiple catch were found for the same try
+    #[error("Catch block already exists")]
+    MultipleCatch,
+    /// Multiple finally were found for the same try
+    #[error("Finally block already exists")]
+    MultipleFinally,
+    /// Catch block found after finally
+    #[error("Catch found after Finally")]
+    CatchWrongOrder,
+
+    /// Found a defer outside a function
+    #[error("found Defer outside of a function")]
+    UnmatchedDefer,
+    /// Found a return-value outside a function
+    #[error("found ReturnValue outside of a function")]
+    UnmatchedReturnValue,
+
     /// Found a break outside a loop
     #[error("found Break without loop body")]
     UnmatchedBreak,
@@ -70,9 +96,12 @@ pub enum BlockError {
 impl<'a> Shell<'a> {
     fn insert_into_block(
         block: &mut Block,
-        statement: Statement,
+        statement: Spanned<Statement>,
     ) -> std::result::Result<(), BlockError> {
-        let block = match block.last_mut().expect("Should not insert statement if stack is empty!")
+        let block = match &mut block
+            .last_mut()
+            .expect("Should not insert statement if stack is empty!")
+            .item
         {
             Statement::Time(inner) => inner,
             top_block => top_block,
@@ -83,7 +112,7 @@ impl<'a> Shell<'a> {
             | Statement::For { ref mut statements, .. }
             | Statement::While { ref mut statements, .. } => statements.push(statement),
             Statement::Match { ref mut cases, .. } => {
-                if let Statement::Case(case) = statement {
+                if let Statement::Case(case) = statement.item {
                     cases.push(case)
                 } else {
                     return Err(BlockError::StatementOutsideMatch);
@@ -96,7 +125,7 @@ impl<'a> Shell<'a> {
                 ref mut failure,
                 ref mut mode,
                 ..
-            } => match statement {
+            } => match statement.item {
                 Statement::ElseIf(eif) => {
                     if *mode == IfMode::Else {
                         return Err(BlockError::ElseWrongOrder);
@@ -112,34 +141,65 @@ impl<'a> Shell<'a> {
                         *mode = IfMode::Else;
                     }
                 }
-                _ => match mode {
-                    IfMode::Success => success.push(statement),
-                    IfMode::ElseIf => else_if.last_mut().unwrap().success.push(statement),
-                    IfMode::Else => failure.push(statement),
-                },
+                item => {
+                    let statement = Spanned::new(item, statement.span);
+                    match mode {
+                        IfMode::Success => success.push(statement),
+                        IfMode::ElseIf => else_if.last_mut().unwrap().success.push(statement),
+                        IfMode::Else => failure.push(statement),
+                    }
+                }
             },
+            Statement::Try { ref mut body, ref mut catch, ref mut finally, ref mut mode } => {
+                match statement.item {
+                    Statement::Catch(new_catch) => match mode {
+                        TryMode::Try => {
+                            *mode = TryMode::Catch;
+                            *catch = Some(new_catch);
+                        }
+                        TryMode::Catch => return Err(BlockError::MultipleCatch),
+                        TryMode::Finally => return Err(BlockError::CatchWrongOrder),
+                    },
+                    Statement::Finally => {
+                        if *mode == TryMode::Finally {
+                            return Err(BlockError::MultipleFinally);
+                        } else {
+                            *mode = TryMode::Finally;
+                        }
+                    }
+                    item => {
+                        let statement = Spanned::new(item, statement.span);
+                        match mode {
+                            TryMode::Try => body.push(statement),
+                            TryMode::Catch => catch.as_mut().unwrap().statements.push(statement),
+                            TryMode::Finally => finally.push(statement),
+                        }
+                    }
+                }
+            }
             _ => unreachable!("Not block-like statement pushed to stack!"),
         }
         Ok(())
     }
 
-    fn insert_statement(
+    pub(crate) fn insert_statement(
         block: &mut Block,
-        statement: Statement,
-    ) -> std::result::Result<Option<Statement>, BlockError> {
-        match statement {
+        statement: Spanned<Statement>,
+    ) -> std::result::Result<Option<Spanned<Statement>>, BlockError> {
+        match &statement.item {
             // Push new block to stack
             Statement::For { .. }
             | Statement::While { .. }
             | Statement::Match { .. }
             | Statement::If { .. }
+            | Statement::Try { .. }
             | Statement::Function { .. } => {
                 block.push(statement);
                 Ok(None)
             }
             // Case is special as it should pop back previous Case
             Statement::Case(_) => {
-                match block.last() {
+                match block.last().map(|statement| &statement.item) {
                     Some(Statement::Case(_)) => {
                         let case = block.pop().unwrap();
                         let _ = Self::insert_into_block(block, case);
@@ -159,7 +219,7 @@ impl<'a> Shell<'a> {
                     // Merge back the top block into the previous one
                     _ => {
                         let last_statement = block.pop().unwrap();
-                        if let Statement::Case(_) = last_statement {
+                        if let Statement::Case(_) = last_statement.item {
                             Self::insert_into_block(block, last_statement)?;
                             // Merge last Case back and pop off Match too
                             let match_stm = block.pop().unwrap();
@@ -177,7 +237,7 @@ impl<'a> Shell<'a> {
                 }
             }
             Statement::And(_) | Statement::Or(_) if !block.is_empty() => {
-                let pushed = match block.last_mut().unwrap() {
+                let pushed = match &mut block.last_mut().unwrap().item {
                     Statement::If {
                         ref mut expression,
                         ref mode,
@@ -187,7 +247,7 @@ impl<'a> Shell<'a> {
                     } => match mode {
                         IfMode::Success if success.is_empty() => {
                             // Insert into If expression if there's no previous statement.
-                            expression.push(statement.clone());
+                            expression.push(statement.item.clone());
                             true
                         }
                         IfMode::ElseIf => {
@@ -195,7 +255,7 @@ impl<'a> Shell<'a> {
                             // statement.
                             let eif = else_if.last_mut().expect("Missmatch in 'If' mode!");
                             if eif.success.is_empty() {
-                                eif.expression.push(statement.clone());
+                                eif.expression.push(statement.item.clone());
                                 true
                             } else {
                                 false
@@ -205,7 +265,7 @@ impl<'a> Shell<'a> {
                     },
                     Statement::While { ref mut expression, ref statements } => {
                         if statements.is_empty() {
-                            expression.push(statement.clone());
+                            expression.push(statement.item.clone());
                             true
                         } else {
                             false
@@ -221,20 +281,24 @@ impl<'a> Shell<'a> {
             }
             Statement::Time(inner) => {
                 if inner.is_block() {
-                    block.push(Statement::Time(inner));
+                    block.push(statement);
                     Ok(None)
                 } else {
-                    Ok(Some(Statement::Time(inner)))
+                    Ok(Some(statement))
                 }
             }
             _ if block.is_empty() => {
                 // Filter out toplevel statements that should produce an error
                 // otherwise return the statement for immediat execution
-                match statement {
+                match statement.item {
                     Statement::ElseIf(_) => Err(BlockError::LoneElseIf),
                     Statement::Else => Err(BlockError::LoneElse),
+                    Statement::Catch(_) => Err(BlockError::LoneCatch),
+                    Statement::Finally => Err(BlockError::LoneFinally),
                     Statement::Break => Err(BlockError::UnmatchedBreak),
                     Statement::Continue => Err(BlockError::UnmatchedContinue),
+                    Statement::Defer(_) => Err(BlockError::UnmatchedDefer),
+                    Statement::ReturnValue(_) => Err(BlockError::UnmatchedReturnValue),
                     // Toplevel statement, return to execute immediately
                     _ => Ok(Some(statement)),
                 }
@@ -251,19 +315,19 @@ impl<'a> Shell<'a> {
     fn execute_if(
         &mut self,
         expression: &[Statement],
-        success: &[Statement],
+        success: &[Spanned<Statement>],
         else_if: &[ElseIf],
-        failure: &[Statement],
+        failure: &[Spanned<Statement>],
     ) -> Result {
         // Try execute success branch
-        self.execute_statements(expression)?;
+        self.execute_condition(expression)?;
         if self.previous_status.is_success() {
             return self.execute_statements(success);
         }
 
         // Try to execute else_if branches
         for ElseIf { expression, success } in else_if {
-            self.execute_statements(expression)?;
+            self.execute_condition(expression)?;
 
             if self.previous_status.is_success() {
                 return self.execute_statements(success);
@@ -279,7 +343,7 @@ impl<'a> Shell<'a> {
         &mut self,
         variables: &[types::Str],
         values: &[types::Str],
-        statements: &[Statement],
+        statements: &[Spanned<Statement>],
     ) -> Result {
         macro_rules! set_vars_then_exec {
             ($chunk:expr, $def:expr) => {
@@ -324,9 +388,13 @@ impl<'a> Shell<'a> {
 
     /// Executes all of the statements within a while block until a certain
     /// condition is met.
-    fn execute_while(&mut self, expression: &[Statement], statements: &[Statement]) -> Result {
+    fn execute_while(
+        &mut self,
+        expression: &[Statement],
+        statements: &[Spanned<Statement>],
+    ) -> Result {
         loop {
-            self.execute_statements(expression)?;
+            self.execute_condition(expression)?;
             if self.previous_status.is_failure() {
                 return Ok(Condition::NoOp);
             }
@@ -340,8 +408,101 @@ impl<'a> Shell<'a> {
         }
     }
 
+    /// Executes the body of a try block, then its catch block if the body failed, and then its
+    /// finally block however the others ended.
+    fn execute_try(
+        &mut self,
+        body: &[Spanned<Statement>],
+        catch: Option<&Catch>,
+        finally: &[Spanned<Statement>],
+    ) -> Result {
+        let catching = mem::replace(&mut self.catching, true);
+        let result = self.execute_statements(body);
+        self.catching = catching;
+
+        let result = match (result, catch) {
+            (Err(why), Some(catch)) if !why.is_interrupt() => self.execute_catch(&why, catch),
+            (result, _) => result,
+        };
+
+        // The finally block does not change the status of the try block, unless it leaves it
+        let status = self.previous_status;
+        match self.execute_statements(finally)? {
+            Condition::NoOp => {
+                self.previous_status = status;
+                self.variables.set("?", status);
+                result
+            }
+            condition => Ok(condition),
+        }
+    }
+
+    /// Executes a catch block for the error `why`, which is bound to its variable as a map of
+    /// its `status` and `message`
+    fn execute_catch(&mut self, why: &IonError, catch: &Catch) -> Result {
+        let why = why.origin();
+        let status = match why {
+            IonError::PipelineExecutionError(PipelineError::Failed(status)) => *status,
+            _ if self.previous_status.is_failure() => self.previous_status,
+            _ => Status::from_exit_code(1),
+        };
+
+        if let Some(binding) = &catch.binding {
+            let mut error = types::HashMap::with_capacity(2);
+            error.insert("status".into(), Value::Str(status.as_os_code().to_string().into()));
+            error.insert("message".into(), Value::Str(why.to_string().into()));
+            self.variables.set(binding, Value::HashMap(error));
+        }
+        self.previous_status = status;
+        self.variables.set("?", status);
+
+        self.execute_statements(&catch.statements)
+    }
+
+    /// Evaluates the value given to `return-value`. A whole array or map variable is returned as
+    /// is, and any other expression is expanded as it would be by an untyped assignment.
+    fn return_value(
+        &mut self,
+        expression: &str,
+    ) -> std::result::Result<Value<Rc<Function>>, IonError> {
+        let mut words = WordIterator::new(expression, false);
+        if let (Some(WordToken::ArrayVariable(name, false, None)), None) =
+            (words.next(), words.next())
+        {
+            if let Some(value) = self.variables.get(name) {
+                return Ok(value.clone());
+            }
+        }
+        value_check(self, expression, &Primitive::Str).map_err(Into::into)
+    }
+
+    /// Executes a statement whose failure is tested, and thus is not caught by a try block
+    fn execute_tested(&mut self, statement: &Statement) -> Result {
+        let catching = mem::replace(&mut self.catching, false);
+        let condition = self.execute_statement(statement);
+        self.catching = catching;
+        condition
+    }
+
+    /// Executes the statements of a condition, whose failure is not caught by a try block
+    fn execute_condition(&mut self, statements: &[Statement]) -> Result {
+        let catching = mem::replace(&mut self.catching, false);
+        let condition = self.execute_expression(statements);
+        self.catching = catching;
+        condition
+    }
+
     /// Executes a single statement
     pub fn execute_statement(&mut self, statement: &Statement) -> Result {
+        // The process substitutions expanded outside of the pipelines of the statement, such as
+        // in an assignment or the values of a loop, are finished once the statement is done
+        let substitutions = self.process_substitutions.len();
+        let condition = self.run_statement(statement);
+        self.reap_substitutions(substitutions, true);
+        condition
+    }
+
+    fn run_statement(&mut self, statement: &Statement) -> Result {
         match statement {
             Statement::Let(action) => {
                 self.previous_status = self.local(action);
@@ -371,16 +532,27 @@ impl<'a> Shell<'a> {
                 }
             }
             Statement::Function { name, args, statements, description } => {
+                let module = self.variables.current_module();
                 self.variables.set(
                     name,
-                    Value::Function(Rc::new(Function::new(
-                        description.clone(),
-                        name.clone(),
-                        args.to_vec(),
-                        statements.to_vec(),
-                    ))),
+                    Value::Function(Rc::new(
+                        Function::new(
+                            description.clone(),
+                            name.clone(),
+                            args.to_vec(),
+                            statements.to_vec(),
+                        )
+                        .in_module(module),
+                    )),
                 );
             }
+            Statement::Import { module, namespace } => {
+                let module = self.expand_string(module)?.join(" ");
+                self.import(&module, namespace.as_ref().map(types::Str::as_str))
+                    .map_err(IonError::Import)?;
+                self.previous_status = Status::SUCCESS;
+                self.variables.set("?", Status::SUCCESS);
+            }
             Statement::Pipeline(pipeline) => {
                 let (pipeline, statements) = expand_pipeline(self, pipeline)?;
                 if !pipeline.items.is_empty() {
@@ -392,7 +564,7 @@ impl<'a> Shell<'a> {
                     self.previous_status = status;
                 }
                 if !statements.is_empty() {
-                    self.execute_statements(&statements)?;
+                    self.execute_expression(&statements)?;
                 }
             }
             Statement::Time(box_statement) => {
@@ -438,10 +610,16 @@ impl<'a> Shell<'a> {
             }
             Statement::Not(box_statement) => {
                 // NOTE: Should the condition be used?
-                let _condition = self.execute_statement(box_statement)?;
+                let _condition = self.execute_tested(box_statement)?;
                 self.previous_status.toggle();
                 self.variables.set("?", self.previous_status);
             }
+            Statement::Defer(statement) => match self.call_stack.last_mut() {
+                Some(frame) => {
+                    frame.defer(Spanned::new((**statement).clone(), self.location.clone()))
+                }
+                None => return Err(BlockError::UnmatchedDefer.into()),
+            },
             Statement::Break => return Ok(Condition::Break),
             Statement::Continue => return Ok(Condition::Continue),
             Statement::Match { expression, cases } => {
@@ -451,6 +629,13 @@ impl<'a> Shell<'a> {
                     return Ok(condition);
                 }
             }
+            Statement::Try { body, catch, finally, .. } => {
+                let condition = self.execute_try(body, catch.as_ref(), finally)?;
+
+                if condition != Condition::NoOp {
+                    return Ok(condition);
+                }
+            }
             Statement::Return(expression) => {
                 if let Some(expression) = expression {
                     let value = self.expand_string(expression.as_ref())?.join(" ");
@@ -460,18 +645,69 @@ impl<'a> Shell<'a> {
                 }
                 return Ok(Condition::Return);
             }
+            Statement::ReturnValue(expression) => {
+                if self.call_stack.is_empty() {
+                    return Err(BlockError::UnmatchedReturnValue.into());
+                }
+                let value = self.return_value(expression)?;
+                if let Some(frame) = self.call_stack.last_mut() {
+                    frame.set_value(value);
+                }
+                self.previous_status = Status::SUCCESS;
+                return Ok(Condition::Return);
+            }
             _ => {}
         }
-        if let Some(signal) = signals::SignalHandler.next() {
-            let _ = self.handle_signal(signal);
-            Err(IonError::from(PipelineError::Interrupted(Pid::this(), signal)))
-        } else {
-            Ok(Condition::NoOp)
+        match signals::SignalHandler.next() {
+            Some(signal) if !self.run_trap(Trap::Signal(signal)) => {
+                let _ = self.handle_signal(signal);
+                Err(IonError::from(PipelineError::Interrupted(Pid::this(), signal)))
+            }
+            _ => Ok(Condition::NoOp),
         }
     }
 
     /// Simply executes all supplied statements.
-    pub fn execute_statements(&mut self, statements: &[Statement]) -> Result {
+    ///
+    /// Errors are annotated with the location of the statement that caused them.
+    pub fn execute_statements(&mut self, statements: &[Spanned<Statement>]) -> Result {
+        self.variables.new_scope(false);
+        let condition = self.execute_block(statements);
+        self.variables.pop_scope();
+
+        condition
+    }
+
+    /// Executes the statements within the current scope of variables
+    pub(crate) fn execute_block(&mut self, statements: &[Spanned<Statement>]) -> Result {
+        let location = self.location.clone();
+
+        let condition = statements
+            .iter()
+            .enumerate()
+            .map(|(i, statement)| {
+                self.location = statement.span.clone();
+                // The status of a statement followed by `&&` or `||` is tested
+                let condition = if matches!(
+                    statements.get(i + 1).map(|next| &next.item),
+                    Some(Statement::And(_)) | Some(Statement::Or(_))
+                ) {
+                    self.execute_tested(&statement.item)
+                } else {
+                    self.execute_statement(&statement.item)
+                };
+                condition.map_err(|why| why.located(&statement.span))
+            })
+            .find(|condition| if let Ok(Condition::NoOp) = condition { false } else { true })
+            .unwrap_or(Ok(Condition::NoOp));
+
+        self.location = location;
+        condition
+    }
+
+    /// Executes the statements of an expression, which are located by the statement that
+    /// contains them.
+    fn execute_expression(&mut self, statements: &[Statement]) -> Result {
         self.variables.new_scope(false);
 
         let condition = statements
@@ -499,7 +735,18 @@ impl<'a> Shell<'a> {
         let is_array = is_array(expression.as_ref());
         let value = self.expand_string(expression.as_ref())?;
         for case in cases.iter() {
-            let is_match = if let Some(v) = &case.value {
+            // The capture groups of a regular expression case, matched against the whole value
+            let mut captures = None;
+            let is_match = if let Some(regex) = &case.regex {
+                captures = regex.captures(&value.join(" ")).map(|groups| {
+                    groups
+                        .iter()
+                        .skip(1)
+                        .map(|group| Value::Str(group.map_or("", |group| group.as_str()).into()))
+                        .collect::<types::Array<_>>()
+                });
+                captures.is_some()
+            } else if let Some(v) = &case.value {
                 let v = self.expand_string(v)?;
                 // Anchor to start and end
                 let v = v.into_iter().map(|v| format!("^{}$", v));
@@ -513,7 +760,15 @@ impl<'a> Shell<'a> {
             if is_match {
                 // let pattern_is_array = is_array(&value);
                 let previous_bind = case.binding.as_ref().and_then(|bind| {
-                    if is_array {
+                    if let Some(groups) = captures.take() {
+                        let out = if let Some(Value::Array(array)) = self.variables.get(bind) {
+                            Some(Value::Array(array.clone()))
+                        } else {
+                            None
+                        };
+                        self.variables_mut().set(bind, Value::Array(groups));
+                        out
+                    } else if is_array {
                         let out = if let Some(Value::Array(array)) = self.variables.get(bind) {
                             Some(Value::Array(array.clone()))
                         } else {
@@ -534,7 +789,10 @@ impl<'a> Shell<'a> {
                 });
 
                 if let Some(statement) = case.conditional.as_ref() {
-                    self.on_command(statement, true)?;
+                    let catching = mem::replace(&mut self.catching, false);
+                    let result = self.on_command(statement, true);
+                    self.catching = catching;
+                    result?;
                     if self.previous_status.is_failure() {
                         continue;
                     }
@@ -566,26 +824,56 @@ impl<'a> Shell<'a> {
         command_string: &str,
         set_cmd_duration: bool,
     ) -> std::result::Result<(), IonError> {
-        let command_start_time = if set_cmd_duration { Some(SystemTime::now()) } else { None };
+        self.on_source(command_string.bytes(), None, set_cmd_duration)
+    }
+
+    /// Reads statements from `source` and executes each of them as soon as its block is
+    /// complete. The statements are located in `file`, if the source was read from one.
+    pub(crate) fn on_source<I: Iterator<Item = u8>>(
+        &mut self,
+        mut source: I,
+        file: Option<Rc<str>>,
+        set_cmd_duration: bool,
+    ) -> std::result::Result<(), IonError> {
+        let mut line = 1;
+        loop {
+            let command_start_time = if set_cmd_duration { Some(SystemTime::now()) } else { None };
+
+            let mut terminator = Terminator::new(&mut source);
+            let stmt = match terminator.terminate() {
+                Some(stmt) => stmt,
+                None => return Ok(()),
+            };
 
-        for stmt in command_string.bytes().batching(|cmd| Terminator::new(cmd).terminate()) {
             // Go through all of the statements and build up the block stack
             // When block is done return statement for execution.
-            for statement in StatementSplitter::new(&stmt) {
-                let statement = parse_and_validate(statement?, &self.builtins)?;
-                if let Some(stm) = Self::insert_statement(&mut self.flow_control, statement)? {
-                    self.execute_statement(&stm)?;
+            let mut statements = StatementSplitter::new(&stmt);
+            while let Some(statement) = statements.next() {
+                let span = terminator.span(file.clone(), line, statements.range());
+                let statement = statement
+                    .and_then(|statement| {
+                        parse_and_validate(statement, span.clone(), &self.builtins)
+                    })
+                    .map_err(|why| IonError::from(why).located(&span))?;
+                if let Some(stm) = Self::insert_statement(&mut self.flow_control, statement)
+                    .map_err(|why| IonError::from(why).located(&span))?
+                {
+                    // Commands that were not read from a script are attributed to the statement
+                    // that runs them
+                    if file.is_some() {
+                        self.location = stm.span.clone();
+                    }
+                    self.execute_statement(&stm.item).map_err(|why| why.located(&stm.span))?;
                 }
             }
-        }
+            line += terminator.lines();
 
-        if let Some(start_time) = command_start_time {
-            if let Ok(elapsed_time) = start_time.elapsed() {
-                self.variables_mut().set("CMD_DURATION", elapsed_time.as_secs().to_string());
+            if let Some(start_time) = command_start_time {
+                if let Ok(elapsed_time) = start_time.elapsed() {
+                    self.variables_mut().set("CMD_DURATION", elapsed_time.as_secs().to_string());
+                }
             }
         }
-
-        Ok(())
     }
 }
 
@@ -604,7 +892,11 @@ fn expand_pipeline<'a>(
     while let Some(item) = item_iter.next() {
         if let Some(Value::Alias(alias)) = shell.variables.get(&item.job.args[0]) {
             statements = StatementSplitter::new(alias.0.as_str())
-                .map(|stmt| parse_and_validate(stmt?, &shell.builtins).map_err(Into::into))
+                .map(|stmt| {
+                    parse_and_validate(stmt?, Span::default(), &shell.builtins)
+                        .map(|statement| statement.item)
+                        .map_err(Into::into)
+                })
                 .collect::<std::result::Result<_, IonError>>()?;
 
             // First item in the alias should be a pipeline item, otherwise it cannot
@@ -668,10 +960,10 @@ fn expand_pipeline<'a>(
 mod tests {
     use super::*;
 
-    fn new_match() -> Statement {
-        Statement::Match { expression: types::Str::from(""), cases: Vec::new() }
+    fn new_match() -> Spanned<Statement> {
+        Statement::Match { expression: types::Str::from(""), cases: Vec::new() }.into()
     }
-    fn new_if() -> Statement {
+    fn new_if() -> Spanned<Statement> {
         Statement::If {
             expression: vec![Statement::Default],
             success:    Vec::new(),
@@ -679,14 +971,17 @@ mod tests {
             failure:    Vec::new(),
             mode:       IfMode::Success,
         }
+        .into()
     }
-    fn new_case() -> Statement {
+    fn new_case() -> Spanned<Statement> {
         Statement::Case(Case {
             value:       None,
+            regex:       None,
             binding:     None,
             conditional: None,
             statements:  Vec::new(),
         })
+        .into()
     }
 
     #[test]
@@ -710,13 +1005,13 @@ mod tests {
         assert_eq!(flow_control.len(), 3);
         assert_eq!(res, Ok(None));
 
-        let res = Shell::insert_statement(&mut flow_control, Statement::End);
+        let res = Shell::insert_statement(&mut flow_control, Statement::End.into());
         assert_eq!(flow_control.len(), 2);
         assert_eq!(res, Ok(None));
 
-        let res = Shell::insert_statement(&mut flow_control, Statement::End);
+        let res = Shell::insert_statement(&mut flow_control, Statement::End.into());
         assert_eq!(flow_control.len(), 0);
-        if let Ok(Some(Statement::Match { ref cases, .. })) = res {
+        if let Ok(Some(Spanned { item: Statement::Match { ref cases, .. }, .. })) = res {
             assert_eq!(cases.len(), 2);
             assert_eq!(cases.last().unwrap().statements.len(), 1);
         } else {
@@ -724,6 +1019,51 @@ mod tests {
         }
     }
 
+    #[test]
+    fn try_blocks() {
+        let new_try = || -> Spanned<Statement> {
+            Statement::Try {
+                body:    Vec::new(),
+                catch:   None,
+                finally: Vec::new(),
+                mode:    TryMode::Try,
+            }
+            .into()
+        };
+        let new_catch = || -> Spanned<Statement> {
+            Statement::Catch(Catch { binding: Some("err".into()), statements: Vec::new() }).into()
+        };
+        let mut flow_control = Block::default();
+
+        for statement in vec![new_try(), Statement::Default.into(), new_catch()] {
+            assert_eq!(Shell::insert_statement(&mut flow_control, statement), Ok(None));
+        }
+        for statement in vec![new_if(), Statement::End.into(), Statement::Finally.into()] {
+            assert_eq!(Shell::insert_statement(&mut flow_control, statement), Ok(None));
+        }
+        let res = Shell::insert_statement(&mut flow_control, new_catch());
+        assert_eq!(res, Err(BlockError::CatchWrongOrder));
+        let res = Shell::insert_statement(&mut flow_control, Statement::Finally.into());
+        assert_eq!(res, Err(BlockError::MultipleFinally));
+
+        let res = Shell::insert_statement(&mut flow_control, Statement::End.into());
+        assert_eq!(flow_control.len(), 0);
+        if let Ok(Some(Spanned { item: Statement::Try { body, catch, finally, .. }, .. })) = res {
+            assert_eq!(body.len(), 1);
+            let catch = catch.unwrap();
+            assert_eq!(catch.binding, Some("err".into()));
+            assert_eq!(catch.statements.len(), 1);
+            assert!(finally.is_empty());
+        } else {
+            panic!();
+        }
+
+        assert_eq!(
+            Shell::insert_statement(&mut flow_control, new_catch()),
+            Err(BlockError::LoneCatch)
+        );
+    }
+
     #[test]
     fn statement_outside_case() {
         let mut flow_control = Block::default();
@@ -732,7 +1072,7 @@ mod tests {
         assert_eq!(flow_control.len(), 1);
         assert_eq!(res, Ok(None));
 
-        let res = Shell::insert_statement(&mut flow_control, Statement::Default);
+        let res = Shell::insert_statement(&mut flow_control, Statement::Default.into());
         if res.is_err() {
             flow_control.clear();
             assert_eq!(flow_control.len(), 0);
@@ -752,13 +1092,20 @@ mod tests {
             Statement::Default,
         ];
         for ok in oks {
-            let res = Shell::insert_statement(&mut flow_control, ok.clone());
-            assert_eq!(Ok(Some(ok)), res);
+            let res = Shell::insert_statement(&mut flow_control, ok.clone().into());
+            assert_eq!(Ok(Some(ok.into())), res);
         }
 
-        let errs = vec![Statement::Else, Statement::End, Statement::Break, Statement::Continue];
+        let errs = vec![
+            Statement::Else,
+            Statement::End,
+            Statement::Break,
+            Statement::Continue,
+            Statement::Defer(Box::new(Statement::Default)),
+            Statement::ReturnValue("[a b]".into()),
+        ];
         for err in errs {
-            assert!(Shell::insert_statement(&mut flow_control, err).is_err());
+            assert!(Shell::insert_statement(&mut flow_control, err.into()).is_err());
         }
     }
 }
@@ -1,12 +1,16 @@
 use crate::{
     assignments::*,
     expansion::pipelines::Pipeline,
-    parser::lexers::assignments::{KeyBuf, Operator, Primitive},
-    shell::{IonError, Job, Shell},
+    parser::{
+        lexers::assignments::{Operator, Primitive},
+        Parameter, ParameterKind, Span, Spanned,
+    },
+    shell::{IonError, Job, Shell, Value},
     types,
 };
+use regex::Regex;
 use smallvec::SmallVec;
-use std::fmt;
+use std::{fmt, rc::Rc, slice};
 use thiserror::Error;
 
 /// Represents a single branch in a match statement. For example, in the expression
@@ -36,10 +40,13 @@ use thiserror::Error;
 /// ```rust,ignore
 /// Case { value: None, ... }
 /// ```
-#[derive(Debug, PartialEq, Clone, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Case {
     /// The value to match with
     pub value:       Option<String>,
+    /// The regular expression compiled from the value, if it is written as `re"..."`. Its
+    /// capture groups are bound to the variable instead of the result.
+    pub regex:       Option<Regex>,
     /// Set a variable with the exact result
     pub binding:     Option<String>,
     /// An additional statement to test before matching the case statement
@@ -48,15 +55,34 @@ pub struct Case {
     pub statements:  Block,
 }
 
+impl PartialEq for Case {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+            && self.regex.as_ref().map(Regex::as_str) == other.regex.as_ref().map(Regex::as_str)
+            && self.binding == other.binding
+            && self.conditional == other.conditional
+            && self.statements == other.statements
+    }
+}
+
 /// An elseif case
 #[derive(Debug, PartialEq, Clone)]
 pub struct ElseIf {
     /// The block to test
-    pub expression: Block,
+    pub expression: Vec<Statement>,
     /// The block to execute on success
     pub success:    Block,
 }
 
+/// The catch block of a try block
+#[derive(Debug, PartialEq, Clone)]
+pub struct Catch {
+    /// The variable that is set to the caught error
+    pub binding:    Option<types::Str>,
+    /// The block to execute when an error is caught
+    pub statements: Block,
+}
+
 /// The action to perform on assignment
 #[derive(Debug, PartialEq, Clone)]
 pub enum LocalAction {
@@ -88,6 +114,17 @@ pub enum IfMode {
     Else,
 }
 
+/// The mode for the next statement of a try block
+#[derive(Debug, PartialEq, Clone, Copy, Hash)]
+pub enum TryMode {
+    /// The body of the block
+    Try,
+    /// The catch block
+    Catch,
+    /// The finally block
+    Finally,
+}
+
 /// A single statement
 ///
 /// Contains all the possible actions for the shell
@@ -103,7 +140,7 @@ pub enum Statement {
     /// An if block
     If {
         /// The block to test
-        expression: Block,
+        expression: Vec<Statement>,
         /// The block to execute on success
         success:    Block,
         /// The list of associated else if blocks
@@ -121,11 +158,18 @@ pub enum Statement {
         name:        types::Str,
         /// the description of the function
         description: Option<types::Str>,
-        /// The required arguments of the function, with their types
-        args:        Vec<KeyBuf>,
+        /// The parameters of the function, with their types
+        args:        Vec<Parameter>,
         /// The statements in the function
         statements:  Block,
     },
+    /// Import a module
+    Import {
+        /// The name or the path of the module
+        module:    types::Str,
+        /// The namespace that the members of the module are bound to
+        namespace: Option<types::Str>,
+    },
     /// for loop
     For {
         /// The bounds
@@ -138,7 +182,7 @@ pub enum Statement {
     /// while
     While {
         /// The block to test
-        expression: Block,
+        expression: Vec<Statement>,
         /// The block to execute repetitively
         statements: Block,
     },
@@ -149,6 +193,21 @@ pub enum Statement {
         /// A list of case to check for
         cases:      Vec<Case>,
     },
+    /// A try block
+    Try {
+        /// The block to execute
+        body:    Block,
+        /// The block to execute if the body failed. Errors are not caught without it.
+        catch:   Option<Catch>,
+        /// The block to execute when leaving the try block
+        finally: Block,
+        /// The mode
+        mode:    TryMode,
+    },
+    /// catch
+    Catch(Catch),
+    /// finally
+    Finally,
     /// Else statement
     Else,
     /// End of a block
@@ -159,10 +218,14 @@ pub enum Statement {
     Continue,
     /// Exit from the current function/script
     Return(Option<types::Str>),
+    /// Exit from the current function, returning the value of the expression
+    ReturnValue(types::Str),
     /// Execute a pipeline
     Pipeline(Pipeline<Job>),
     /// Time the statement
     Time(Box<Statement>),
+    /// Run the statement when the current function exits
+    Defer(Box<Statement>),
     /// Execute the statement if the previous command succeeded
     And(Box<Statement>),
     /// Execute the statement if the previous command failed
@@ -185,19 +248,25 @@ impl fmt::Display for Statement {
                 Statement::If { .. } => "If { .. }",
                 Statement::ElseIf(_) => "ElseIf { .. }",
                 Statement::Function { .. } => "Function { .. }",
+                Statement::Import { .. } => "Import { .. }",
                 Statement::For { .. } => "For { .. }",
                 Statement::While { .. } => "While { .. }",
                 Statement::Match { .. } => "Match { .. }",
+                Statement::Try { .. } => "Try { .. }",
+                Statement::Catch(_) => "Catch { .. }",
+                Statement::Finally => "Finally",
                 Statement::Else => "Else",
                 Statement::End => "End",
                 Statement::Break => "Break",
                 Statement::Continue => "Continue",
                 Statement::Pipeline(_) => "Pipeline { .. }",
                 Statement::Time(_) => "Time { .. }",
+                Statement::Defer(_) => "Defer { .. }",
                 Statement::And(_) => "And { .. }",
                 Statement::Or(_) => "Or { .. }",
                 Statement::Not(_) => "Not { .. }",
                 Statement::Return(_) => "Return",
+                Statement::ReturnValue(_) => "ReturnValue",
                 Statement::Default => "Default",
             }
         )
@@ -216,22 +285,93 @@ impl Statement {
             | Statement::For { .. }
             | Statement::While { .. }
             | Statement::Match { .. }
+            | Statement::Try { .. }
+            | Statement::Catch(_)
+            | Statement::Finally
             | Statement::Else => true,
             _ => false,
         }
     }
+
+    /// Check if the statement leaves the enclosing loop or function
+    #[must_use]
+    pub fn is_jump(&self) -> bool {
+        match self {
+            Statement::Break
+            | Statement::Continue
+            | Statement::Return(_)
+            | Statement::ReturnValue(_) => true,
+            Statement::And(statement)
+            | Statement::Or(statement)
+            | Statement::Not(statement)
+            | Statement::Time(statement) => statement.is_jump(),
+            _ => false,
+        }
+    }
 }
 
-/// A collection of statement in a block (delimited by braces in most languages)
-pub type Block = Vec<Statement>;
+/// A collection of statement in a block (delimited by braces in most languages), along with
+/// their location
+pub type Block = Vec<Spanned<Statement>>;
 
 /// A user-defined function
 #[derive(Clone, Debug, PartialEq, Default)]
 pub struct Function {
     description: Option<types::Str>,
     name:        types::Str,
-    args:        Vec<KeyBuf>,
+    args:        Vec<Parameter>,
     statements:  Block,
+    /// The module which the function is a member of
+    module:      Option<usize>,
+}
+
+/// A function call, as recorded in the call stack of the shell
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallFrame {
+    name:      types::Str,
+    args:      Vec<types::Str>,
+    call_site: Span,
+    deferred:  Vec<Spanned<Statement>>,
+    value:     Option<Value<Rc<Function>>>,
+}
+
+impl CallFrame {
+    /// The name of the called function
+    #[must_use]
+    pub const fn name(&self) -> &types::Str { &self.name }
+
+    /// The arguments given to the function
+    #[must_use]
+    pub fn args(&self) -> &[types::Str] { &self.args }
+
+    /// The location of the statement that called the function
+    #[must_use]
+    pub const fn call_site(&self) -> &Span { &self.call_site }
+
+    /// Queue a statement to run when the function exits
+    pub(crate) fn defer(&mut self, statement: Spanned<Statement>) { self.deferred.push(statement) }
+
+    /// Set the value returned by the function
+    pub(crate) fn set_value(&mut self, value: Value<Rc<Function>>) { self.value = Some(value) }
+}
+
+impl fmt::Display for CallFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        if let Some(file) = self.call_site.file() {
+            write!(
+                f,
+                " (called at {}:{}:{})",
+                file,
+                self.call_site.line(),
+                self.call_site.column()
+            )?;
+        }
+        Ok(())
+    }
 }
 
 /// Error during function execution
@@ -245,6 +385,16 @@ pub enum FunctionError {
     InvalidArgumentType(Primitive, String),
 }
 
+/// Check that the value given to a parameter has its type
+fn check_argument(
+    shell: &mut Shell<'_>,
+    value: &str,
+    kind: &Primitive,
+) -> Result<Value<Rc<Function>>, FunctionError> {
+    value_check(shell, value, kind)
+        .map_err(|_| FunctionError::InvalidArgumentType(kind.clone(), value.into()))
+}
+
 impl Function {
     /// execute the function in the shell
     pub fn execute<'a, S: AsRef<str>>(
@@ -252,59 +402,121 @@ impl Function {
         shell: &mut Shell<'a>,
         args: &[S],
     ) -> Result<(), IonError> {
-        if args.len() - 1 != self.args.len() {
+        let required = self.args.iter().filter(|p| p.kind == ParameterKind::Required).count();
+        let rest = self.args.last().map_or(false, |p| p.kind == ParameterKind::Rest);
+        if args.len() - 1 < required || (!rest && args.len() - 1 > self.args.len()) {
             return Err(FunctionError::InvalidArgumentCount.into());
         }
 
-        let values = self
-            .args
-            .iter()
-            .zip(args.iter().skip(1))
-            .map(|(type_, value)| {
-                if let Ok(value) = value_check(shell, value.as_ref(), &type_.kind) {
-                    Ok((type_.clone(), value))
-                } else {
-                    Err(FunctionError::InvalidArgumentType(
-                        type_.kind.clone(),
-                        value.as_ref().into(),
-                    ))
+        let mut given = args.iter().skip(1).map(|arg| arg.as_ref());
+        let mut values = SmallVec::<[_; 8]>::new();
+        for Parameter { key, kind } in &self.args {
+            let value = match (kind, &key.kind) {
+                (ParameterKind::Rest, Primitive::Array(inner)) => {
+                    let mut array = types::Array::new();
+                    for value in given.by_ref() {
+                        // Arrays given to a rest parameter of scalars are spread into it
+                        if is_array(value) && !inner.is_collection() {
+                            match check_argument(shell, value, &key.kind)? {
+                                Value::Array(values) => array.extend(values),
+                                value => array.push(value),
+                            }
+                        } else {
+                            array.push(check_argument(shell, value, inner)?);
+                        }
+                    }
+                    Value::Array(array)
                 }
-            })
-            .collect::<Result<SmallVec<[_; 8]>, _>>()?;
+                (kind, _) => match (given.next(), kind) {
+                    (Some(value), _) => check_argument(shell, value, &key.kind)?,
+                    (None, ParameterKind::Default(value)) => {
+                        check_argument(shell, value, &key.kind)?
+                    }
+                    // Optional parameters are not defined without an argument
+                    (None, _) => continue,
+                },
+            };
+            values.push((&key.name, value));
+        }
 
-        let index = shell
-            .variables
-            .index_scope_for_var(&self.name)
-            .expect("execute called with invalid function");
+        // Pop off all scopes since function temporarily. The functions of a module run within
+        // the scope of its members, on top of the global scope, and may change the members.
+        let temporary: Vec<_> = if let Some(module) = self.module {
+            let temporary = shell.variables.pop_scopes(0).collect();
+            shell.variables.enter_module(module);
+            shell.variables.new_scope(false);
+            temporary
+        } else {
+            let index = shell
+                .variables
+                .index_scope_for_var(&self.name)
+                .expect("execute called with invalid function");
+            let temporary = shell.variables.pop_scopes(index).collect();
+            shell.variables.new_scope(true);
+            temporary
+        };
 
-        // Pop off all scopes since function temporarily
-        let temporary: Vec<_> = shell.variables.pop_scopes(index).collect();
+        for (name, value) in values {
+            shell.variables.set(name, value);
+        }
 
-        shell.variables.new_scope(true);
+        shell.call_stack.push(CallFrame {
+            name:      self.name.clone(),
+            args:      args.iter().skip(1).map(|arg| arg.as_ref().into()).collect(),
+            call_site: shell.location.clone(),
+            deferred:  Vec::new(),
+            value:     None,
+        });
+        let names = shell.call_stack.iter().rev().map(|frame| Value::Str(frame.name.clone()));
+        shell.variables.set("FUNCNAME", Value::Array(names.collect()));
 
-        for (type_, value) in values {
-            shell.variables.set(&type_.name, value);
-        }
+        let mut res = shell.execute_block(&self.statements);
 
-        let res = shell.execute_statements(&self.statements);
+        // The deferred statements run however the function exited, from the last one to the
+        // first one, and without changing its status. The first error is kept.
+        let status = shell.previous_status;
+        while let Some(statement) =
+            shell.call_stack.last_mut().and_then(|frame| frame.deferred.pop())
+        {
+            let deferred = shell.execute_block(slice::from_ref(&statement));
+            if res.is_ok() && deferred.is_err() {
+                res = deferred;
+            }
+        }
+        shell.previous_status = status;
+        shell.variables.set("?", status);
 
+        let mut frame = shell.call_stack.pop().expect("the call frame was pushed before the call");
         shell.variables.pop_scope();
+        if self.module.is_some() {
+            shell.variables.pop_scope();
+        }
         shell.variables.append_scopes(temporary);
-        res.map(|_| ())
+        match res {
+            Ok(_) => {
+                shell.returned = frame.value.take();
+                Ok(())
+            }
+            Err(why) => Err(IonError::InFunction(Box::new(why), frame)),
+        }
     }
 
     /// Get the function's description
     #[must_use]
     pub fn description(&self) -> Option<&types::Str> { self.description.as_ref() }
 
+    /// The function, as a member of the given module
+    #[must_use]
+    pub(crate) fn in_module(self, module: Option<usize>) -> Self { Self { module, ..self } }
+
     /// Create a new function
     #[must_use]
     pub const fn new(
         description: Option<types::Str>,
         name: types::Str,
-        args: Vec<KeyBuf>,
-        statements: Vec<Statement>,
+        args: Vec<Parameter>,
+        statements: Block,
     ) -> Self {
-        Self { description, name, args, statements }
+        Self { description, name, args, statements, module: None }
     }
 }
@@ -1,15 +1,26 @@
-use super::{IonError, Shell};
+use super::{flow_control::Block, IonError, Shell};
 use crate::{
     builtins::BuiltinFunction,
     expansion::{self, pipelines::RedirectFrom, Expander},
     types, Value,
 };
-use std::{fmt, fs::File, str};
+use std::{
+    fmt,
+    fs::File,
+    os::unix::io::{AsRawFd, RawFd},
+    rc::Rc,
+    str,
+};
 
 #[derive(Clone)]
 pub struct Job {
     pub args:        types::Args,
     pub redirection: RedirectFrom,
+    /// The `NAME=value` words that precede the command, with their values left unexpanded
+    pub env:         Vec<(types::Str, types::Str)>,
+    /// The statements of the group that the command is made of, if any, along with whether the
+    /// group is run in a fork of the shell
+    pub group:       Option<(Rc<Block>, bool)>,
 }
 
 impl Job {
@@ -19,26 +30,47 @@ impl Job {
     /// Takes the current job's arguments and expands them, one argument at a
     /// time, returning a new `Job` with the expanded arguments.
     pub fn expand<'a>(&self, shell: &mut Shell<'a>) -> expansion::Result<RefinedJob<'a>, IonError> {
-        let mut args = types::Args::new();
-        for arg in &self.args {
-            args.extend(expand_arg(arg, shell)?);
-        }
+        let env = self
+            .env
+            .iter()
+            .map(|(name, value)| shell.get_string(value).map(|value| (name.clone(), value)))
+            .collect::<expansion::Result<_, IonError>>()?;
 
-        Ok(if let Some(Value::Function(_)) = shell.variables.get(&self.args[0]) {
-            RefinedJob::function(self.args.clone(), self.redirection)
-        } else if let Some(bt) = shell.builtins.get(&args[0]) {
-            RefinedJob::builtin(bt, args, self.redirection)
+        // The statements of a group are expanded when they are run
+        let mut job = if let Some((body, subshell)) = &self.group {
+            RefinedJob::group(self.args.clone(), body.clone(), *subshell, self.redirection)
         } else {
-            RefinedJob::external(args, self.redirection)
-        })
+            let mut args = types::Args::new();
+            for arg in &self.args {
+                args.extend(expand_arg(arg, shell)?);
+            }
+
+            if let Some(Value::Function(_)) = shell.variables.get(&self.args[0]) {
+                RefinedJob::function(self.args.clone(), self.redirection)
+            } else if let Some(bt) = shell.builtins.get(&args[0]) {
+                RefinedJob::builtin(bt, args, self.redirection)
+            } else {
+                RefinedJob::external(args, self.redirection)
+            }
+        };
+        job.env = env;
+        Ok(job)
     }
 
-    pub fn new(args: types::Args, redirection: RedirectFrom) -> Self { Job { args, redirection } }
+    pub fn new(args: types::Args, redirection: RedirectFrom) -> Self {
+        Job { args, redirection, env: Vec::new(), group: None }
+    }
+
+    /// Set the environment variables given to the command alone
+    pub fn with_env(mut self, env: Vec<(types::Str, types::Str)>) -> Self {
+        self.env = env;
+        self
+    }
 }
 
 impl PartialEq for Job {
     fn eq(&self, other: &Job) -> bool {
-        self.args == other.args && self.redirection == other.redirection
+        self.args == other.args && self.redirection == other.redirection && self.env == other.env
     }
 }
 
@@ -46,8 +78,8 @@ impl fmt::Debug for Job {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Job {{ command: {}, args: {:?}, redirection: {:?} }}",
-            self.args[0], self.args, self.redirection
+            "Job {{ command: {}, args: {:?}, redirection: {:?}, env: {:?} }}",
+            self.args[0], self.args, self.redirection, self.env
         )
     }
 }
@@ -71,6 +103,32 @@ pub struct RefinedJob<'a> {
     pub args:        types::Args,
     pub var:         Variant<'a>,
     pub redirection: RedirectFrom,
+    /// Numbered file descriptors to point elsewhere, in order, before running the job
+    pub fds:         Vec<(RawFd, FdTarget)>,
+    /// The environment variables set for this job alone
+    pub env:         Vec<(types::Str, types::Str)>,
+}
+
+/// What a numbered file descriptor of a job is made to refer to
+#[derive(Debug)]
+pub enum FdTarget {
+    /// A file opened by the shell
+    File(File),
+    /// Another file descriptor of the job
+    Fd(RawFd),
+    /// Nothing: the descriptor is closed
+    Closed,
+}
+
+impl FdTarget {
+    /// The raw file descriptor to duplicate, if any
+    pub fn as_raw_fd(&self) -> Option<RawFd> {
+        match self {
+            FdTarget::File(file) => Some(file.as_raw_fd()),
+            FdTarget::Fd(fd) => Some(*fd),
+            FdTarget::Closed => None,
+        }
+    }
 }
 
 pub enum Variant<'a> {
@@ -80,6 +138,8 @@ pub enum Variant<'a> {
     Builtin { main: BuiltinFunction<'a> },
     /// Functions can act as commands too!
     Function,
+    /// A group of statements, which is run by a fork of the shell if `subshell` is set
+    Group { body: Rc<Block>, subshell: bool },
     /// Represents redirection into stdin from more than one source
     Cat { sources: Vec<File> },
     Tee {
@@ -168,6 +228,7 @@ impl<'a> RefinedJob<'a> {
     pub fn needs_forking(&self) -> bool {
         match self.var {
             Variant::Function | Variant::Builtin { .. } => false,
+            Variant::Group { subshell, .. } => subshell,
             _ => true,
         }
     }
@@ -176,6 +237,16 @@ impl<'a> RefinedJob<'a> {
 
     pub fn stdin(&mut self, file: File) { self.stdin = Some(file); }
 
+    /// The environment variables set for this job alone
+    pub fn env_vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.env.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// The numbered file descriptors to redirect, and the raw descriptors they should copy
+    pub fn raw_fds(&self) -> Vec<(RawFd, Option<RawFd>)> {
+        self.fds.iter().map(|(fd, target)| (*fd, target.as_raw_fd())).collect()
+    }
+
     pub fn tee(
         tee_out: Option<TeeItem>,
         tee_err: Option<TeeItem>,
@@ -188,6 +259,8 @@ impl<'a> RefinedJob<'a> {
             args: types::Args::new(),
             var: Variant::Tee { items: (tee_out, tee_err) },
             redirection,
+            fds: Vec::new(),
+            env: Vec::new(),
         }
     }
 
@@ -199,11 +272,40 @@ impl<'a> RefinedJob<'a> {
             args: types::Args::new(),
             var: Variant::Cat { sources },
             redirection,
+            fds: Vec::new(),
+            env: Vec::new(),
         }
     }
 
     pub const fn function(args: types::Args, redirection: RedirectFrom) -> Self {
-        Self { stdin: None, stdout: None, stderr: None, args, var: Variant::Function, redirection }
+        Self {
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            args,
+            var: Variant::Function,
+            redirection,
+            fds: Vec::new(),
+            env: Vec::new(),
+        }
+    }
+
+    pub const fn group(
+        args: types::Args,
+        body: Rc<Block>,
+        subshell: bool,
+        redirection: RedirectFrom,
+    ) -> Self {
+        Self {
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            args,
+            var: Variant::Group { body, subshell },
+            redirection,
+            fds: Vec::new(),
+            env: Vec::new(),
+        }
     }
 
     pub fn builtin(
@@ -218,10 +320,21 @@ impl<'a> RefinedJob<'a> {
             args,
             var: Variant::Builtin { main },
             redirection,
+            fds: Vec::new(),
+            env: Vec::new(),
         }
     }
 
     pub const fn external(args: types::Args, redirection: RedirectFrom) -> Self {
-        Self { stdin: None, stdout: None, stderr: None, args, var: Variant::External, redirection }
+        Self {
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            args,
+            var: Variant::External,
+            redirection,
+            fds: Vec::new(),
+            env: Vec::new(),
+        }
     }
 }
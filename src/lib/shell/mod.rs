@@ -1,55 +1,63 @@
 mod assignments;
 mod colors;
 mod directory_stack;
+mod environment;
 mod flow;
 /// The various blocks
 pub mod flow_control;
 mod job;
+mod modules;
 mod pipe_exec;
 mod shell_expand;
 mod signals;
 pub(crate) mod sys;
+mod traps;
 /// Variables for the shell
 pub mod variables;
 
 pub(crate) use self::job::{Job, RefinedJob};
 use self::{
     directory_stack::DirectoryStack,
-    flow_control::{Block, Function, FunctionError, Statement},
+    flow_control::{Block, Function, FunctionError},
     pipe_exec::foreground,
     sys::NULL_PATH,
     variables::Variables,
 };
 pub use self::{
+    environment::Environment,
     flow::BlockError,
+    flow_control::CallFrame,
+    modules::ImportError,
     pipe_exec::{
-        job_control::{BackgroundEvent, BackgroundProcess},
+        job_control::{BackgroundEvent, BackgroundProcess, ProcessState},
         PipelineError,
     },
+    traps::{InvalidTrap, Trap, Traps},
     variables::Value,
 };
 use crate::{
-    assignments::value_check,
     builtins::{BuiltinMap, Status},
     expansion::{
         pipelines::{PipeType, Pipeline},
-        Error as ExpansionError,
+        Error as ExpansionError, Expander,
     },
     parser::{
         lexers::{Key, Primitive},
-        Error as ParseError, Terminator,
+        Error as ParseError, Span,
     },
+    types,
 };
-use itertools::Itertools;
 use nix::{
     sys::signal::{self, SigHandler},
     unistd::Pid,
 };
 use std::{
+    collections::HashMap,
     convert::TryFrom,
     fs::File,
     mem,
     ops::{Deref, DerefMut},
+    path::PathBuf,
     rc::Rc,
     sync::{atomic::Ordering, Arc, Mutex},
     time::SystemTime,
@@ -77,6 +85,75 @@ pub enum IonError {
     /// Could not properly expand to a pipeline
     #[error("expansion error: {0}")]
     ExpansionError(#[source] ExpansionError<IonError>),
+
+    /// A module could not be imported
+    #[error("import error: {0}")]
+    Import(#[source] ImportError),
+
+    /// An error that occured in a statement read from a script
+    #[error("{0}\n{1}")]
+    Located(#[source] Box<IonError>, Span),
+    /// An error that occured during a function call
+    #[error("{0}\n  in {1}")]
+    InFunction(#[source] Box<IonError>, CallFrame),
+}
+
+impl IonError {
+    /// Annotate the error with the location of the statement that caused it.
+    ///
+    /// Only statements that were read from a script are annotated, and the innermost location
+    /// is kept. Errors from function calls are located by their call stack instead.
+    #[must_use]
+    pub fn located(self, span: &Span) -> Self {
+        match self {
+            Self::Located(..) | Self::InFunction(..) => self,
+            _ if span.file().is_none() => self,
+            _ => Self::Located(Box::new(self), span.clone()),
+        }
+    }
+
+    /// The location of the statement that caused the error, if it is known
+    #[must_use]
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Self::Located(_, span) => Some(span),
+            Self::InFunction(why, _) => why.span(),
+            _ => None,
+        }
+    }
+
+    /// The error itself, without the location and the function calls that led to it
+    #[must_use]
+    pub fn origin(&self) -> &Self {
+        match self {
+            Self::Located(why, _) | Self::InFunction(why, _) => why.origin(),
+            _ => self,
+        }
+    }
+
+    /// Whether the error is the interruption of a command by a signal
+    #[must_use]
+    pub fn is_interrupt(&self) -> bool {
+        matches!(self.origin(), Self::PipelineExecutionError(PipelineError::Interrupted(..)))
+    }
+
+    /// The function calls that led to the error, from the innermost to the outermost
+    pub fn call_stack(&self) -> impl Iterator<Item = &CallFrame> {
+        std::iter::successors(Some(self), |why| match why {
+            Self::Located(why, _) | Self::InFunction(why, _) => Some(&**why),
+            _ => None,
+        })
+        .filter_map(|why| if let Self::InFunction(_, frame) = why { Some(frame) } else { None })
+    }
+
+    /// The error, without its location and the function calls that led to it
+    #[must_use]
+    pub fn unlocated(&self) -> &Self {
+        match self {
+            Self::Located(why, _) | Self::InFunction(why, _) => why.unlocated(),
+            _ => self,
+        }
+    }
 }
 
 impl From<ParseError> for IonError {
@@ -113,6 +190,9 @@ pub struct Options {
     pub no_exec:  bool,
     /// If set, denotes that this shell is running as a background job.
     pub grab_tty: bool,
+    /// The status of a pipeline is the last non-zero status of its commands, rather than the
+    /// status of its last command.
+    pub pipefail: bool,
 }
 
 /// The shell structure is a megastructure that manages all of the state of the shell throughout
@@ -122,26 +202,46 @@ pub struct Options {
 pub struct Shell<'a> {
     /// Contains a list of built-in commands that were created when the program
     /// started.
-    builtins:           BuiltinMap<'a>,
+    builtins:              BuiltinMap<'a>,
     /// Contains the aliases, strings, and array variable maps.
-    variables:          Variables,
+    variables:             Variables,
     /// Contains the current state of flow control parameters.
-    flow_control:       Block,
+    flow_control:          Block,
     /// Contains the directory stack parameters.
-    directory_stack:    DirectoryStack,
+    directory_stack:       DirectoryStack,
     /// When a command is executed, the final result of that command is stored
     /// here.
-    previous_status:    Status,
+    previous_status:       Status,
     /// The job ID of the previous command sent to the background.
-    previous_job:       usize,
+    previous_job:          usize,
+    /// The job ID of the command sent to the background before the previous one.
+    older_job:             usize,
     /// Contains all the options relative to the shell
-    opts:               Options,
+    opts:                  Options,
     /// Contains information on all of the active background processes that are being managed
     /// by the shell.
-    background:         Arc<Mutex<Vec<BackgroundProcess>>>,
+    background:            Arc<Mutex<Vec<BackgroundProcess>>>,
     /// When the `fg` command is run, this will be used to communicate with the specified
     /// background process.
-    foreground_signals: Arc<foreground::Signals>,
+    foreground_signals:    Arc<foreground::Signals>,
+    /// Contains the handlers registered with the `trap` builtin
+    traps:                 Traps,
+    /// The functions that are currently being executed, from the outermost to the innermost
+    call_stack:            Vec<CallFrame>,
+    /// The location of the statement being executed
+    location:              Span,
+    /// Whether a failing pipeline raises an error, to be caught by a try block
+    catching:              bool,
+    /// The value returned by the function called by the last pipeline, if any
+    returned:              Option<Value<Rc<Function>>>,
+    /// The statuses of the commands of the last pipeline run in the foreground, in order
+    pipe_status:           Vec<Status>,
+    /// The processes spawned for the process substitutions of the pipelines being run, along
+    /// with the shell's end of their pipe
+    process_substitutions: Vec<(Pid, File)>,
+    /// The index of the modules that were imported, by the path of their file. A module which
+    /// is being loaded has no index yet.
+    modules:               HashMap<PathBuf, Option<usize>>,
 
     // Callbacks
     /// Custom callback for each command call
@@ -169,6 +269,10 @@ impl<'a> Default for Shell<'a> {
     fn default() -> Self { Self::new() }
 }
 
+impl<'a> Drop for Shell<'a> {
+    fn drop(&mut self) { self.run_trap(Trap::Exit); }
+}
+
 impl<'a> Shell<'a> {
     /// Install signal handlers necessary for the shell to work
     fn install_signal_handler() {
@@ -204,16 +308,27 @@ impl<'a> Shell<'a> {
         // for this shell to manage its own process group / children / etc.
         signals::block();
 
+        let mut variables = Variables::default();
+        let directory_stack = DirectoryStack::new(variables.env_mut());
         Shell {
             builtins,
-            variables: Variables::default(),
+            variables,
             flow_control: Block::with_capacity(5),
-            directory_stack: DirectoryStack::new(),
+            directory_stack,
             previous_job: !0,
+            older_job: !0,
             previous_status: Status::SUCCESS,
             opts: Options::default(),
             background: Arc::new(Mutex::new(Vec::new())),
             foreground_signals: Arc::new(foreground::Signals::new()),
+            traps: Traps::default(),
+            call_stack: Vec::new(),
+            location: Span::default(),
+            catching: false,
+            returned: None,
+            pipe_status: Vec::new(),
+            process_substitutions: Vec::new(),
+            modules: HashMap::new(),
             on_command: None,
             pre_command: None,
             background_event: None,
@@ -247,6 +362,13 @@ impl<'a> Shell<'a> {
     #[must_use]
     pub fn dir_stack_mut(&mut self) -> &mut DirectoryStack { &mut self.directory_stack }
 
+    /// Mutable access to the directory stack, along with the environment holding the `PWD` and
+    /// `OLDPWD` variables that it updates when changing directory
+    #[must_use]
+    pub fn dir_stack_env_mut(&mut self) -> (&mut DirectoryStack, &mut Environment) {
+        (&mut self.directory_stack, self.variables.env_mut())
+    }
+
     /// Resets the flow control fields to their default values.
     pub fn reset_flow(&mut self) { self.flow_control.clear(); }
 
@@ -277,17 +399,30 @@ impl<'a> Shell<'a> {
     /// not
     /// terminated, then an error will be returned.
     pub fn execute_command<T: std::io::Read>(&mut self, command: T) -> Result<Status, IonError> {
-        for cmd in command
-            .bytes()
-            .filter_map(Result::ok)
-            .batching(|bytes| Terminator::new(bytes).terminate())
-        {
-            self.on_command(&cmd, true)?;
-        }
+        self.execute_source(command, None)
+    }
 
-        if let Some(block) = self.flow_control.last().map(Statement::to_string) {
+    /// Execute a script read from `script`, in the same way as `execute_command`. The errors
+    /// are annotated with their location in the script, with `name` as the file name.
+    pub fn execute_script<T: std::io::Read>(
+        &mut self,
+        name: &str,
+        script: T,
+    ) -> Result<Status, IonError> {
+        self.execute_source(script, Some(name.into()))
+    }
+
+    fn execute_source<T: std::io::Read>(
+        &mut self,
+        source: T,
+        file: Option<Rc<str>>,
+    ) -> Result<Status, IonError> {
+        self.on_source(source.bytes().filter_map(Result::ok), file, true)?;
+
+        if let Some(block) = self.flow_control.last() {
             self.previous_status = Status::from_exit_code(1);
-            Err(IonError::StatementFlowError(BlockError::UnclosedBlock(block)))
+            Err(IonError::StatementFlowError(BlockError::UnclosedBlock(block.item.to_string()))
+                .located(&block.span))
         } else {
             Ok(self.previous_status)
         }
@@ -297,7 +432,60 @@ impl<'a> Shell<'a> {
     pub fn run_pipeline(&mut self, pipeline: &Pipeline<Job>) -> Result<Status, IonError> {
         let command_start_time = SystemTime::now();
 
+        // Only the process substitutions of this pipeline are reaped once it is done. Those of
+        // a pipeline sent to the background are not waited for.
+        let substitutions = self.process_substitutions.len();
+        let exit_status = self.expand_and_execute(pipeline);
+        self.reap_substitutions(substitutions, pipeline.pipe == PipeType::Normal);
+        let mut statuses = mem::take(&mut self.pipe_status);
+        match &exit_status {
+            // A pipeline sent to the background has the status of its fork
+            Ok(status) if pipeline.pipe != PipeType::Normal => statuses = vec![*status],
+            // A pipeline which could not be run has the status of the error
+            Err(why) if statuses.is_empty() => {
+                statuses = vec![match why.origin() {
+                    IonError::PipelineExecutionError(PipelineError::CommandNotFound(_)) => {
+                        Status::NO_SUCH_COMMAND
+                    }
+                    IonError::PipelineExecutionError(PipelineError::CommandExecError(..)) => {
+                        Status::COULD_NOT_EXEC
+                    }
+                    IonError::PipelineExecutionError(PipelineError::Failed(status)) => *status,
+                    _ => Status::from_exit_code(1),
+                }]
+            }
+            _ => (),
+        }
+        let statuses: types::Array<_> = statuses.into_iter().map(Value::from).collect();
+        self.variables.set("PIPESTATUS", statuses);
+        let exit_status = exit_status?;
+
+        if let Some(ref callback) = self.on_command {
+            if let Ok(elapsed_time) = command_start_time.elapsed() {
+                callback(self, elapsed_time);
+            }
+        }
+
+        if exit_status.is_failure() {
+            self.previous_status = exit_status;
+            self.variables.set("?", exit_status);
+            self.run_trap(Trap::Error);
+        }
+
+        if self.catching && exit_status.is_failure() {
+            return Err(PipelineError::Failed(exit_status).into());
+        }
+
+        if self.opts.err_exit && !exit_status.is_success() {
+            return Err(PipelineError::EarlyExit.into());
+        }
+
+        Ok(exit_status)
+    }
+
+    fn expand_and_execute(&mut self, pipeline: &Pipeline<Job>) -> Result<Status, IonError> {
         let mut pipeline = pipeline.expand(self)?;
+        self.returned = None;
 
         let null_file =
             if pipeline.pipe == PipeType::Disown { File::open(NULL_PATH).ok() } else { None };
@@ -324,7 +512,7 @@ impl<'a> Shell<'a> {
         }
 
         // Don't execute commands when the `-n` flag is passed.
-        let exit_status = if self.opts.no_exec {
+        if self.opts.no_exec {
             Ok(Status::SUCCESS)
         } else if pipeline.requires_piping()
             || self.stderr.is_some()
@@ -340,19 +528,7 @@ impl<'a> Shell<'a> {
             function.execute(self, &pipeline.items[0].job.args).map(|_| self.previous_status)
         } else {
             self.execute_pipeline(pipeline).map_err(Into::into)
-        }?;
-
-        if let Some(ref callback) = self.on_command {
-            if let Ok(elapsed_time) = command_start_time.elapsed() {
-                callback(self, elapsed_time);
-            }
-        }
-
-        if self.opts.err_exit && !exit_status.is_success() {
-            return Err(PipelineError::EarlyExit.into());
         }
-
-        Ok(exit_status)
     }
 
     /// Get the pid of the last executed job
@@ -365,6 +541,16 @@ impl<'a> Shell<'a> {
         }
     }
 
+    /// Get the job ID of the command sent to the background before the previous one
+    #[must_use]
+    pub fn older_job(&self) -> Option<usize> {
+        if self.older_job == !0 {
+            None
+        } else {
+            Some(self.older_job)
+        }
+    }
+
     /// Set the callback to call before each command
     pub fn set_background_event(&mut self, callback: Option<BackgroundEventCallback>) {
         self.background_event = callback;
@@ -415,6 +601,18 @@ impl<'a> Shell<'a> {
     #[must_use]
     pub fn opts_mut(&mut self) -> &mut Options { &mut self.opts }
 
+    /// Access to the handlers registered with `trap`
+    #[must_use]
+    pub const fn traps(&self) -> &Traps { &self.traps }
+
+    /// Mutable access to the handlers registered with `trap`
+    #[must_use]
+    pub fn traps_mut(&mut self) -> &mut Traps { &mut self.traps }
+
+    /// The functions that are currently being executed, from the outermost to the innermost
+    #[must_use]
+    pub fn call_stack(&self) -> &[CallFrame] { &self.call_stack }
+
     /// Access to the variables
     #[must_use]
     pub const fn variables(&self) -> &Variables { &self.variables }
@@ -454,56 +652,82 @@ impl<'a> Shell<'a> {
     #[must_use]
     pub const fn previous_status(&self) -> Status { self.previous_status }
 
-    fn assign(&mut self, key: &Key<'_>, value: Value<Rc<Function>>) -> Result<(), String> {
-        match (&key.kind, &value) {
-            (Primitive::Indexed(ref index_name, ref index_kind), Value::Str(_)) => {
-                let index = value_check(self, index_name, index_kind)
-                    .map_err(|why| format!("{}: {}", key.name, why))?;
-
-                match index {
-                    Value::Str(index) => {
-                        let lhs = self
-                            .variables
-                            .get_mut(key.name)
-                            .ok_or_else(|| "index value does not exist".to_string())?;
-
-                        match lhs {
-                            Value::HashMap(hmap) => {
-                                let _ = hmap.insert(index, value);
-                                Ok(())
-                            }
-                            Value::BTreeMap(bmap) => {
-                                let _ = bmap.insert(index, value);
-                                Ok(())
-                            }
-                            Value::Array(array) => {
-                                let index_num = index.parse::<usize>().map_err(|_| {
-                                    format!("index variable is not a numeric value: `{}`", index)
-                                })?;
-
-                                if let Some(var) = array.get_mut(index_num) {
-                                    *var = value;
-                                }
-                                Ok(())
-                            }
-                            Value::Str(_) => Err("cannot assign to an index of a string".into()),
-                            _ => Ok(()),
-                        }
-                    }
-                    Value::Array(_) => Err("index variable cannot be an array".into()),
-                    Value::HashMap(_) => Err("index variable cannot be a hmap".into()),
-                    Value::BTreeMap(_) => Err("index variable cannot be a bmap".into()),
-                    _ => Ok(()),
-                }
+    /// The expanded indexes that a value is assigned at, which are none for a whole variable.
+    /// Each index that follows the first one (`[a][b]`) reaches into the value nested at the
+    /// previous one.
+    fn index_path(&mut self, key: &Key<'_>) -> Result<Vec<types::Str>, String> {
+        let mut path = Vec::new();
+        if let Primitive::Indexed(ref index_name, _) = key.kind {
+            for index in index_name.split("][") {
+                path.push(self.get_string(index).map_err(|why| format!("{}: {}", key.name, why))?);
             }
-            (_, Value::Str(_))
-            | (_, Value::Array(_))
-            | (Primitive::HashMap(_), Value::HashMap(_))
-            | (Primitive::BTreeMap(_), Value::BTreeMap(_)) => {
+        }
+        Ok(path)
+    }
+
+    fn assign(
+        &mut self,
+        key: &Key<'_>,
+        path: &[types::Str],
+        value: Value<Rc<Function>>,
+    ) -> Result<(), String> {
+        let (index, path) = match path.split_last() {
+            Some(split) => split,
+            None => {
                 self.variables.set(key.name, value);
+                return Ok(());
+            }
+        };
+
+        let mut lhs = self
+            .variables
+            .get_mut(key.name)
+            .ok_or_else(|| "index value does not exist".to_string())?;
+        for index in path {
+            lhs = match lhs {
+                // Missing keys create maps of the same kind, so that trees can be built
+                Value::HashMap(hmap) => hmap
+                    .entry(index.clone())
+                    .or_insert_with(|| Value::HashMap(types::HashMap::new())),
+                Value::BTreeMap(bmap) => bmap
+                    .entry(index.clone())
+                    .or_insert_with(|| Value::BTreeMap(types::BTreeMap::new())),
+                Value::Array(array) => {
+                    let index_num = array_index(index)?;
+                    array.get_mut(index_num).ok_or_else(|| {
+                        format!("index `{}` is out of bounds of `{}`", index_num, key.name)
+                    })?
+                }
+                Value::Str(_) => return Err("cannot assign to an index of a string".into()),
+                _ => return Err(format!("cannot assign to an index of `{}`", key.name)),
+            };
+        }
+
+        match lhs {
+            Value::HashMap(hmap) => {
+                let _ = hmap.insert(index.clone(), value);
                 Ok(())
             }
-            _ => Ok(()),
+            Value::BTreeMap(bmap) => {
+                let _ = bmap.insert(index.clone(), value);
+                Ok(())
+            }
+            Value::Array(array) => {
+                let index_num = array_index(index)?;
+                let var = array.get_mut(index_num).ok_or_else(|| {
+                    format!("index `{}` is out of bounds of `{}`", index_num, key.name)
+                })?;
+                *var = value;
+                Ok(())
+            }
+            Value::Str(_) => Err("cannot assign to an index of a string".into()),
+            _ => Err(format!("cannot assign to an index of `{}`", key.name)),
         }
     }
 }
+
+fn array_index(index: &str) -> Result<usize, String> {
+    index
+        .parse::<usize>()
+        .map_err(|_| format!("index variable is not a numeric value: `{}`", index))
+}
@@ -0,0 +1,125 @@
+//! Modules loaded with the `import` statement.
+
+use super::{variables::Value, IonError, Shell, Variables};
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+/// The namespaces which are already used by the shell
+const RESERVED: [&str; 7] = ["c", "color", "env", "global", "hex", "super", "x"];
+
+/// An error encountered while importing a module
+#[derive(Debug, Error)]
+pub enum ImportError {
+    /// The module is not a path, and was not found in the directories of `ION_PATH`
+    #[error("module '{0}' was not found in ION_PATH")]
+    NotFound(String),
+    /// The members of the module can not be bound to the namespace
+    #[error("'{0}' is not a valid namespace")]
+    InvalidNamespace(String),
+    /// The module imports itself, directly or through other modules
+    #[error("cyclic import of module '{0}'")]
+    Cycle(String),
+    /// The file of the module could not be opened
+    #[error("failed to open module '{0}': {1}")]
+    Open(String, #[source] io::Error),
+    /// The module failed while it was loaded
+    #[error("failed to load module '{0}': {1}")]
+    Load(String, #[source] Box<IonError>),
+}
+
+impl<'a> Shell<'a> {
+    /// Import a module, binding its functions and variables to `namespace`, or to the name of
+    /// its file. A module is only loaded the first time that it is imported.
+    pub fn import(&mut self, module: &str, namespace: Option<&str>) -> Result<(), ImportError> {
+        let path = self.find_module(module)?;
+        let namespace = match namespace {
+            Some(namespace) => namespace,
+            None => path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default(),
+        };
+        if !Variables::is_valid_name(namespace) || RESERVED.contains(&namespace) {
+            return Err(ImportError::InvalidNamespace(namespace.into()));
+        }
+
+        let key = path.canonicalize().map_err(|why| ImportError::Open(module.into(), why))?;
+        let index = match self.modules.get(&key) {
+            Some(Some(index)) => *index,
+            Some(None) => return Err(ImportError::Cycle(module.into())),
+            None => self.load_module(module, &path, key)?,
+        };
+        self.variables.import(namespace, index);
+        Ok(())
+    }
+
+    /// The path of the file of a module: the module itself if it contains a `/`, or else the
+    /// first `<module>.ion` file found in the directories of `ION_PATH`
+    fn find_module(&self, module: &str) -> Result<PathBuf, ImportError> {
+        if module.contains('/') {
+            return Ok(self.variables.env().path(module));
+        }
+
+        let dirs: Vec<String> = match self.variables.get("ION_PATH") {
+            Some(Value::Array(dirs)) => dirs.iter().map(ToString::to_string).collect(),
+            _ => self
+                .variables
+                .get_str("ION_PATH")
+                .map(|dirs| dirs.split(':').map(Into::into).collect())
+                .unwrap_or_default(),
+        };
+        let file = [module, ".ion"].concat();
+        dirs.iter()
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| self.variables.env().path(Path::new(dir).join(&file)))
+            .find(|path| path.is_file())
+            .ok_or_else(|| ImportError::NotFound(module.into()))
+    }
+
+    /// Run the file of a module in a namespace of its own, on top of the global scope, and
+    /// return the index of the module holding the members that it defines. The module is marked
+    /// as being loaded in the meantime, to detect cyclic imports.
+    fn load_module(
+        &mut self,
+        module: &str,
+        path: &Path,
+        key: PathBuf,
+    ) -> Result<usize, ImportError> {
+        let file = File::open(path).map_err(|why| ImportError::Open(module.into(), why))?;
+        self.modules.insert(key.clone(), None);
+
+        let temporary: Vec<_> = self.variables.pop_scopes(0).collect();
+        let index = self.variables.new_module();
+        let result = self.execute_script(&path.to_string_lossy(), file);
+        self.variables.pop_scope();
+        self.variables.append_scopes(temporary);
+
+        match result {
+            Ok(_) => {
+                self.modules.insert(key, Some(index));
+                Ok(index)
+            }
+            Err(why) => {
+                self.modules.remove(&key);
+                Err(nested_import(why)
+                    .unwrap_or_else(|why| ImportError::Load(module.into(), Box::new(why))))
+            }
+        }
+    }
+}
+
+/// The error of a module imported by a module which failed to load, if the module failed for
+/// that reason
+fn nested_import(why: IonError) -> Result<ImportError, IonError> {
+    match why {
+        IonError::Import(why) => Ok(why),
+        IonError::Located(why, span) => {
+            nested_import(*why).map_err(|why| IonError::Located(Box::new(why), span))
+        }
+        IonError::InFunction(why, frame) => {
+            nested_import(*why).map_err(|why| IonError::InFunction(Box::new(why), frame))
+        }
+        why => Err(why),
+    }
+}
@@ -16,8 +16,8 @@ const ERRORED: u8 = 2;
 
 #[derive(Debug)]
 /// An atomic structure that can safely be shared across threads, which serves to provide
-/// communication between the shell and background threads. The `fg` command uses this
-/// structure to notify a background thread that it needs to wait for and return
+/// communication between the shell and the reaper thread. The `fg` command uses this
+/// structure to notify the reaper that it needs to wait for and return
 /// the exit status back to the `fg` function.
 pub struct Signals {
     grab:   AtomicUsize, // AtomicU32,
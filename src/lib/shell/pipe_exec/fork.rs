@@ -30,6 +30,7 @@ impl<'a> Shell<'a> {
                     signal::signal(Signal::SIGTERM, SigHandler::SigDfl).unwrap();
                 }
                 unistd::close(io::stdin().as_raw_fd()).unwrap();
+                let _ = unistd::chdir(self.variables().env().cwd());
 
                 // This ensures that the child fork has a unique PGID.
                 Self::create_process_group();
@@ -49,6 +50,7 @@ impl<'a> Shell<'a> {
                     // The parent process should add the child fork's PID to the background.
                     self.send_to_background(BackgroundProcess::new(
                         child,
+                        vec![child],
                         state,
                         pipeline.to_string(),
                     ));
@@ -1,10 +1,11 @@
 use super::{
     foreground::{BackgroundResult, Signals},
+    reaper::Reaper,
     PipelineError,
 };
 use crate::{
     builtins::Status,
-    shell::{signals, BackgroundEventCallback, Shell},
+    shell::{signals, BackgroundEventCallback, Shell, Trap},
 };
 use nix::{
     sys::{
@@ -14,17 +15,20 @@ use nix::{
     unistd::{self, Pid},
 };
 use std::{
-    fmt,
-    sync::Mutex,
-    thread::{sleep, spawn},
-    time::Duration,
+    env, fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
 };
 
 #[derive(Clone, Copy, Hash, Debug, PartialEq)]
 /// Defines whether the background process is running or stopped.
 pub enum ProcessState {
+    /// The job is running
     Running,
+    /// The job was stopped by a signal
     Stopped,
+    /// The job exited or was disowned, and its number may be given to another job
     Empty,
 }
 
@@ -61,24 +65,69 @@ pub enum BackgroundEvent {
 /// method.
 pub struct BackgroundProcess {
     pid:           Pid,
+    pids:          Vec<Pid>,
     ignore_sighup: bool,
     state:         ProcessState,
     name:          String,
+    started:       SystemTime,
+    cwd:           PathBuf,
+    exit_status:   Option<Status>,
 }
 
 impl BackgroundProcess {
-    pub(super) const fn new(pid: Pid, state: ProcessState, name: String) -> Self {
-        Self { pid, ignore_sighup: false, state, name }
+    /// A job of the process group `pid`, made of the processes `pids`, which was started from
+    /// the current directory
+    #[must_use]
+    pub fn new(pid: Pid, pids: Vec<Pid>, state: ProcessState, name: String) -> Self {
+        Self {
+            pid,
+            pids,
+            ignore_sighup: false,
+            state,
+            name,
+            started: SystemTime::now(),
+            cwd: env::current_dir().unwrap_or_default(),
+            exit_status: None,
+        }
     }
 
-    /// Get the pid associated with the job
+    /// Get the pid associated with the job, which is the ID of its process group
     #[must_use]
     pub const fn pid(&self) -> Pid { self.pid }
 
+    /// Get the IDs of the processes of the job that were spawned by the shell, in the order of
+    /// the pipeline. A job sent to the background is a single fork of the shell.
+    #[must_use]
+    pub fn pids(&self) -> &[Pid] { &self.pids }
+
+    /// Get the command that the job is executing
+    #[must_use]
+    pub fn name(&self) -> &str { &self.name }
+
+    /// Get the state of the job
+    #[must_use]
+    pub const fn state(&self) -> ProcessState { self.state }
+
+    /// Get the time at which the job was started
+    #[must_use]
+    pub const fn started(&self) -> SystemTime { self.started }
+
+    /// Get the directory that the job was started from
+    #[must_use]
+    pub fn cwd(&self) -> &Path { &self.cwd }
+
+    /// The status that the job exited with, once it has exited
+    #[must_use]
+    pub const fn exit_status(&self) -> Option<Status> { self.exit_status }
+
     /// Check if the process is still running
     #[must_use]
     pub fn is_running(&self) -> bool { self.state == ProcessState::Running }
 
+    /// Check if the process is stopped
+    #[must_use]
+    pub fn is_stopped(&self) -> bool { self.state == ProcessState::Stopped }
+
     /// Check if this is in fact a process
     #[must_use]
     pub fn exists(&self) -> bool { self.state != ProcessState::Empty }
@@ -99,134 +148,146 @@ impl fmt::Display for BackgroundProcess {
     }
 }
 
-impl<'a> Shell<'a> {
-    /// If a SIGTERM is received, a SIGTERM will be sent to all background processes
-    /// before the shell terminates itself.
-    pub fn handle_signal(&self, signal: Signal) -> nix::Result<bool> {
-        if signal == Signal::SIGTERM || signal == Signal::SIGHUP {
-            self.background_send(signal)?;
-            Ok(true)
-        } else {
-            Ok(false)
-        }
-    }
+/// A background job watched by the reaper, until all of its processes have exited
+pub(super) struct WatchedJob {
+    pgid:             Pid,
+    njob:             usize,
+    processes:        Arc<Mutex<Vec<BackgroundProcess>>>,
+    fg:               Arc<Signals>,
+    background_event: Option<BackgroundEventCallback>,
+    exit_status:      i32,
+}
 
-    fn add_to_background(&mut self, job: BackgroundProcess) -> usize {
-        let mut processes = self.background_jobs_mut();
-        if let Some(id) = processes.iter().position(|x| !x.exists()) {
-            processes[id] = job;
-            id
-        } else {
-            let njobs = processes.len();
-            processes.push(job);
-            njobs
+impl WatchedJob {
+    fn event(&self, event: BackgroundEvent) {
+        if let Some(ref callback) = &self.background_event {
+            callback(self.njob, self.pgid, event);
         }
     }
 
-    fn watch_background(
-        fg: &Signals,
-        processes: &Mutex<Vec<BackgroundProcess>>,
-        pgid: Pid,
-        njob: usize,
-        background_event: &Option<BackgroundEventCallback>,
-    ) {
-        let mut exit_status = 0;
-
-        macro_rules! get_process {
-            (| $ident:ident | $func:expr) => {
-                let mut processes = processes.lock().unwrap();
-                let $ident = processes.get_mut(njob).unwrap();
-                $func
-            };
+    /// Update the job in the table, unless it was disowned and replaced by another job
+    fn update<F: FnOnce(&mut BackgroundProcess)>(&self, func: F) {
+        let mut processes = self.processes.lock().unwrap();
+        if let Some(process) = processes.get_mut(self.njob).filter(|p| p.pid == self.pgid) {
+            func(process);
         }
+    }
 
+    /// Collect the changes of state of the processes of the job, without blocking. Returns
+    /// whether the job is still alive.
+    pub(super) fn reap(&mut self) -> bool {
+        let mut opts = WaitPidFlag::WUNTRACED;
+        opts.insert(WaitPidFlag::WCONTINUED);
+        opts.insert(WaitPidFlag::WNOHANG);
         loop {
-            let fg_was_grabbed = fg.was_grabbed(pgid);
-            let mut opts = WaitPidFlag::WUNTRACED;
-            opts.insert(WaitPidFlag::WCONTINUED);
-            opts.insert(WaitPidFlag::WNOHANG);
-            match wait::waitpid(Pid::from_raw(-pgid.as_raw()), Some(opts)) {
+            let fg_was_grabbed = self.fg.was_grabbed(self.pgid);
+            match wait::waitpid(Pid::from_raw(-self.pgid.as_raw()), Some(opts)) {
                 Err(nix::Error::Sys(nix::errno::Errno::ECHILD)) => {
                     if !fg_was_grabbed {
-                        if let Some(ref callback) = &background_event {
-                            callback(njob, pgid, BackgroundEvent::Exited(exit_status));
-                        }
+                        self.event(BackgroundEvent::Exited(self.exit_status));
                     }
 
-                    get_process!(|process| {
+                    self.update(|process| {
                         process.forget();
+                        process.exit_status = Some(Status::from_exit_code(self.exit_status));
                         if fg_was_grabbed {
-                            fg.reply_with(exit_status);
+                            self.fg.reply_with(self.exit_status);
                         }
                     });
 
-                    break;
+                    return false;
                 }
                 Err(errno) => {
-                    if let Some(ref callback) = &background_event {
-                        callback(njob, pgid, BackgroundEvent::Errored(errno));
-                    }
+                    self.event(BackgroundEvent::Errored(errno));
 
-                    get_process!(|process| {
+                    self.update(|process| {
                         process.forget();
                         if fg_was_grabbed {
-                            fg.errored();
+                            self.fg.errored();
                         }
                     });
 
-                    break;
+                    return false;
+                }
+                Ok(WaitStatus::StillAlive) => return true,
+                Ok(WaitStatus::Exited(_, status)) => self.exit_status = status,
+                Ok(WaitStatus::Signaled(_, signal, _)) => {
+                    self.exit_status = Status::from_signal(signal as u8).as_os_code();
                 }
-                Ok(WaitStatus::Exited(_, status)) => exit_status = status,
                 Ok(WaitStatus::Stopped(..)) => {
                     if !fg_was_grabbed {
-                        if let Some(ref callback) = &background_event {
-                            callback(njob, pgid, BackgroundEvent::Stopped);
-                        }
+                        self.event(BackgroundEvent::Stopped);
                     }
 
-                    get_process!(|process| {
+                    self.update(|process| {
                         if fg_was_grabbed {
-                            fg.reply_with(Status::TERMINATED.as_os_code());
+                            self.fg.reply_with(Status::TERMINATED.as_os_code());
                         }
                         process.state = ProcessState::Stopped;
                     });
                 }
                 Ok(WaitStatus::Continued(_)) => {
                     if !fg_was_grabbed {
-                        if let Some(ref callback) = &background_event {
-                            callback(njob, pgid, BackgroundEvent::Resumed);
-                        }
+                        self.event(BackgroundEvent::Resumed);
                     }
 
-                    get_process!(|process| process.state = ProcessState::Running);
+                    self.update(|process| process.state = ProcessState::Running);
                 }
                 Ok(_) => (),
             }
-            sleep(Duration::from_millis(100));
         }
     }
+}
 
-    /// Send the current job to the background and spawn a thread to update its state
-    pub fn send_to_background(&mut self, process: BackgroundProcess) {
+impl<'a> Shell<'a> {
+    /// If a SIGTERM is received, a SIGTERM will be sent to all background processes
+    /// before the shell terminates itself.
+    pub fn handle_signal(&self, signal: Signal) -> nix::Result<bool> {
+        if signal == Signal::SIGTERM || signal == Signal::SIGHUP {
+            self.background_send(signal)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn add_to_background(&mut self, job: BackgroundProcess) -> usize {
+        let mut processes = self.background_jobs_mut();
+        if let Some(id) = processes.iter().position(|x| !x.exists()) {
+            processes[id] = job;
+            id
+        } else {
+            let njobs = processes.len();
+            processes.push(job);
+            njobs
+        }
+    }
+
+    /// Send the current job to the background, and have the reaper update its state
+    pub fn send_to_background(&mut self, mut process: BackgroundProcess) {
+        // The job was started from the directory of the shell, not necessarily of the process
+        process.cwd = self.variables().env().cwd().to_path_buf();
         // Add the process to the background list, and mark the job's ID as
         // the previous job in the shell (in case fg/bg is executed w/ no args).
         let pid = process.pid();
         let njob = self.add_to_background(process);
+        if njob != self.previous_job {
+            self.older_job = self.previous_job;
+        }
         self.previous_job = njob;
         if let Some(ref callback) = &self.background_event {
             callback(njob, pid, BackgroundEvent::Added);
         }
 
-        // Increment the `Arc` counters so that these fields can be moved into
-        // the upcoming background thread.
-        let processes = self.background.clone();
-        let fg_signals = self.foreground_signals.clone();
-        let background_event = self.background_event.clone();
-        // Spawn a background thread that will monitor the progress of the
-        // background process, updating it's state changes until it finally
-        // exits.
-        let _ = spawn(move || {
-            Self::watch_background(&fg_signals, &processes, pid, njob as usize, &background_event)
+        // The reaper polls the job right away, in case it changed state before being watched,
+        // and then each time that a SIGCHLD is received, until the job exits.
+        Reaper::get().watch(WatchedJob {
+            pgid: pid,
+            njob,
+            processes: self.background.clone(),
+            fg: self.foreground_signals.clone(),
+            background_event: self.background_event.clone(),
+            exit_status: 0,
         });
     }
 
@@ -249,15 +310,39 @@ impl<'a> Shell<'a> {
         }
     }
 
-    /// Wait for the job in foreground
-    pub fn watch_foreground(&mut self, group: Pid) -> Result<Status, PipelineError> {
+    /// The status of a pipeline whose commands exited with `statuses`: the status of the last
+    /// command, or the last failure with the `pipefail` option.
+    fn pipeline_status(&self, statuses: &[Status]) -> Status {
+        let mut statuses = statuses.iter().rev();
+        let status = if self.opts.pipefail {
+            statuses.find(|status| status.is_failure())
+        } else {
+            statuses.next()
+        };
+        status.copied().unwrap_or(Status::SUCCESS)
+    }
+
+    /// Wait for the job in foreground, whose processes are `pids`, in the order of the pipeline.
+    /// Their statuses are kept for the `PIPESTATUS` variable. If the job is stopped, it is sent
+    /// to the background as `command`, and the processes which did not exit yet have the status
+    /// of the signal which stopped them.
+    pub fn watch_foreground(
+        &mut self,
+        group: Pid,
+        pids: &[Pid],
+        command: &str,
+    ) -> Result<Status, PipelineError> {
         let mut signaled = None;
-        let mut exit_status = Status::SUCCESS;
+        let mut statuses = vec![Status::SUCCESS; pids.len()];
+        let mut exited = vec![false; pids.len()];
+        let index = |pid| pids.iter().position(|&p| p == pid);
 
         loop {
             match wait::waitpid(Pid::from_raw(-group.as_raw()), Some(WaitPidFlag::WUNTRACED)) {
                 Err(err) => match err {
                     nix::Error::Sys(nix::errno::Errno::ECHILD) => {
+                        let exit_status = self.pipeline_status(&statuses);
+                        self.pipe_status = statuses;
                         if let Some(signal) = signaled {
                             break Err(signal);
                         } else {
@@ -266,8 +351,17 @@ impl<'a> Shell<'a> {
                     }
                     err => break Err(PipelineError::WaitPid(err)),
                 },
-                Ok(WaitStatus::Exited(_, status)) => exit_status = Status::from_exit_code(status),
+                Ok(WaitStatus::Exited(pid, status)) => {
+                    if let Some(index) = index(pid) {
+                        statuses[index] = Status::from_exit_code(status);
+                        exited[index] = true;
+                    }
+                }
                 Ok(WaitStatus::Signaled(pid, signal, core_dumped)) => {
+                    if let Some(index) = index(pid) {
+                        statuses[index] = Status::from_signal(signal as u8);
+                        exited[index] = true;
+                    }
                     if signal == signal::Signal::SIGPIPE {
                     } else if core_dumped {
                         signaled = Some(PipelineError::CoreDump(pid));
@@ -281,10 +375,17 @@ impl<'a> Shell<'a> {
                     }
                 }
                 Ok(WaitStatus::Stopped(pid, signal)) => {
+                    for (status, &exited) in statuses.iter_mut().zip(&exited) {
+                        if !exited {
+                            *status = Status::from_signal(signal as u8);
+                        }
+                    }
+                    self.pipe_status = statuses;
                     self.send_to_background(BackgroundProcess::new(
-                        pid,
+                        group,
+                        pids.to_vec(),
                         ProcessState::Stopped,
-                        "".to_string(),
+                        command.to_string(),
                     ));
                     break Err(PipelineError::Interrupted(pid, signal));
                 }
@@ -296,14 +397,102 @@ impl<'a> Shell<'a> {
     /// Waits until all running background tasks have completed, and listens for signals in the
     /// event that a signal is sent to kill the running tasks.
     pub fn wait_for_background(&mut self) -> Result<(), PipelineError> {
-        while self.background_jobs().iter().any(BackgroundProcess::is_running) {
+        self.wait_until(|jobs| !jobs.iter().any(BackgroundProcess::is_running), None).map(|_| ())
+    }
+
+    /// Waits until none of the jobs `njobs` is running, or until the timeout expires. Returns
+    /// whether the jobs have exited or stopped in time.
+    pub fn wait_for_jobs(
+        &mut self,
+        njobs: &[usize],
+        timeout: Option<Duration>,
+    ) -> Result<bool, PipelineError> {
+        self.wait_until(
+            |jobs| njobs.iter().all(|&njob| jobs.get(njob).map_or(true, |job| !job.is_running())),
+            timeout,
+        )
+    }
+
+    /// Waits until one of the running jobs among `njobs` has exited, or until the timeout
+    /// expires. Returns the job that exited, if any did in time.
+    pub fn wait_for_any(
+        &mut self,
+        njobs: &[usize],
+        timeout: Option<Duration>,
+    ) -> Result<Option<usize>, PipelineError> {
+        // A job has exited once it no longer exists, or once its number is given to another job
+        let watched: Vec<(usize, Pid)> = {
+            let jobs = self.background_jobs();
+            njobs
+                .iter()
+                .filter_map(|&njob| {
+                    jobs.get(njob).filter(|job| job.is_running()).map(|job| (njob, job.pid))
+                })
+                .collect()
+        };
+        let exited = |jobs: &[BackgroundProcess]| {
+            watched
+                .iter()
+                .find(|&&(njob, pid)| {
+                    jobs.get(njob).map_or(true, |job| !job.exists() || job.pid != pid)
+                })
+                .map(|&(njob, _)| njob)
+        };
+
+        if watched.is_empty() || !self.wait_until(|jobs| exited(jobs).is_some(), timeout)? {
+            return Ok(None);
+        }
+        Ok(exited(self.background_jobs().as_slice()))
+    }
+
+    /// The status of a job which is no longer running: the status that it exited with, or the
+    /// status of a process stopped by SIGTSTP if it is stopped.
+    #[must_use]
+    pub fn job_status(&self, njob: usize) -> Status {
+        match self.background_jobs().get(njob) {
+            Some(job) if job.is_stopped() => Status::from_signal(Signal::SIGTSTP as u8),
+            Some(job) => job.exit_status().unwrap_or(Status::TERMINATED),
+            None => Status::TERMINATED,
+        }
+    }
+
+    /// Waits until `done` is true of the background jobs, checking them each time that the
+    /// reaper has polled them, or until the timeout expires. Returns whether `done` became true
+    /// in time. Listens for signals in the event that a signal is sent to kill the running
+    /// tasks.
+    fn wait_until<F: Fn(&[BackgroundProcess]) -> bool>(
+        &mut self,
+        done: F,
+        timeout: Option<Duration>,
+    ) -> Result<bool, PipelineError> {
+        if done(self.background_jobs().as_slice()) {
+            return Ok(true);
+        }
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let reaper = Reaper::get();
+        loop {
+            let generation = reaper.generation();
+            if done(self.background_jobs().as_slice()) {
+                break Ok(true);
+            }
             if let Some(signal) = signals::SignalHandler.find(|&s| s != Signal::SIGTSTP) {
-                self.background_send(signal).map_err(PipelineError::KillFailed)?;
-                return Err(PipelineError::Interrupted(Pid::this(), signal));
+                if !self.run_trap(Trap::Signal(signal)) {
+                    self.background_send(signal).map_err(PipelineError::KillFailed)?;
+                    return Err(PipelineError::Interrupted(Pid::this(), signal));
+                }
+            }
+            // Signals sent to the shell do not wake the reaper, so they are checked periodically
+            let mut poll = Duration::from_millis(100);
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    break Ok(false);
+                }
+                poll = poll.min(deadline - now);
             }
-            sleep(Duration::from_millis(100));
+            reaper.wait_for_change(generation, poll);
         }
-        Ok(())
     }
 
     /// When given a process ID, that process's group will be assigned as the
@@ -320,21 +509,22 @@ impl<'a> Shell<'a> {
     pub fn set_bg_task_in_foreground(&self, pid: Pid, cont: bool) -> Status {
         // Pass the TTY to the background job
         Self::set_foreground_as(pid);
-        // Signal the background thread that is waiting on this process to stop waiting.
+        // Signal the reaper that the status of this process has to be sent back to us.
         self.foreground_signals.signal_to_grab(pid);
         // Resume the background task, if needed.
         if cont {
             signals::resume(pid);
         }
 
+        let reaper = Reaper::get();
         let status = loop {
-            // When the background thread that is monitoring the task receives an exit/stop
-            // signal, the status of that process will be communicated back. To
-            // avoid consuming CPU cycles, we wait 25 ms between polls.
+            // When the reaper receives an exit/stop signal for the task, the status of that
+            // process will be communicated back, and the reaper will wake us up.
+            let generation = reaper.generation();
             match self.foreground_signals.was_processed() {
                 Some(BackgroundResult::Status(stat)) => break Status::from_exit_code(stat),
                 Some(BackgroundResult::Errored) => break Status::TERMINATED,
-                None => sleep(Duration::from_millis(25)),
+                None => reaper.wait_for_change(generation, Duration::from_millis(100)),
             }
         };
         // Have the shell reclaim the TTY
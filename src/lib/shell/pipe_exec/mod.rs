@@ -9,29 +9,42 @@ pub mod foreground;
 mod fork;
 pub mod job_control;
 mod pipes;
+mod reaper;
 pub mod streams;
 
 pub use self::{job_control::BackgroundEvent, pipes::create_pipe};
-use self::{job_control::ProcessState, pipes::TeePipe};
+use self::{job_control::ProcessState, pipes::TeePipe, reaper::Reaper};
 use super::{
-    job::{RefinedJob, TeeItem, Variant},
+    flow_control::Statement,
+    job::{FdTarget, RefinedJob, TeeItem, Variant},
     signals::{self, SignalHandler},
     IonError, Shell, Value,
 };
 use crate::{
     builtins::Status,
-    expansion::pipelines::{Input, PipeItem, PipeType, Pipeline, RedirectFrom, Redirection},
+    expansion::pipelines::{
+        FdMode, FdRedirection, Input, PipeItem, PipeType, Pipeline, RedirectFrom, Redirection,
+    },
+    parser::Spanned,
     types,
 };
 use nix::{
-    sys::signal::{self, Signal},
+    fcntl::{self, FcntlArg, FdFlag},
+    sys::{
+        signal::{self, Signal},
+        wait,
+    },
     unistd::{self, ForkResult, Pid},
 };
 use smallvec::SmallVec;
 use std::{
     fs::{File, OpenOptions},
     io::{self, Write},
-    os::unix::process::CommandExt,
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        process::CommandExt,
+    },
+    path::Path,
     process::{exit, Command, Stdio},
 };
 use thiserror::Error;
@@ -52,6 +65,15 @@ pub enum RedirectError {
         #[source]
         why:      io::Error,
     },
+
+    /// Numbered file descriptor
+    #[error("failed to redirect file descriptor {fd} to file '{file}': {why}")]
+    Fd {
+        fd:   RawFd,
+        file: String,
+        #[source]
+        why:  io::Error,
+    },
 }
 
 /// This is created when Ion fails to create a pipeline
@@ -104,6 +126,10 @@ pub enum PipelineError {
     #[error("early exit: pipeline failed")]
     EarlyExit,
 
+    /// A pipeline failed within a try block
+    #[error("pipeline failed with status {}", .0.as_os_code())]
+    Failed(Status),
+
     /// A command could not be found in the pipeline
     #[error("command not found: {0}")]
     CommandNotFound(types::Str),
@@ -145,13 +171,38 @@ pub fn stdin_of<T: AsRef<str>>(input: &T) -> Result<File, PipelineError> {
 }
 
 impl Input {
-    pub(self) fn get_infile(&self) -> Result<File, PipelineError> {
+    pub(self) fn get_infile(&self, cwd: &Path) -> Result<File, PipelineError> {
         match self {
-            Self::File(ref filename) => match File::open(filename.as_str()) {
+            Self::File(ref filename) => match File::open(cwd.join(filename.as_str())) {
                 Ok(file) => Ok(file),
                 Err(why) => Err(RedirectError::File(filename.to_string(), why).into()),
             },
             Self::HereString(ref string) => stdin_of(&string),
+            // An empty body gives the process no input at all
+            Self::Heredoc(ref body, _) if body.is_empty() => Ok(create_pipe()?.0),
+            Self::Heredoc(ref body, _) => stdin_of(&body),
+        }
+    }
+}
+
+impl FdRedirection {
+    pub(self) fn get_target(&self, cwd: &Path) -> Result<(RawFd, FdTarget), RedirectError> {
+        match *self {
+            Self::File { fd, ref file, mode } => OpenOptions::new()
+                .read(mode == FdMode::Read)
+                .write(mode != FdMode::Read)
+                .create(mode != FdMode::Read)
+                .append(mode == FdMode::Append)
+                .truncate(mode == FdMode::Write)
+                .open(cwd.join(file.as_str()))
+                .and_then(|file| {
+                    streams::relocate(&file)
+                        .map_err(|why| io::Error::new(io::ErrorKind::Other, why))
+                })
+                .map(|file| (fd, FdTarget::File(file)))
+                .map_err(|why| RedirectError::Fd { fd, file: file.to_string(), why }),
+            Self::Duplicate { fd, source } => Ok((fd, FdTarget::Fd(source))),
+            Self::Close(fd) => Ok((fd, FdTarget::Closed)),
         }
     }
 }
@@ -182,6 +233,7 @@ fn need_tee(outs: &[Redirection], redirection: RedirectFrom) -> (bool, bool) {
 }
 
 fn do_tee<'a>(
+    cwd: &Path,
     outputs: &[Redirection],
     job: &mut RefinedJob<'a>,
     stdout: &mut dyn FnMut(&mut RefinedJob<'a>, File),
@@ -194,7 +246,7 @@ fn do_tee<'a>(
             .write(true)
             .append(output.append)
             .truncate(!output.append)
-            .open(output.file.as_str())
+            .open(cwd.join(output.file.as_str()))
         {
             Ok(file) => match output.from {
                 RedirectFrom::None => (),
@@ -227,22 +279,24 @@ fn do_tee<'a>(
 }
 
 /// Insert the multiple redirects as pipelines if necessary. Handle both input and output
-/// redirection if necessary.
+/// redirection if necessary. The files are relative to `cwd`.
 fn prepare<'a>(
     pipeline: Pipeline<RefinedJob<'a>>,
+    cwd: &Path,
 ) -> Result<impl IntoIterator<Item = RefinedJob<'a>>, PipelineError> {
     // Real logic begins here
     let mut new_commands =
         SmallVec::<[RefinedJob<'a>; 16]>::with_capacity(2 * pipeline.items.len());
     let mut prev_kind = RedirectFrom::None;
-    for PipeItem { mut job, outputs, inputs } in pipeline.items {
+    for PipeItem { mut job, outputs, inputs, fds } in pipeline.items {
         let kind = job.redirection;
+        job.fds = fds.iter().map(|fd| fd.get_target(cwd)).collect::<Result<_, _>>()?;
         match (inputs.len(), prev_kind) {
             (0, _) => {}
-            (1, RedirectFrom::None) => job.stdin(inputs[0].get_infile()?),
+            (1, RedirectFrom::None) => job.stdin(inputs[0].get_infile(cwd)?),
             _ => {
                 new_commands.push(RefinedJob::cat(
-                    inputs.iter().map(Input::get_infile).collect::<Result<_, _>>()?,
+                    inputs.iter().map(|input| input.get_infile(cwd)).collect::<Result<_, _>>()?,
                     RedirectFrom::Stdout,
                 ));
             }
@@ -254,13 +308,21 @@ fn prepare<'a>(
             match need_tee(&outputs, kind) {
                 // No tees
                 (false, false) => {
-                    do_tee(&outputs, &mut job, &mut RefinedJob::stdout, &mut RefinedJob::stderr)?;
+                    do_tee(
+                        cwd,
+                        &outputs,
+                        &mut job,
+                        &mut RefinedJob::stdout,
+                        &mut RefinedJob::stderr,
+                    )?;
                     new_commands.push(job);
                 }
                 // tee stderr
                 (false, true) => {
                     let mut tee = TeeItem::new();
-                    do_tee(&outputs, &mut job, &mut RefinedJob::stdout, &mut |_, f| tee.add(f))?;
+                    do_tee(cwd, &outputs, &mut job, &mut RefinedJob::stdout, &mut |_, f| {
+                        tee.add(f)
+                    })?;
                     let tee = RefinedJob::tee(None, Some(tee), job.redirection);
                     job.redirection = RedirectFrom::Stderr;
                     new_commands.push(job);
@@ -269,7 +331,13 @@ fn prepare<'a>(
                 // tee stdout
                 (true, false) => {
                     let mut tee = TeeItem::new();
-                    do_tee(&outputs, &mut job, &mut |_, f| tee.add(f), &mut RefinedJob::stderr)?;
+                    do_tee(
+                        cwd,
+                        &outputs,
+                        &mut job,
+                        &mut |_, f| tee.add(f),
+                        &mut RefinedJob::stderr,
+                    )?;
                     let tee = RefinedJob::tee(Some(tee), None, job.redirection);
                     job.redirection = RedirectFrom::Stdout;
                     new_commands.push(job);
@@ -279,7 +347,7 @@ fn prepare<'a>(
                 (true, true) => {
                     let mut tee_out = TeeItem::new();
                     let mut tee_err = TeeItem::new();
-                    do_tee(&outputs, &mut job, &mut |_, f| tee_out.add(f), &mut |_, f| {
+                    do_tee(cwd, &outputs, &mut job, &mut |_, f| tee_out.add(f), &mut |_, f| {
                         tee_err.sinks.push(f)
                     })?;
                     let tee = RefinedJob::tee(Some(tee_out), Some(tee_err), job.redirection);
@@ -344,6 +412,14 @@ impl<'b> Shell<'b> {
         }
     }
 
+    /// Runs the statements of a group within a new scope of variables
+    fn exec_group(&mut self, body: &[Spanned<Statement>]) -> Result<Status, IonError> {
+        self.variables.new_scope(false);
+        let result = self.execute_block(body);
+        self.variables.pop_scope();
+        result.map(|_| self.previous_status)
+    }
+
     /// Executes a `RefinedJob` that was created in the `generate_commands` method.
     ///
     /// The aforementioned `RefinedJob` may be either a builtin or external command.
@@ -353,12 +429,29 @@ impl<'b> Shell<'b> {
         let (stdin_bk, stdout_bk, stderr_bk) =
             streams::duplicate().map_err(PipelineError::CreatePipeError)?;
         streams::redirect(&job.stdin, &job.stdout, &job.stderr)?;
-        let code = match job.var {
-            Variant::Builtin { main } => Ok(main(job.args(), self)),
-            Variant::Function => self.exec_function(job.command(), job.args()),
-            _ => panic!("exec job should not be able to be called on Cat or Tee jobs"),
+        let fds = job.raw_fds();
+        let fds_bk = streams::duplicate_fds(&fds);
+        // As in a forked job, a redirection which fails is reported and the job is not run
+        let code = if let Err((fd, why)) = streams::redirect_fds(&fds) {
+            streams::report_fd_error(fd, why);
+            Ok(Status::from_exit_code(1))
+        } else {
+            let env_bk = self.variables.env_mut().apply(job.env_vars());
+            let code = match job.var {
+                Variant::Builtin { main } => Ok(main(job.args(), self)),
+                Variant::Function => self.exec_function(job.command(), job.args()),
+                Variant::Group { ref body, .. } => self.exec_group(body),
+                _ => panic!("exec job should not be able to be called on Cat or Tee jobs"),
+            };
+            self.variables.env_mut().restore(env_bk);
+            code
         };
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+        // The standard streams are put back even if the numbered descriptors could not be
+        let restored = streams::restore_fds(fds_bk);
         streams::redirect(&stdin_bk, &Some(stdout_bk), &Some(stderr_bk))?;
+        restored?;
         code
     }
 
@@ -401,11 +494,78 @@ impl<'b> Shell<'b> {
         }
     }
 
+    /// Forks `command` with one end of a new pipe as its stdout (`<(command)`), or as its stdin
+    /// when `writable` (`>(command)`), and returns the path to the other end. That end stays
+    /// open, and is inherited by commands, until the current pipeline is done.
+    pub(super) fn substitute_process(
+        &mut self,
+        command: &str,
+        writable: bool,
+    ) -> Result<types::Str, PipelineError> {
+        let (reader, writer) = create_pipe()?;
+        let (end, stdin, stdout) =
+            if writable { (writer, Some(reader), None) } else { (reader, None, Some(writer)) };
+        let fd = end.as_raw_fd();
+
+        let cwd = self.variables.env().cwd().to_path_buf();
+        let pid = fork_exec_internal(stdout, None, stdin, &[], None, &cwd, |stdout, _, stdin| {
+            // The pipe must only be held open by the command and its consumer, so that either
+            // of them exiting is noticed by the other
+            let _ = unistd::close(fd);
+            self.process_substitutions.clear();
+            self.opts_mut().grab_tty = false;
+            if writable {
+                self.stdin(stdin);
+            } else {
+                self.stdout(stdout);
+            }
+            match self.on_command(command, false) {
+                Ok(()) => self.previous_status,
+                Err(why) => Status::error(format!("{}", why)),
+            }
+        })?;
+        self.process_substitutions.push((pid, end));
+
+        fcntl::fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))
+            .map_err(PipelineError::CloneFdFailed)?;
+        Ok(format!("/dev/fd/{}", fd).into())
+    }
+
+    /// Closes the shell's end of the process substitutions made since the `start`th one, then
+    /// waits for their processes to exit if `wait` is set, or leaves them to the reaper.
+    pub(super) fn reap_substitutions(&mut self, start: usize, wait: bool) {
+        // Every end is closed while collecting the pids, before any process is waited for
+        let pids = self
+            .process_substitutions
+            .drain(start..)
+            .map(|(pid, _end)| pid)
+            .collect::<SmallVec<[Pid; 4]>>();
+        if wait {
+            for pid in pids {
+                let _ = wait::waitpid(pid, None);
+            }
+        } else if !pids.is_empty() {
+            Reaper::get().adopt(pids);
+        }
+    }
+
     /// Executes a piped job `job1 | job2 | job3`
     ///
     /// This function will panic if called with an empty slice
     fn pipe(&mut self, pipeline: Pipeline<RefinedJob<'b>>) -> Result<Status, IonError> {
-        let mut commands = prepare(pipeline)?.into_iter().peekable();
+        // The command that the job is listed as, if it is stopped. Only forked jobs can be
+        // stopped, so it isn't built for the jobs which are run by the shell itself.
+        let runs_in_shell = match pipeline.items.as_slice() {
+            [item] => {
+                item.inputs.len() < 2
+                    && item.outputs.is_empty()
+                    && item.job.redirection == RedirectFrom::None
+                    && !item.job.needs_forking()
+            }
+            _ => false,
+        };
+        let command = if runs_in_shell { String::new() } else { pipeline.to_string() };
+        let mut commands = prepare(pipeline, self.variables.env().cwd())?.into_iter().peekable();
 
         if let Some(mut parent) = commands.next() {
             if parent.redirection == RedirectFrom::None && !parent.needs_forking() {
@@ -414,9 +574,14 @@ impl<'b> Shell<'b> {
                 let _ = io::stdout().flush();
                 let _ = io::stderr().flush();
 
+                if let Ok(status) = status {
+                    self.pipe_status = vec![status];
+                }
                 status
             } else {
                 let (mut pgid, mut last_pid, mut current_pid) = (None, None, Pid::this());
+                // The processes of the pipeline, in order
+                let mut pids = Vec::new();
 
                 // Append jobs until all piped jobs are running
                 for mut child in commands {
@@ -467,6 +632,7 @@ impl<'b> Shell<'b> {
                     }
 
                     spawn_proc(self, parent, &mut last_pid, &mut current_pid, &mut pgid)?;
+                    pids.push(current_pid);
 
                     last_pid = Some(current_pid);
                     parent = child;
@@ -476,6 +642,7 @@ impl<'b> Shell<'b> {
                 }
 
                 spawn_proc(self, parent, &mut last_pid, &mut current_pid, &mut pgid)?;
+                pids.push(current_pid);
                 if self.opts.grab_tty {
                     unistd::tcsetpgrp(nix::libc::STDIN_FILENO, pgid.unwrap())
                         .map_err(PipelineError::TerminalGrabFailed)?;
@@ -485,7 +652,7 @@ impl<'b> Shell<'b> {
                 // Waits for all of the children of the assigned pgid to finish executing,
                 // returning the exit status of the last process in the queue.
                 // Watch the foreground group, dropping all commands that exit as they exit.
-                let status = self.watch_foreground(pgid.unwrap())?;
+                let status = self.watch_foreground(pgid.unwrap(), &pids, &command)?;
                 if status == Status::TERMINATED {
                     signal::killpg(pgid.unwrap(), signal::Signal::SIGTERM)
                         .map_err(PipelineError::TerminateJobsError)?;
@@ -508,11 +675,19 @@ fn spawn_proc(
     current_pid: &mut Pid,
     group: &mut Option<Pid>,
 ) -> Result<(), PipelineError> {
-    let RefinedJob { mut var, mut args, stdin, stdout, stderr, redirection } = cmd;
+    let fds = cmd.raw_fds();
+    // The variables of the job are only set for the time it takes to spawn it
+    let env_bk = shell.variables.env_mut().apply(cmd.env_vars());
+    let cwd = shell.variables.env().cwd().to_path_buf();
+    // The targets of `fds` must stay open until the command has been spawned
+    let RefinedJob { mut var, mut args, stdin, stdout, stderr, redirection, fds: _targets, .. } =
+        cmd;
     let pid = match var {
         Variant::External => {
             let mut command = Command::new(&args[0].as_str());
             command.args(args[1..].iter().map(types::Str::as_str));
+            command.env_clear().envs(shell.variables().env().iter());
+            command.current_dir(&cwd);
 
             command.stdin(stdin.map_or_else(Stdio::inherit, Into::into));
             command.stdout(stdout.map_or_else(Stdio::inherit, Into::into));
@@ -522,7 +697,8 @@ fn spawn_proc(
             unsafe {
                 command.pre_exec(move || {
                     let _ = unistd::setpgid(Pid::this(), grp.unwrap_or_else(Pid::this));
-                    Ok(())
+                    streams::redirect_fds(&fds)
+                        .map_err(|(_, why)| io::Error::new(io::ErrorKind::Other, why))
                 })
             };
             match command.spawn() {
@@ -537,24 +713,33 @@ fn spawn_proc(
             }
         }
         Variant::Builtin { main } => {
-            fork_exec_internal(stdout, stderr, stdin, *group, |_, _, _| main(&args, shell))
+            fork_exec_internal(stdout, stderr, stdin, &fds, *group, &cwd, |_, _, _| {
+                main(&args, shell)
+            })
+        }
+        Variant::Function => {
+            fork_exec_internal(stdout, stderr, stdin, &fds, *group, &cwd, |_, _, _| {
+                forked_status(shell.exec_function(&args[0], &args))
+            })
+        }
+        Variant::Group { ref body, .. } => {
+            fork_exec_internal(stdout, stderr, stdin, &fds, *group, &cwd, |_, _, _| {
+                forked_status(shell.exec_group(body))
+            })
         }
-        Variant::Function => fork_exec_internal(stdout, stderr, stdin, *group, |_, _, _| {
-            shell
-                .exec_function(&args[0], &args)
-                .unwrap_or_else(|why| Status::error(format!("{}", why)))
-        }),
         Variant::Cat { ref mut sources } => {
-            fork_exec_internal(stdout, None, stdin, *group, |_, _, mut stdin| {
+            fork_exec_internal(stdout, None, stdin, &[], *group, &cwd, |_, _, mut stdin| {
                 Shell::exec_multi_in(sources, &mut stdin)
             })
         }
         Variant::Tee { ref mut items } => {
-            fork_exec_internal(stdout, stderr, stdin, *group, |_, _, _| {
+            fork_exec_internal(stdout, stderr, stdin, &[], *group, &cwd, |_, _, _| {
                 Shell::exec_multi_out(items, redirection)
             })
         }
-    }?;
+    };
+    shell.variables.env_mut().restore(env_bk);
+    let pid = pid?;
     *last_pid = Some(std::mem::replace(current_pid, pid));
     if group.is_none() {
         *group = Some(pid);
@@ -563,12 +748,23 @@ fn spawn_proc(
     Ok(())
 }
 
+/// The exit status of a function or group run in a child process. The failure of a pipeline
+/// within a try block is reported to the parent by the status alone.
+fn forked_status(result: Result<Status, IonError>) -> Status {
+    result.unwrap_or_else(|why| match why.origin() {
+        IonError::PipelineExecutionError(PipelineError::Failed(status)) => *status,
+        _ => Status::error(format!("{}", why)),
+    })
+}
+
 // TODO: Integrate this better within the RefinedJob type.
 fn fork_exec_internal<F>(
     stdout: Option<File>,
     stderr: Option<File>,
     stdin: Option<File>,
+    fds: &[(RawFd, Option<RawFd>)],
     pgid: Option<Pid>,
+    cwd: &Path,
     mut exec_action: F,
 ) -> Result<Pid, PipelineError>
 where
@@ -582,9 +778,15 @@ where
                 signal::signal(signal::Signal::SIGTERM, signal::SigHandler::SigIgn).unwrap();
             }
             signals::unblock();
+            // The shell may have a working directory other than that of the process
+            let _ = unistd::chdir(cwd);
 
             unistd::setpgid(Pid::this(), pgid.unwrap_or_else(Pid::this)).unwrap();
             streams::redirect(&stdin, &stdout, &stderr).unwrap();
+            if let Err((fd, why)) = streams::redirect_fds(fds) {
+                streams::report_fd_error(fd, why);
+                exit(1);
+            }
             let exit_status = exec_action(stdout, stderr, stdin);
             exit(exit_status.as_os_code())
         }
@@ -0,0 +1,179 @@
+//! A single thread reaping the background jobs of all the shells of the process.
+//!
+//! The thread sleeps on the read end of a pipe, to which the SIGCHLD handler writes a byte each
+//! time that a child changes state. The jobs being watched are then polled without blocking, so
+//! that their state is updated as soon as the signal is received.
+
+use super::{job_control::WatchedJob, streams::FD_BASE};
+use nix::{
+    fcntl::{self, FcntlArg, OFlag},
+    libc,
+    sys::{
+        signal::{self, SaFlags, SigAction, SigHandler, SigSet, SigmaskHow, Signal},
+        wait::{self, WaitPidFlag, WaitStatus},
+    },
+    unistd::{self, Pid},
+};
+use std::{
+    mem,
+    os::unix::io::RawFd,
+    ptr,
+    sync::{
+        atomic::{AtomicI32, AtomicPtr, Ordering},
+        Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// The reaper of the process, if it was started
+static REAPER: AtomicPtr<Reaper> = AtomicPtr::new(ptr::null_mut());
+/// The end of the pipe to which the SIGCHLD handler writes
+static WAKE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_sigchld(_signal: i32) {
+    let fd = WAKE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        // The pipe is non-blocking: when it is full, the reaper is already going to wake up.
+        unsafe { libc::write(fd, [0u8].as_ptr() as *const libc::c_void, 1) };
+    }
+}
+
+/// Reaps the background jobs, and lets the shells wait for their state to change
+pub(super) struct Reaper {
+    /// The process which started the reaper. A forked child has to start its own.
+    pid:        Pid,
+    wake:       RawFd,
+    wait:       RawFd,
+    /// The jobs sent to the background since the last time that the reaper woke up
+    added:      Mutex<Vec<WatchedJob>>,
+    /// The processes which are not part of a job, and that nothing else waits for
+    orphans:    Mutex<Vec<Pid>>,
+    /// Incremented each time that the reaper has polled the jobs
+    generation: Mutex<usize>,
+    changed:    Condvar,
+}
+
+impl Reaper {
+    /// Get the reaper of the process, starting it on the first call
+    pub(super) fn get() -> &'static Self {
+        let current = REAPER.load(Ordering::SeqCst);
+        if let Some(reaper) = unsafe { current.as_ref() } {
+            if reaper.pid == Pid::this() {
+                return reaper;
+            }
+        }
+
+        let new = Box::into_raw(Box::new(Self::new()));
+        match REAPER.compare_exchange(current, new, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => {
+                let reaper = unsafe { &*new };
+                reaper.start();
+                reaper
+            }
+            Err(winner) => {
+                // Another thread started the reaper in the meantime
+                drop(unsafe { Box::from_raw(new) });
+                unsafe { &*winner }
+            }
+        }
+    }
+
+    fn new() -> Self {
+        // The pipe is moved out of the range of descriptors that commands may redirect, as the
+        // redirections of builtins and functions are applied within the shell
+        let relocate = |fd| {
+            let moved = fcntl::fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(FD_BASE))
+                .expect("could not move the pipe");
+            let _ = unistd::close(fd);
+            moved
+        };
+        let (wait, wake) = unistd::pipe().expect("could not create a pipe");
+        let (wait, wake) = (relocate(wait), relocate(wake));
+        fcntl::fcntl(wake, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+            .expect("could not make the pipe non-blocking");
+        Self {
+            pid: Pid::this(),
+            wake,
+            wait,
+            added: Mutex::new(Vec::new()),
+            orphans: Mutex::new(Vec::new()),
+            generation: Mutex::new(0),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn start(&'static self) {
+        WAKE.store(self.wake, Ordering::SeqCst);
+        let action =
+            SigAction::new(SigHandler::Handler(on_sigchld), SaFlags::SA_RESTART, SigSet::empty());
+        unsafe { signal::sigaction(Signal::SIGCHLD, &action) }
+            .expect("could not install the SIGCHLD handler");
+        let _ = thread::spawn(move || self.run());
+    }
+
+    /// Watch a job until it exits
+    pub(super) fn watch(&self, job: WatchedJob) {
+        self.added.lock().unwrap().push(job);
+        self.wake();
+    }
+
+    /// Reap the processes once they exit, without reporting anything about them
+    pub(super) fn adopt<I: IntoIterator<Item = Pid>>(&self, pids: I) {
+        self.orphans.lock().unwrap().extend(pids);
+        self.wake();
+    }
+
+    /// Make the reaper poll the jobs, as if a child had changed state
+    pub(super) fn wake(&self) { let _ = unistd::write(self.wake, &[0]); }
+
+    /// The number of times that the reaper has polled the jobs
+    pub(super) fn generation(&self) -> usize { *self.generation.lock().unwrap() }
+
+    /// Wait until the reaper has polled the jobs since `generation`, or until the timeout expires
+    pub(super) fn wait_for_change(&self, generation: usize, timeout: Duration) {
+        let current = self.generation.lock().unwrap();
+        if *current == generation {
+            let _ = self.changed.wait_timeout(current, timeout);
+        }
+    }
+
+    fn run(&self) {
+        // Leave the other signals to the shell, which blocks SIGCHLD most of the time
+        let mut mask = SigSet::all();
+        mask.remove(Signal::SIGCHLD);
+        let _ = signal::pthread_sigmask(SigmaskHow::SIG_SETMASK, Some(&mask), None);
+
+        let mut jobs = Vec::new();
+        let mut buffer = [0; 64];
+        loop {
+            match unistd::read(self.wait, &mut buffer) {
+                Ok(_) | Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => (),
+                Err(_) => return,
+            }
+
+            jobs.extend(mem::take(&mut *self.added.lock().unwrap()));
+            let mut index = 0;
+            while index < jobs.len() {
+                if jobs[index].reap() {
+                    index += 1;
+                } else {
+                    jobs.swap_remove(index);
+                }
+            }
+            self.orphans.lock().unwrap().retain(|&pid| {
+                matches!(wait::waitpid(pid, Some(WaitPidFlag::WNOHANG)), Ok(WaitStatus::StillAlive))
+            });
+
+            *self.generation.lock().unwrap() += 1;
+            self.changed.notify_all();
+        }
+    }
+}
+
+impl Drop for Reaper {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.wait);
+        let _ = unistd::close(self.wake);
+    }
+}
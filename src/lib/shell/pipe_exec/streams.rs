@@ -1,9 +1,13 @@
-use crate::PipelineError;
-use nix::unistd;
+use crate::{expansion::pipelines::FD_LIMIT, PipelineError};
+use nix::{
+    errno::Errno,
+    fcntl::{self, FcntlArg, FdFlag},
+    unistd,
+};
 use std::{
     fs::File,
     io,
-    os::unix::io::{AsRawFd, FromRawFd},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
 };
 
 /// Use dup2 to replace `old` with `new` using `old`s file descriptor ID
@@ -38,3 +42,71 @@ pub fn redirect(
     redir(out, &io::stdout())?;
     redir(err, &io::stderr())
 }
+
+/// The lowest descriptor that files and backups are moved to, so that they are not clobbered by
+/// a later redirection of a low, user-facing descriptor. Commands can not redirect it or above.
+pub(super) const FD_BASE: RawFd = FD_LIMIT;
+
+/// Moves `file` to a close-on-exec descriptor of at least `FD_BASE`.
+pub fn relocate(file: &File) -> nix::Result<File> {
+    fcntl::fcntl(file.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(FD_BASE))
+        .map(|fd| unsafe { File::from_raw_fd(fd) })
+}
+
+/// Points each numbered file descriptor at its target, in order. A target of `None` closes the
+/// descriptor. On failure, the target which could not be copied is returned with the error.
+///
+/// This does not allocate, so that it can be called between forking and executing a command.
+pub fn redirect_fds(fds: &[(RawFd, Option<RawFd>)]) -> Result<(), (RawFd, nix::Error)> {
+    for &(fd, target) in fds {
+        match target {
+            // dup2 is a no-op here, so the descriptor must be kept open across exec by hand
+            Some(target) if target == fd => {
+                fcntl::fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))
+                    .map_err(|why| (target, why))?;
+            }
+            Some(target) => {
+                unistd::dup2(target, fd).map_err(|why| (target, why))?;
+            }
+            // Closing a descriptor that is not open is not an error
+            None => {
+                let _ = unistd::close(fd);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reports the target of a numbered redirection which `redirect_fds` could not copy.
+pub fn report_fd_error(fd: RawFd, why: nix::Error) {
+    match why {
+        nix::Error::Sys(Errno::EBADF) => eprintln!("ion: {}: bad file descriptor", fd),
+        why => eprintln!("ion: {}: {}", fd, why),
+    }
+}
+
+/// Duplicates the numbered file descriptors that `redirect_fds` will replace, so that they can
+/// be put back with `restore_fds` once an in-process command is done.
+pub fn duplicate_fds(fds: &[(RawFd, Option<RawFd>)]) -> Vec<(RawFd, Option<File>)> {
+    fds.iter()
+        .map(|&(fd, _)| {
+            let backup = fcntl::fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(FD_BASE));
+            (fd, backup.ok().map(|dup| unsafe { File::from_raw_fd(dup) }))
+        })
+        .collect()
+}
+
+/// Restores the file descriptors saved by `duplicate_fds`, closing those that were not open.
+pub fn restore_fds(backups: Vec<(RawFd, Option<File>)>) -> Result<(), PipelineError> {
+    for (fd, backup) in backups.into_iter().rev() {
+        match backup {
+            Some(backup) => {
+                unistd::dup2(backup.as_raw_fd(), fd).map_err(PipelineError::CloneFdFailed)?;
+            }
+            None => {
+                let _ = unistd::close(fd);
+            }
+        }
+    }
+    Ok(())
+}
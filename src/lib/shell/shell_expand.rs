@@ -5,22 +5,22 @@ use crate::{
     expansion::{Error, Expander, Result, Select},
     types,
 };
+use itertools::Itertools;
 use nix::unistd::{tcsetpgrp, Pid};
 #[cfg(target_os = "redox")]
 use redox_users::All;
-use std::{env, fs::File, io::Read, iter::FromIterator};
+use std::{fs::File, io::Read, iter::FromIterator, mem, rc::Rc};
 #[cfg(not(target_os = "redox"))]
 use users::os::unix::UserExt;
 
-impl<'a, 'b> Expander for Shell<'b> {
-    type Error = IonError;
-
-    /// Uses a subshell to expand a given command.
-    fn command(
+impl<'b> Shell<'b> {
+    /// Runs a command with its output captured, along with the value returned by the function
+    /// that it called, if any.
+    fn capture(
         &mut self,
         command: &str,
         set_cmd_duration: bool,
-    ) -> Result<types::Str, Self::Error> {
+    ) -> Result<(types::Str, Option<Value<Rc<types::Function>>>), IonError> {
         let (mut reader, writer) = create_pipe()
             .map_err(|err| Error::Subprocess(Box::new(IonError::PipelineExecutionError(err))))?;
         let null_file = File::open(NULL_PATH).map_err(|err| {
@@ -29,6 +29,9 @@ impl<'a, 'b> Expander for Shell<'b> {
             )))
         })?;
 
+        // Only a value returned by a function called by the command is captured
+        self.returned = None;
+
         // Store the previous default redirections
         let prev_stdout = self.stdout(writer);
         let prev_stderr = self.stderr(null_file);
@@ -46,13 +49,86 @@ impl<'a, 'b> Expander for Shell<'b> {
         let _ = tcsetpgrp(nix::libc::STDIN_FILENO, Pid::this());
 
         result?;
+        let returned = self.returned.take();
 
         let mut string = String::with_capacity(1024);
         match reader.read_to_string(&mut string) {
-            Ok(_) => Ok(string.into()),
+            Ok(_) => Ok((string.into(), returned)),
             Err(why) => Err(Error::Subprocess(Box::new(PipelineError::CaptureFailed(why).into()))),
         }
     }
+}
+
+impl<'a, 'b> Expander for Shell<'b> {
+    type Error = IonError;
+
+    /// Uses a subshell to expand a given command. The value returned by the function that it
+    /// calls replaces its output.
+    fn command(
+        &mut self,
+        command: &str,
+        set_cmd_duration: bool,
+    ) -> Result<types::Str, Self::Error> {
+        match self.capture(command, set_cmd_duration)? {
+            (_, Some(value)) => Ok(value.to_string().into()),
+            (output, None) => Ok(output),
+        }
+    }
+
+    /// Uses a subshell to expand a given command into an array, whose elements are those of the
+    /// value returned by the function that it calls, if any.
+    fn command_array(
+        &mut self,
+        command: &str,
+        set_cmd_duration: bool,
+    ) -> Result<types::Args, Self::Error> {
+        match self.capture(command, set_cmd_duration)? {
+            (_, Some(Value::Str(string))) => Ok(args![string]),
+            (_, Some(value)) => select(&value, &Select::All, command),
+            (output, None) => Ok(output.split_whitespace().map(Into::into).collect()),
+        }
+    }
+
+    /// Uses a subshell to expand a given command into the value returned by the function that it
+    /// calls, or into its output.
+    fn command_value(&mut self, command: &str) -> Result<Value<Rc<types::Function>>, Self::Error> {
+        match self.capture(command, true)? {
+            (_, Some(value)) => Ok(value),
+            (output, None) => Ok(Value::Str(output.split_whitespace().join(" ").into())),
+        }
+    }
+
+    /// Calls a function with the value bound to its argument, or evaluates an inline expression
+    /// with the value bound to `$it`. Failures are not caught by try blocks, since they are tested.
+    fn apply(&mut self, function: &str, value: &str) -> Result<(bool, types::Str), Self::Error> {
+        let command = match self.variables.get(function) {
+            Some(Value::Function(_)) => format!("{} $it", function),
+            _ => function.to_owned(),
+        };
+
+        self.variables.new_scope(false);
+        self.variables.shadow("it", Value::Str(value.into()));
+        let catching = mem::replace(&mut self.catching, false);
+        let result = self.capture(&command, false);
+        self.catching = catching;
+        self.variables.pop_scope();
+
+        let output = match result? {
+            (_, Some(value)) => value.to_string().into(),
+            (output, None) => output.trim_end_matches('\n').into(),
+        };
+        Ok((self.previous_status.is_success(), output))
+    }
+
+    /// Forks a process substitution, connected to the returned path through a pipe.
+    fn process_substitution(
+        &mut self,
+        command: &str,
+        writable: bool,
+    ) -> Result<types::Str, Self::Error> {
+        self.substitute_process(command, writable)
+            .map_err(|err| Error::Subprocess(Box::new(IonError::PipelineExecutionError(err))))
+    }
 
     /// Expand a string variable given if its quoted / unquoted
     fn string(&self, name: &str) -> Result<types::Str, Self::Error> {
@@ -69,116 +145,50 @@ impl<'a, 'b> Expander for Shell<'b> {
         name: &str,
         selection: &Select<types::Str>,
     ) -> Result<types::Args, Self::Error> {
-        match self.variables.get(name) {
-            Some(Value::Array(array)) => match selection {
-                Select::All => {
-                    Ok(types::Args::from_iter(array.iter().map(|x| format!("{}", x).into())))
-                }
-                Select::Index(ref id) => id
-                    .resolve(array.len())
-                    .and_then(|n| array.get(n))
-                    .map(|x| args![types::Str::from(format!("{}", x))])
-                    .ok_or(Error::OutOfBound),
-                Select::Range(ref range) => range
-                    .bounds(array.len())
-                    .and_then(|(start, length)| {
-                        if array.len() > start {
-                            Some(
-                                array
-                                    .iter()
-                                    .skip(start)
-                                    .take(length)
-                                    .map(|var| format!("{}", var).into())
-                                    .collect(),
-                            )
-                        } else {
-                            None
-                        }
-                    })
-                    .ok_or(Error::OutOfBound),
-                Select::Key(_) => Err(Error::InvalidIndex(selection.clone(), "array", name.into())),
-            },
-            Some(Value::HashMap(hmap)) => match selection {
-                Select::All => {
-                    let mut array = types::Args::new();
-                    for (key, value) in hmap.iter() {
-                        array.push(key.clone());
-                        let f = format!("{}", value);
-                        match *value {
-                            Value::Str(_) => array.push(f.into()),
-                            Value::Array(_) | Value::HashMap(_) | Value::BTreeMap(_) => {
-                                for split in f.split_whitespace() {
-                                    array.push(split.into());
-                                }
-                            }
-                            _ => (),
-                        }
-                    }
-                    Ok(array)
-                }
-                Select::Key(key) => {
-                    Ok(args![format!("{}", hmap.get(&*key).unwrap_or(&Value::Str("".into())))])
-                }
-                Select::Index(index) => {
-                    use crate::ranges::Index;
-                    Ok(args![format!(
-                        "{}",
-                        hmap.get(&types::Str::from(
-                            match index {
-                                Index::Forward(n) => *n as isize,
-                                Index::Backward(n) => -((*n + 1) as isize),
-                            }
-                            .to_string()
-                        ))
-                        .unwrap_or(&Value::Str("".into()))
-                    )])
-                }
-                Select::Range(_) => {
-                    Err(Error::InvalidIndex(selection.clone(), "hashmap", name.into()))
-                }
-            },
-            Some(Value::BTreeMap(bmap)) => match selection {
-                Select::All => {
-                    let mut array = types::Args::new();
-                    for (key, value) in bmap.iter() {
-                        array.push(key.clone());
-                        let f = format!("{}", value);
-                        match *value {
-                            Value::Str(_) => array.push(f.into()),
-                            Value::Array(_) | Value::HashMap(_) | Value::BTreeMap(_) => {
-                                for split in f.split_whitespace() {
-                                    array.push(split.into());
-                                }
-                            }
-                            _ => (),
-                        }
-                    }
-                    Ok(array)
-                }
-                Select::Key(key) => {
-                    Ok(args![format!("{}", bmap.get(&*key).unwrap_or(&Value::Str("".into())))])
-                }
-                Select::Index(index) => {
-                    use crate::ranges::Index;
-                    Ok(args![format!(
-                        "{}",
-                        bmap.get(&types::Str::from(
-                            match index {
-                                Index::Forward(n) => *n as isize,
-                                Index::Backward(n) => -((*n + 1) as isize),
-                            }
-                            .to_string()
-                        ))
-                        .unwrap_or(&Value::Str("".into()))
-                    )])
-                }
-                Select::Range(_) => {
-                    Err(Error::InvalidIndex(selection.clone(), "btreemap", name.into()))
+        self.nested(name, &[], selection)
+    }
+
+    /// Expand a value nested within an array or map variable with some selection
+    fn nested(
+        &self,
+        name: &str,
+        path: &[Select<types::Str>],
+        selection: &Select<types::Str>,
+    ) -> Result<types::Args, Self::Error> {
+        let mut value = self.variables.get(name).ok_or(Error::VarNotFound)?;
+        for index in path {
+            value = match nested_value(value, index, name)? {
+                Some(value) => value,
+                None => return Ok(args![""]),
+            };
+        }
+        select(value, selection, name)
+    }
+
+    /// Expand the values nested within a variable, at any depth
+    fn flatten(&self, name: &str) -> Result<types::Args, Self::Error> {
+        fn flatten_into(value: &Value<Rc<types::Function>>, output: &mut types::Args) {
+            match value {
+                Value::Array(array) => array.iter().for_each(|value| flatten_into(value, output)),
+                // The values are taken in the order of their keys, as in JSON
+                Value::HashMap(map) => {
+                    let mut entries: Vec<_> = map.iter().collect();
+                    entries.sort_by(|a, b| a.0.cmp(b.0));
+                    entries.into_iter().for_each(|(_, value)| flatten_into(value, output))
                 }
-            },
-            None => Err(Error::VarNotFound),
-            _ => Err(Error::ScalarAsArray(name.into())),
+                Value::BTreeMap(map) => map.values().for_each(|value| flatten_into(value, output)),
+                value => output.push(value.to_string().into()),
+            }
         }
+
+        let mut output = types::Args::new();
+        flatten_into(self.variables.get(name).ok_or(Error::VarNotFound)?, &mut output);
+        Ok(output)
+    }
+
+    /// Serialize a variable to JSON
+    fn to_json(&self, name: &str) -> Result<types::Str, Self::Error> {
+        self.variables.get(name).map(|value| value.to_json().into()).ok_or(Error::VarNotFound)
     }
 
     fn map_keys(&self, name: &str) -> Result<types::Args, Self::Error> {
@@ -217,7 +227,7 @@ impl<'a, 'b> Expander for Shell<'b> {
         let (tilde_prefix, rest) = input[1..].split_at(separator.unwrap_or(input.len() - 1));
 
         match tilde_prefix {
-            "+" => Ok(env::var("PWD").unwrap_or_else(|_| "?".into()).into()),
+            "+" => Ok(self.variables.env().get("PWD").unwrap_or("?").into()),
             "-" => Ok(self.variables.get_str("OLDPWD")?),
             _ => {
                 let (neg, tilde_num) = if tilde_prefix.starts_with('+') {
@@ -273,3 +283,149 @@ impl<'a, 'b> Expander for Shell<'b> {
         .map(|home: types::Str| home + rest)
     }
 }
+
+/// Select within an array or map value
+fn select(
+    value: &Value<Rc<types::Function>>,
+    selection: &Select<types::Str>,
+    name: &str,
+) -> Result<types::Args, IonError> {
+    match value {
+        Value::Array(array) => match selection {
+            Select::All => {
+                Ok(types::Args::from_iter(array.iter().map(|x| format!("{}", x).into())))
+            }
+            Select::Index(ref id) => id
+                .resolve(array.len())
+                .and_then(|n| array.get(n))
+                .map(|x| args![types::Str::from(format!("{}", x))])
+                .ok_or(Error::OutOfBound),
+            Select::Range(ref range) => range
+                .bounds(array.len())
+                .and_then(|(start, length)| {
+                    if array.len() > start {
+                        Some(
+                            array
+                                .iter()
+                                .skip(start)
+                                .take(length)
+                                .map(|var| format!("{}", var).into())
+                                .collect(),
+                        )
+                    } else {
+                        None
+                    }
+                })
+                .ok_or(Error::OutOfBound),
+            Select::Key(_) => Err(Error::InvalidIndex(selection.clone(), "array", name.into())),
+        },
+        Value::HashMap(hmap) => match selection {
+            Select::All => {
+                let mut array = types::Args::new();
+                for (key, value) in hmap.iter() {
+                    array.push(key.clone());
+                    let f = format!("{}", value);
+                    match *value {
+                        Value::Str(_) => array.push(f.into()),
+                        Value::Array(_) | Value::HashMap(_) | Value::BTreeMap(_) => {
+                            for split in f.split_whitespace() {
+                                array.push(split.into());
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                Ok(array)
+            }
+            Select::Key(key) => {
+                Ok(args![format!("{}", hmap.get(&*key).unwrap_or(&Value::Str("".into())))])
+            }
+            Select::Index(index) => {
+                use crate::ranges::Index;
+                Ok(args![format!(
+                    "{}",
+                    hmap.get(&types::Str::from(
+                        match index {
+                            Index::Forward(n) => *n as isize,
+                            Index::Backward(n) => -((*n + 1) as isize),
+                        }
+                        .to_string()
+                    ))
+                    .unwrap_or(&Value::Str("".into()))
+                )])
+            }
+            Select::Range(_) => Err(Error::InvalidIndex(selection.clone(), "hashmap", name.into())),
+        },
+        Value::BTreeMap(bmap) => match selection {
+            Select::All => {
+                let mut array = types::Args::new();
+                for (key, value) in bmap.iter() {
+                    array.push(key.clone());
+                    let f = format!("{}", value);
+                    match *value {
+                        Value::Str(_) => array.push(f.into()),
+                        Value::Array(_) | Value::HashMap(_) | Value::BTreeMap(_) => {
+                            for split in f.split_whitespace() {
+                                array.push(split.into());
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                Ok(array)
+            }
+            Select::Key(key) => {
+                Ok(args![format!("{}", bmap.get(&*key).unwrap_or(&Value::Str("".into())))])
+            }
+            Select::Index(index) => {
+                use crate::ranges::Index;
+                Ok(args![format!(
+                    "{}",
+                    bmap.get(&types::Str::from(
+                        match index {
+                            Index::Forward(n) => *n as isize,
+                            Index::Backward(n) => -((*n + 1) as isize),
+                        }
+                        .to_string()
+                    ))
+                    .unwrap_or(&Value::Str("".into()))
+                )])
+            }
+            Select::Range(_) => {
+                Err(Error::InvalidIndex(selection.clone(), "btreemap", name.into()))
+            }
+        },
+        _ => Err(Error::ScalarAsArray(name.into())),
+    }
+}
+
+/// Get the value nested within an array or map at the given index, if there is one
+fn nested_value<'a>(
+    value: &'a Value<Rc<types::Function>>,
+    index: &Select<types::Str>,
+    name: &str,
+) -> Result<Option<&'a Value<Rc<types::Function>>>, IonError> {
+    use crate::ranges::Index;
+    let index_key = |index: &Index| {
+        types::Str::from(
+            match index {
+                Index::Forward(n) => *n as isize,
+                Index::Backward(n) => -((*n + 1) as isize),
+            }
+            .to_string(),
+        )
+    };
+    match (value, index) {
+        (Value::Array(array), Select::Index(id)) => {
+            id.resolve(array.len()).and_then(|n| array.get(n)).map(Some).ok_or(Error::OutOfBound)
+        }
+        (Value::HashMap(hmap), Select::Key(key)) => Ok(hmap.get(key)),
+        (Value::HashMap(hmap), Select::Index(index)) => Ok(hmap.get(&index_key(index))),
+        (Value::BTreeMap(bmap), Select::Key(key)) => Ok(bmap.get(key)),
+        (Value::BTreeMap(bmap), Select::Index(index)) => Ok(bmap.get(&index_key(index))),
+        (Value::Array(_), _) => Err(Error::InvalidIndex(index.clone(), "array", name.into())),
+        (Value::HashMap(_), _) => Err(Error::InvalidIndex(index.clone(), "hashmap", name.into())),
+        (Value::BTreeMap(_), _) => Err(Error::InvalidIndex(index.clone(), "btreemap", name.into())),
+        _ => Err(Error::ScalarAsArray(name.into())),
+    }
+}
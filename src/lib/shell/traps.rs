@@ -0,0 +1,150 @@
+//! Handlers bound to signals and pseudo-signals with the `trap` builtin.
+
+use super::Shell;
+use crate::types;
+use nix::sys::signal::Signal;
+use std::{collections::BTreeMap, convert::TryFrom, fmt, mem, str::FromStr};
+use thiserror::Error;
+
+/// The signals which can be trapped by the shell
+const TRAPPABLE: [Signal; 3] = [Signal::SIGHUP, Signal::SIGINT, Signal::SIGTERM];
+
+/// An event which a handler can be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Trap {
+    /// The shell is exiting
+    Exit,
+    /// A pipeline returned a non-zero exit status
+    Error,
+    /// The shell received a signal
+    Signal(Signal),
+}
+
+/// The given string does not name a signal that can be trapped
+#[derive(Debug, Error, Clone, PartialEq, Eq, Hash)]
+#[error("'{0}' is not a valid signal specification")]
+pub struct InvalidTrap(String);
+
+impl Trap {
+    /// The list of all the events that can be trapped
+    pub fn all() -> impl Iterator<Item = Self> {
+        [Self::Exit, Self::Error].iter().copied().chain(TRAPPABLE.iter().copied().map(Self::Signal))
+    }
+}
+
+impl FromStr for Trap {
+    type Err = InvalidTrap;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let signal = match name.to_uppercase().as_str() {
+            "EXIT" | "0" => return Ok(Self::Exit),
+            "ERR" => return Ok(Self::Error),
+            number if number.bytes().all(|b| b.is_ascii_digit()) => {
+                number.parse::<i32>().ok().and_then(|number| Signal::try_from(number).ok())
+            }
+            name if name.starts_with("SIG") => Signal::from_str(name).ok(),
+            name => Signal::from_str(&["SIG", name].concat()).ok(),
+        };
+
+        signal
+            .filter(|signal| TRAPPABLE.contains(signal))
+            .map(Self::Signal)
+            .ok_or_else(|| InvalidTrap(name.into()))
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exit => write!(f, "EXIT"),
+            Self::Error => write!(f, "ERR"),
+            Self::Signal(signal) => write!(f, "{}", &signal.as_str()[3..]),
+        }
+    }
+}
+
+/// The table of handlers registered with the `trap` builtin
+///
+/// An empty handler means that the event is ignored.
+#[derive(Debug, Clone, Default)]
+pub struct Traps {
+    handlers: BTreeMap<Trap, types::Str>,
+    running:  bool,
+}
+
+impl Traps {
+    /// Bind a command to the given event, replacing the previous one
+    pub fn set(&mut self, trap: Trap, handler: types::Str) -> Option<types::Str> {
+        self.handlers.insert(trap, handler)
+    }
+
+    /// Restore the default behavior for the given event
+    pub fn reset(&mut self, trap: Trap) -> Option<types::Str> { self.handlers.remove(&trap) }
+
+    /// Get the command bound to the given event
+    #[must_use]
+    pub fn get(&self, trap: Trap) -> Option<&types::Str> { self.handlers.get(&trap) }
+
+    /// Iterate over all the registered handlers
+    pub fn iter(&self) -> impl Iterator<Item = (Trap, &types::Str)> {
+        self.handlers.iter().map(|(&trap, handler)| (trap, handler))
+    }
+}
+
+impl<'a> Shell<'a> {
+    /// Run the handler bound to the given event, if any, and return whether one was found.
+    ///
+    /// The exit status of the handler is discarded so that `$?` is left untouched. The `EXIT`
+    /// handler is removed before it is run, so that it executes at most once.
+    pub fn run_trap(&mut self, trap: Trap) -> bool {
+        let handler =
+            if trap == Trap::Exit { self.traps.reset(trap) } else { self.traps.get(trap).cloned() };
+
+        let handler = match handler {
+            Some(handler) => handler,
+            None => return false,
+        };
+        // Do not trigger handlers from within handlers, unless the handler exits the shell
+        if handler.is_empty() || (self.traps.running && trap != Trap::Exit) {
+            return true;
+        }
+
+        let previous_status = self.previous_status;
+        let flow_control = mem::take(&mut self.flow_control);
+        let running = mem::replace(&mut self.traps.running, true);
+        let catching = mem::replace(&mut self.catching, false);
+        if let Err(why) = self.on_command(&handler, false) {
+            eprintln!("ion: trap: {}: {}", trap, why);
+        }
+        self.catching = catching;
+        self.traps.running = running;
+        self.flow_control = flow_control;
+        self.previous_status = previous_status;
+        self.variables.set("?", previous_status);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_traps() {
+        assert_eq!("EXIT".parse::<Trap>(), Ok(Trap::Exit));
+        assert_eq!("0".parse::<Trap>(), Ok(Trap::Exit));
+        assert_eq!("err".parse::<Trap>(), Ok(Trap::Error));
+        assert_eq!("INT".parse::<Trap>(), Ok(Trap::Signal(Signal::SIGINT)));
+        assert_eq!("SIGTERM".parse::<Trap>(), Ok(Trap::Signal(Signal::SIGTERM)));
+        assert_eq!("1".parse::<Trap>(), Ok(Trap::Signal(Signal::SIGHUP)));
+        assert!("KILL".parse::<Trap>().is_err());
+        assert!("FOO".parse::<Trap>().is_err());
+    }
+
+    #[test]
+    fn display_traps() {
+        let names = Trap::all().map(|trap| trap.to_string()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["EXIT", "ERR", "HUP", "INT", "TERM"]);
+    }
+}
@@ -1,4 +1,4 @@
-use super::{colors::Colors, flow_control::Function};
+use super::{colors::Colors, flow_control::Function, Environment};
 use crate::{
     expansion,
     shell::IonError,
@@ -6,13 +6,14 @@ use crate::{
 };
 use nix::unistd::{geteuid, gethostname, getpid, getuid};
 use scopes::{Namespace, Scope, Scopes};
-use std::{env, ffi::CStr, rc::Rc};
+use std::{ffi::CStr, rc::Rc};
 use unicode_segmentation::UnicodeSegmentation;
 
 /// Contain a dynamically-typed variable value
 pub use types_rs::Value;
-/// A structure containing dynamically-typed values organised in scopes
-pub struct Variables(Scopes<types::Str, Value<Rc<Function>>>);
+/// A structure containing dynamically-typed values organised in scopes, along with the
+/// environment exported to the commands
+pub struct Variables(Scopes<types::Str, Value<Rc<Function>>>, Environment);
 
 impl Variables {
     /// Get all strings
@@ -87,6 +88,34 @@ impl Variables {
     /// Exit the current scope
     pub fn pop_scope(&mut self) { self.0.pop_scope() }
 
+    /// Create a new module, and enter the namespace scope holding its members. Returns the
+    /// index of the module.
+    pub(crate) fn new_module(&mut self) -> usize { self.0.new_module() }
+
+    /// Enter the namespace scope holding the members of a module, which keeps the changes made
+    /// to them
+    pub(crate) fn enter_module(&mut self, module: usize) { self.0.enter_module(module) }
+
+    /// The module whose members are held by the current scope
+    #[must_use]
+    pub(crate) fn current_module(&self) -> Option<usize> { self.0.current_module() }
+
+    /// Bind the members of a module to a namespace in the current scope
+    pub(crate) fn import(&mut self, namespace: &str, module: usize) {
+        self.0.import(namespace, module)
+    }
+
+    /// The module bound to the namespaces leading `name`, such as `outer::inner::`, along with
+    /// the name of the member
+    fn module_member<'b>(&self, mut name: &'b str) -> Option<(usize, &'b str)> {
+        let mut module = None;
+        while let Some(pos) = name.find("::") {
+            module = Some(self.0.module(&name[..pos], module)?);
+            name = &name[pos + 2..];
+        }
+        module.map(|module| (module, name))
+    }
+
     pub(crate) fn pop_scopes<'b>(
         &'b mut self,
         index: usize,
@@ -115,6 +144,19 @@ impl Variables {
         }
     }
 
+    /// Set a variable in the current scope, shadowing the variables of the same name
+    pub(crate) fn shadow<T: Into<Value<Rc<Function>>>>(&mut self, name: &str, value: T) {
+        self.0.set(name, value.into());
+    }
+
+    /// Access to the environment exported to the commands
+    #[must_use]
+    pub const fn env(&self) -> &Environment { &self.1 }
+
+    /// Mutable access to the environment exported to the commands
+    #[must_use]
+    pub fn env_mut(&mut self) -> &mut Environment { &mut self.1 }
+
     /// Obtains the value for the **MWD** variable.
     ///
     /// Further minimizes the directory path in the same manner that Fish does by default.
@@ -155,7 +197,7 @@ impl Variables {
     #[must_use]
     fn get_simplified_directory(&self) -> types::Str {
         let home = self.get_str("HOME").unwrap_or_else(|_| "?".into());
-        env::var("PWD").unwrap().replace(&*home, "~").into()
+        self.1.get("PWD").unwrap_or("?").replace(&*home, "~").into()
     }
 
     /// Indicates if name is valid for functions and variables
@@ -196,15 +238,19 @@ impl Variables {
                     .map_err(|cause| Error::InvalidHex(variable.into(), cause))?;
                 Ok((c as char).to_string().into())
             }
-            Some(("env", variable)) => Ok(env::var(variable).unwrap_or_default().into()),
+            Some(("env", variable)) => Ok(self.1.get(variable).unwrap_or_default().into()),
             Some(("super", _)) | Some(("global", _)) | None => {
                 // Otherwise, it's just a simple variable name.
                 match self.get(name) {
                     Some(Value::Str(val)) => Ok(val.clone()),
-                    _ => env::var(name).map(Into::into).map_err(|_| Error::VarNotFound),
+                    _ => self.1.get(name).map(Into::into).ok_or(Error::VarNotFound),
                 }
             }
-            Some((..)) => Err(Error::UnsupportedNamespace(name.into())),
+            // The members of the imported modules
+            Some((..)) => match self.get(name) {
+                Some(Value::Str(val)) => Ok(val.clone()),
+                _ => Err(Error::UnsupportedNamespace(name.into())),
+            },
         }
     }
 
@@ -226,6 +272,9 @@ impl Variables {
             }
 
             Namespace::Specific(up)
+        } else if let Some((module, member)) = self.module_member(name) {
+            name = member;
+            Namespace::Module(module)
         } else {
             Namespace::Any
         };
@@ -264,16 +313,14 @@ impl Default for Variables {
         map.set("CDPATH", Array::new());
 
         // Initialize the HOST variable
+        let mut env = Environment::from_process();
         let mut host_name = [0_u8; 512];
-        env::set_var(
+        env.set(
             "HOST",
-            &gethostname(&mut host_name)
-                .ok()
-                .map_or_else(|| "?".into(), CStr::to_string_lossy)
-                .as_ref(),
+            gethostname(&mut host_name).ok().map_or_else(|| "?".into(), CStr::to_string_lossy),
         );
 
-        Variables(map)
+        Variables(map, env)
     }
 }
 
@@ -284,7 +331,6 @@ pub(crate) mod tests {
         expansion::{Expander, Result, Select},
         shell::IonError,
     };
-    use serial_test_derive::serial;
 
     pub struct VariableExpander(pub Variables);
 
@@ -309,6 +355,14 @@ pub(crate) mod tests {
             Ok(cmd.into())
         }
 
+        fn process_substitution(
+            &mut self,
+            cmd: &str,
+            _writable: bool,
+        ) -> Result<types::Str, Self::Error> {
+            Ok(cmd.into())
+        }
+
         fn tilde(&self, input: &str) -> Result<types::Str, Self::Error> { Ok(input.into()) }
 
         fn map_keys(&self, _name: &str) -> Result<types::Args, Self::Error> {
@@ -318,6 +372,14 @@ pub(crate) mod tests {
         fn map_values(&self, _name: &str) -> Result<types::Args, Self::Error> {
             Err(expansion::Error::VarNotFound)
         }
+
+        fn apply(
+            &mut self,
+            _function: &str,
+            _value: &str,
+        ) -> Result<(bool, types::Str), Self::Error> {
+            Err(expansion::Error::VarNotFound)
+        }
     }
 
     #[test]
@@ -335,10 +397,54 @@ pub(crate) mod tests {
     }
 
     #[test]
-    #[serial]
+    fn env_namespace_reads_own_environment() {
+        let mut variables = Variables::default();
+        variables.env_mut().set("ION_EXPORTED", "value");
+        assert_eq!(variables.get_str("env::ION_EXPORTED").unwrap(), types::Str::from("value"));
+        assert_eq!(variables.get_str("ION_EXPORTED").unwrap(), types::Str::from("value"));
+        assert!(std::env::var("ION_EXPORTED").is_err());
+        assert!(Variables::default().get_str("ION_EXPORTED").is_err());
+    }
+
+    #[test]
+    fn module_members() {
+        let mut variables = Variables::default();
+        variables.set("name", "global");
+        let lib = variables.new_module();
+        variables.set("name", "value");
+        let inner = variables.new_module();
+        variables.set("name", "inner");
+        variables.pop_scope();
+        variables.import("inner", inner);
+        variables.pop_scope();
+        variables.import("lib", lib);
+        assert_eq!(variables.get_str("lib::name").unwrap(), types::Str::from("value"));
+        assert_eq!(variables.get_str("lib::inner::name").unwrap(), types::Str::from("inner"));
+        assert!(variables.get_str("other::name").is_err());
+        assert_eq!(variables.get_str("name").unwrap(), types::Str::from("global"));
+
+        // The functions of a module see its members, and change them
+        variables.enter_module(lib);
+        variables.new_scope(false);
+        assert_eq!(variables.get_str("name").unwrap(), types::Str::from("value"));
+        assert_eq!(variables.get_str("inner::name").unwrap(), types::Str::from("inner"));
+        variables.set("name", "changed");
+        variables.pop_scope();
+        variables.pop_scope();
+        assert_eq!(variables.get_str("lib::name").unwrap(), types::Str::from("changed"));
+
+        // A module imported within a function is bound in the scope of the function
+        variables.new_scope(true);
+        variables.import("local", inner);
+        assert_eq!(variables.get_str("local::name").unwrap(), types::Str::from("inner"));
+        variables.pop_scope();
+        assert!(variables.get_str("local::name").is_err());
+    }
+
+    #[test]
     fn minimal_directory_var_should_compact_path() {
-        let variables = Variables::default();
-        env::set_var("PWD", "/var/log/nix");
+        let mut variables = Variables::default();
+        variables.env_mut().set("PWD", "/var/log/nix");
         assert_eq!(
             types::Str::from("v/l/nix"),
             variables.get_str("MWD").expect("no value returned"),
@@ -346,10 +452,9 @@ pub(crate) mod tests {
     }
 
     #[test]
-    #[serial]
     fn minimal_directory_var_shouldnt_compact_path() {
-        let variables = Variables::default();
-        env::set_var("PWD", "/var/log");
+        let mut variables = Variables::default();
+        variables.env_mut().set("PWD", "/var/log");
         assert_eq!(
             types::Str::from("/var/log"),
             variables.get_str("MWD").expect("no value returned"),
@@ -1,6 +1,6 @@
 use self::binary::{builtins, InteractiveShell};
 use atty::Stream;
-use ion_shell::{BackgroundEvent, BuiltinMap, IonError, PipelineError, Shell, Value};
+use ion_shell::{BackgroundEvent, BuiltinMap, IonError, PipelineError, Shell, Trap, Value};
 use liner::KeyBindings;
 use nix::{
     sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal},
@@ -256,7 +256,7 @@ fn main() {
         shell.execute_command(command.as_bytes())
     } else if let Some(path) = script_path {
         match fs::File::open(&path) {
-            Ok(script) => shell.execute_command(std::io::BufReader::new(script)),
+            Ok(script) => shell.execute_script(&path, std::io::BufReader::new(script)),
             Err(cause) => {
                 println!("ion: could not execute '{}': {}", path, cause);
                 process::exit(1);
@@ -280,11 +280,14 @@ fn main() {
         shell.execute_command(BufReader::new(stdin()))
     }
     .and_then(|_| shell.wait_for_background().map_err(Into::into));
-    if let Err(IonError::PipelineExecutionError(PipelineError::Interrupted(_, signal))) = err {
+    shell.run_trap(Trap::Exit);
+    if let Some(IonError::PipelineExecutionError(PipelineError::Interrupted(_, signal))) =
+        err.as_ref().err().map(IonError::unlocated)
+    {
         // When the job was aborted because of an interrupt signal, abort with this same signal
         let action = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
-        let _ = unsafe { nix::sys::signal::sigaction(signal, &action) };
-        let _ = nix::sys::signal::raise(signal);
+        let _ = unsafe { nix::sys::signal::sigaction(*signal, &action) };
+        let _ = nix::sys::signal::raise(*signal);
     }
     if let Err(why) = err {
         eprintln!("ion: {}", why);